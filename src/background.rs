@@ -0,0 +1,317 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::vec3::Onb;
+use crate::{Color, CrateRng, Ray, Vec3};
+
+/// The light seen by rays that don't hit anything.
+#[derive(Clone, Debug)]
+pub enum Background {
+    /// Gradient from white at the horizon to light blue straight up.
+    Sky,
+    /// The same color in every direction. Use black for scenes only lit by emissive materials.
+    Solid(Color),
+    /// Light from an image wrapped around the whole scene.
+    Environment(Arc<EnvironmentMap>),
+}
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_dir = Vec3::normalized(ray.dir);
+                let t = 0.5 * (unit_dir.y + 1.);
+                (1. - t) * Color::new(1., 1., 1.) + t * Color::new(0.5, 0.7, 1.)
+            }
+            Background::Solid(color) => *color,
+            Background::Environment(map) => map.color(ray.dir),
+        }
+    }
+}
+
+/// An equirectangular image of the light coming from every direction, e.g. a panorama photo.
+/// The left and right edges of the image meet at `-X`, the middle column faces `+X`, and the top
+/// row is straight up.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    pub width: usize,
+    pub height: usize,
+    /// Linear RGB pixels in rows from top to bottom
+    pixels: Vec<Color>,
+}
+impl EnvironmentMap {
+    /// Load a Radiance HDR (`.hdr`) image.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("Couldn't read HDR file {:?}", path))?;
+        Self::parse_hdr(&bytes).with_context(|| format!("Invalid HDR file {:?}", path))
+    }
+
+    /// Parse the contents of a Radiance HDR file with RGBE pixels.
+    pub fn parse_hdr(bytes: &[u8]) -> Result<Self> {
+        let (width, height, body) = rgbe::parse_header(bytes)?;
+        let pixels = rgbe::read_pixels(body, width, height)?;
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// The light seen by a ray escaping in the direction `dir`. Bilinearly interpolates between
+    /// the four nearest pixels.
+    pub fn color(&self, dir: Vec3) -> Color {
+        let dir = Vec3::normalized(dir);
+        // Longitude increases when turning right so that the image isn't mirrored.
+        let u = (dir.z.atan2(dir.x) + PI) / (2. * PI);
+        let v = dir.y.clamp(-1., 1.).acos() / PI;
+        if u.is_nan() || v.is_nan() {
+            return Color::new(0., 0., 0.);
+        }
+
+        // Pixel centers are at half-integer coordinates
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        // Wrap around horizontally and clamp vertically
+        let column = |x: f64| (x as i64).rem_euclid(self.width as i64) as usize;
+        let row = |y: f64| (y.max(0.) as usize).min(self.height - 1);
+        let pixel = |x: f64, y: f64| self.pixels[row(y) * self.width + column(x)];
+
+        let top = pixel(x0, y0).lerp(pixel(x0 + 1., y0), tx);
+        let bottom = pixel(x0, y0 + 1.).lerp(pixel(x0 + 1., y0 + 1.), tx);
+        top.lerp(bottom, ty)
+    }
+}
+
+/// Reading the Radiance HDR format, where each pixel is stored as an 8-bit mantissa per channel
+/// sharing an 8-bit exponent.
+/// <http://paulbourke.net/dataformats/pic/>
+mod rgbe {
+    use super::*;
+
+    /// Parses the header and returns the width and height of the image along with the rest of
+    /// the file.
+    pub(super) fn parse_header(bytes: &[u8]) -> Result<(usize, usize, &[u8])> {
+        let mut lines = bytes.split(|&b| b == b'\n');
+        let mut read = 0;
+        let mut next_line = || {
+            let line = lines.next()?;
+            read += line.len() + 1;
+            Some(String::from_utf8_lossy(line).trim().to_string())
+        };
+
+        let magic = next_line().unwrap_or_default();
+        ensure!(
+            magic == "#?RADIANCE" || magic == "#?RGBE",
+            "Missing \"#?RADIANCE\" magic number"
+        );
+        // The header ends at a blank line
+        loop {
+            let line = next_line().ok_or_else(|| anyhow!("Missing end of header"))?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                ensure!(
+                    format == "32-bit_rle_rgbe",
+                    "Unsupported format \"{}\"",
+                    format
+                );
+            }
+        }
+
+        let resolution = next_line().ok_or_else(|| anyhow!("Missing resolution"))?;
+        let (width, height) = match resolution.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (width.parse()?, height.parse()?),
+            _ => bail!("Unsupported resolution \"{}\"", resolution),
+        };
+        ensure!(width > 0 && height > 0, "Image is empty");
+        Ok((width, height, bytes.get(read..).unwrap_or_default()))
+    }
+
+    /// Reads `height` scanlines, which are either flat or run-length encoded.
+    pub(super) fn read_pixels(mut body: &[u8], width: usize, height: usize) -> Result<Vec<Color>> {
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0; 4]; width];
+        for _ in 0..height {
+            body = match body {
+                // Run-length encoded scanlines start with 2, 2 and the width
+                [2, 2, hi, lo, rest @ ..] if (8..0x8000).contains(&width) => {
+                    let length = (*hi as usize) << 8 | *lo as usize;
+                    ensure!(length == width, "Scanline length doesn't match the width");
+                    read_rle_scanline(rest, &mut scanline)?
+                }
+                _ => {
+                    ensure!(body.len() >= 4 * width, "Unexpected end of file");
+                    for (pixel, bytes) in scanline.iter_mut().zip(body.chunks_exact(4)) {
+                        pixel.copy_from_slice(bytes);
+                    }
+                    &body[4 * width..]
+                }
+            };
+            pixels.extend(scanline.iter().map(|&rgbe| to_color(rgbe)));
+        }
+        Ok(pixels)
+    }
+
+    /// Reads each channel of the scanline in turn, and returns the rest of the file.
+    fn read_rle_scanline<'a>(mut body: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8]> {
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < scanline.len() {
+                let (&count, rest) = body
+                    .split_first()
+                    .ok_or_else(|| anyhow!("Unexpected end of file"))?;
+                // Counts above 128 are a run of a single value, otherwise they're literal values
+                let (run, count) = if count > 128 {
+                    (true, count as usize - 128)
+                } else {
+                    (false, count as usize)
+                };
+                ensure!(count > 0 && x + count <= scanline.len(), "Bad run length");
+                let values = if run { 1 } else { count };
+                ensure!(rest.len() >= values, "Unexpected end of file");
+                for i in 0..count {
+                    scanline[x + i][channel] = rest[if run { 0 } else { i }];
+                }
+                x += count;
+                body = &rest[values..];
+            }
+        }
+        Ok(body)
+    }
+
+    fn to_color([r, g, b, e]: [u8; 4]) -> Color {
+        if e == 0 {
+            return Color::new(0., 0., 0.);
+        }
+        // The mantissas are fractions of 256
+        let scale = 2f64.powi(e as i32 - 136);
+        Color::new(r as f64 * scale, g as f64 * scale, b as f64 * scale)
+    }
+}
+
+/// A light infinitely far away, like the sun. It lights the scene without any geometry, and rays
+/// that escape towards it see it on top of the `Background`.
+#[derive(Clone, Debug)]
+pub struct SunLight {
+    /// Unit vector pointing towards the sun
+    pub dir: Vec3,
+    /// How bright a white surface facing the sun is when lit only by the sun.
+    pub color: Color,
+    /// Cosine of the sun's angular radius
+    cos_max: f64,
+    /// Solid angle covered by the sun
+    solid_angle: f64,
+}
+impl SunLight {
+    /// A sun with no size could never be hit, so the angular radius is at least this many
+    /// degrees.
+    const MIN_ANGLE: f64 = 0.01;
+
+    /// Creates a sun towards `dir` with an angular radius of `angle` degrees. Larger suns cast
+    /// softer shadows.
+    pub fn new(dir: Vec3, color: Color, angle: f64) -> Self {
+        let angle = angle.clamp(Self::MIN_ANGLE, 90.).to_radians();
+        Self {
+            dir: Vec3::normalized(dir),
+            color,
+            cos_max: angle.cos(),
+            // Written with the half angle to avoid cancellation in `1 - cos` for small suns
+            solid_angle: 4. * PI * (angle / 2.).sin().powi(2),
+        }
+    }
+
+    fn contains(&self, dir: Vec3) -> bool {
+        Vec3::normalized(dir).dot(self.dir) >= self.cos_max
+    }
+
+    /// The light seen by a ray escaping in the direction `dir`.
+    pub fn emitted(&self, dir: Vec3) -> Color {
+        if self.contains(dir) {
+            // Scale so that the light falling on a surface facing the sun doesn't depend on
+            // the sun's size.
+            self.color * PI / self.solid_angle
+        } else {
+            Color::new(0., 0., 0.)
+        }
+    }
+
+    /// Probability density, with respect to solid angle, of `random_dir` sampling `dir`.
+    pub fn pdf_value(&self, dir: Vec3) -> f64 {
+        if self.contains(dir) {
+            1. / self.solid_angle
+        } else {
+            0.
+        }
+    }
+
+    /// Samples a direction towards the sun uniformly.
+    pub fn random_dir(&self, rng: &mut CrateRng) -> Vec3 {
+        Onb::from_w(self.dir).local(Vec3::rand_cone_direction(rng, self.cos_max))
+    }
+}
+
+#[cfg(test)]
+mod background_test {
+    use super::*;
+
+    /// A 2x1 image that's 0.5 red on the left and 4.0 blue on the right.
+    const FLAT: &[u8] =
+        b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n\x80\x00\x00\x80\x00\x00\x80\x83";
+
+    #[test]
+    fn parse_flat_hdr() {
+        let map = EnvironmentMap::parse_hdr(FLAT).unwrap();
+        assert_eq!((map.width, map.height), (2, 1));
+        let [left, right] = [map.pixels[0], map.pixels[1]];
+        assert_eq!((left.r, left.g, left.b), (0.5, 0., 0.));
+        assert_eq!((right.r, right.g, right.b), (0., 0., 4.));
+        assert!(EnvironmentMap::parse_hdr(b"#?RADIANCE\n\n-Y 1 +X 2\n").is_err());
+        assert!(EnvironmentMap::parse_hdr(b"P6\n").is_err());
+    }
+
+    #[test]
+    fn parse_rle_hdr() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        // Red alternates between 64 and 128, green is a run of zeros, and so is blue
+        bytes.extend_from_slice(&[8, 64, 128, 64, 128, 64, 128, 64, 128]);
+        bytes.extend_from_slice(&[128 + 8, 0]);
+        bytes.extend_from_slice(&[128 + 8, 0]);
+        // The exponent is a run of 129, i.e. a scale of 1/128
+        bytes.extend_from_slice(&[128 + 8, 129]);
+
+        let map = EnvironmentMap::parse_hdr(&bytes).unwrap();
+        let reds: Vec<_> = map.pixels.iter().map(|c| c.r).collect();
+        assert_eq!(reds, [0.5, 1., 0.5, 1., 0.5, 1., 0.5, 1.]);
+        assert!(map.pixels.iter().all(|c| c.g == 0. && c.b == 0.));
+
+        // A run past the end of the scanline
+        let len = bytes.len();
+        bytes[len - 2] = 128 + 9;
+        assert!(EnvironmentMap::parse_hdr(&bytes).is_err());
+    }
+
+    #[test]
+    fn environment_map_directions() {
+        let map = EnvironmentMap::parse_hdr(FLAT).unwrap();
+        // Looking towards -Z is a quarter of the way across the image, in the middle of the red
+        // pixel. +Z is in the middle of the blue pixel.
+        let forward = map.color(-Vec3::UNIT_Z);
+        assert_eq!((forward.r, forward.b), (0.5, 0.));
+        let back = map.color(Vec3::UNIT_Z);
+        assert_eq!((back.r, back.b), (0., 4.));
+        // Halfway between the pixel centers
+        let right = map.color(Vec3::UNIT_X);
+        assert_eq!((right.r, right.b), (0.25, 2.));
+        // The edges of the image meet at -X, so it's halfway between the pixels too
+        let left = map.color(-Vec3::UNIT_X);
+        assert_eq!((left.r, left.b), (0.25, 2.));
+    }
+}
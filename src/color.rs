@@ -1,18 +1,28 @@
+use std::f64::consts;
 use std::ops;
 
+use anyhow::{ensure, Context, Result};
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
 
-use crate::{CrateRng, Texture};
+use crate::{CrateRng, TexCoord, Texture};
 
 /// Each color value ranges from 0.0 to 1.0, where 1.0 is full brightness
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "[f64; 3]", into = "[f64; 3]")
+)]
 pub struct Color {
     pub r: f64,
     pub g: f64,
     pub b: f64,
 }
 impl Color {
+    /// Range of wavelengths (in nanometers) sampled for spectral effects.
+    pub const WAVELENGTHS: ops::Range<f64> = 400.0..700.0;
+
     pub fn new(r: f64, g: f64, b: f64) -> Self {
         Self { r, g, b }
     }
@@ -27,9 +37,170 @@ impl Color {
         let albedo = [distr.sample(rng), distr.sample(rng), distr.sample(rng)];
         albedo.into()
     }
+
+    /// Relative luminance using the Rec. 709 weights
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Linearly interpolates between `self` (at `t = 0`) and `other` (at `t = 1`).
+    pub fn lerp(self, other: Color, t: f64) -> Self {
+        (1. - t) * self + t * other
+    }
+
+    /// Approximate response to light of a single wavelength (in nanometers), going from blue at
+    /// 400nm through green at 550nm to red at 700nm. The response averages to white over
+    /// `Color::WAVELENGTHS`, so uniformly sampling a wavelength per path doesn't shift the
+    /// color balance of the image.
+    /// # Example
+    /// ```
+    /// # use raytracing::Color;
+    /// let c = Color::from_wavelength(400.);
+    /// assert_eq!((c.r, c.g, c.b), (0., 0., 4.));
+    /// ```
+    pub fn from_wavelength(wavelength: f64) -> Self {
+        let range = Self::WAVELENGTHS;
+        let t = ((wavelength - range.start) / (range.end - range.start)).clamp(0., 1.);
+        // The three lobes add up to one everywhere. The green lobe covers twice the range of the
+        // others, so each is scaled by the inverse of its average.
+        let (sin2, cos2) = (
+            (consts::PI * t).sin().powi(2),
+            (consts::PI * t).cos().powi(2),
+        );
+        let (r, b) = if t < 0.5 { (0., cos2) } else { (cos2, 0.) };
+        Self::new(4. * r, 2. * sin2, 4. * b)
+    }
+
+    /// Creates a color from hue (in degrees), saturation, and value.
+    /// The hue wraps around to `[0, 360)`, and saturation and value are clamped to `[0, 1]`.
+    /// # Example
+    /// ```
+    /// # use raytracing::Color;
+    /// let c = Color::from_hsv(120., 1., 0.5);
+    /// assert_eq!((c.r, c.g, c.b), (0., 0.5, 0.));
+    /// assert_eq!(c.to_hsv(), (120., 1., 0.5));
+    /// ```
+    pub fn from_hsv(hue: f64, saturation: f64, value: f64) -> Self {
+        let h = hue.rem_euclid(360.) / 60.;
+        let s = saturation.clamp(0., 1.);
+        let v = value.clamp(0., 1.);
+
+        let chroma = v * s;
+        let x = chroma * (1. - (h % 2. - 1.).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        let m = v - chroma;
+        Self::new(r + m, g + m, b + m)
+    }
+
+    /// Returns the hue (in degrees within `[0, 360)`), saturation, and value of the color.
+    pub fn to_hsv(self) -> (f64, f64, f64) {
+        let Self { r, g, b } = self;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let chroma = max - min;
+
+        let hue = if chroma == 0. {
+            0.
+        } else if max == r {
+            60. * ((g - b) / chroma).rem_euclid(6.)
+        } else if max == g {
+            60. * ((b - r) / chroma + 2.)
+        } else {
+            60. * ((r - g) / chroma + 4.)
+        };
+        let saturation = if max == 0. { 0. } else { chroma / max };
+        (hue, saturation, max)
+    }
+
+    /// Parses an sRGB color written as `#rgb` or `#rrggbb` (the `#` is optional) and converts it
+    /// to linear RGB.
+    /// # Example
+    /// ```
+    /// # use raytracing::Color;
+    /// let c = Color::from_hex("#ff8000").unwrap();
+    /// assert_eq!(c.r, 1.);
+    /// assert_eq!(c.to_hex(), "#ff8000");
+    /// assert_eq!(Color::from_hex("f80").unwrap().to_hex(), "#ff8800");
+    /// assert!(Color::from_hex("#ff80").is_err());
+    /// assert!(Color::from_hex("#+f+f+f").is_err());
+    /// ```
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        ensure!(
+            // Checked by hand since `from_str_radix` also accepts a leading `+`
+            digits.chars().all(|c| c.is_ascii_hexdigit())
+                && (digits.len() == 3 || digits.len() == 6),
+            "\"{}\" isn't a hex color. Expected #rgb or #rrggbb",
+            hex
+        );
+
+        let width = digits.len() / 3;
+        let mut channels = [0.; 3];
+        for (i, channel) in channels.iter_mut().enumerate() {
+            let digit = &digits[i * width..(i + 1) * width];
+            let value = u8::from_str_radix(digit, 16)
+                .with_context(|| format!("\"{}\" isn't a hex color", hex))?;
+            // Expand a single digit `f` to `ff`
+            let value = if width == 1 { value * 17 } else { value };
+            *channel = srgb_to_linear(value as f64 / 255.);
+        }
+        Ok(channels.into())
+    }
+
+    /// Converts the color to sRGB and writes it as `#rrggbb`.
+    pub fn to_hex(self) -> String {
+        let Self { r, g, b } = self.clamp01();
+        let byte = |c: f64| (linear_to_srgb(c) * 255.).round() as u8;
+        format!("#{:02x}{:02x}{:02x}", byte(r), byte(g), byte(b))
+    }
+
+    /// Clamps each channel to the range `[0, 1]`. NaN channels become `0`.
+    pub fn clamp01(self) -> Self {
+        let clamp = |c: f64| if c.is_nan() { 0. } else { c.clamp(0., 1.) };
+        Self::new(clamp(self.r), clamp(self.g), clamp(self.b))
+    }
+
+    /// Applies gamma correction with `gamma` and quantizes each channel to a byte. A `gamma` of
+    /// 2 takes the square root of each channel, and 1 leaves them linear.
+    /// # Example
+    /// ```
+    /// # use raytracing::Color;
+    /// let c = Color::new(1., 0.25, f64::NAN);
+    /// assert_eq!(c.to_rgb8(2.), [255, 127, 0]);
+    /// assert_eq!(c.to_rgb8(1.), [255, 63, 0]);
+    /// ```
+    pub fn to_rgb8(self, gamma: f64) -> [u8; 3] {
+        let Self { r, g, b } = self.clamp01();
+        let encode = |c: f64| (255.99 * c.powf(1. / gamma)) as u8;
+        [encode(r), encode(g), encode(b)]
+    }
+}
+/// Converts a single sRGB channel in `[0, 1]` to linear RGB.
+pub(crate) fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
 }
+
 impl Texture for Color {
-    fn value(&self, _u: f64, _v: f64, _point: crate::Vec3) -> Color {
+    fn value(&self, _coord: &TexCoord) -> Color {
         *self
     }
 }
@@ -39,6 +210,11 @@ impl From<[f64; 3]> for Color {
         Self::new(a[0], a[1], a[2])
     }
 }
+impl From<Color> for [f64; 3] {
+    fn from(c: Color) -> Self {
+        [c.r, c.g, c.b]
+    }
+}
 impl Default for Color {
     /// Returns white
     fn default() -> Self {
@@ -60,6 +236,20 @@ impl ops::AddAssign for Color {
         self.b += rhs.b;
     }
 }
+impl ops::Sub for Color {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+impl ops::SubAssign for Color {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.r -= rhs.r;
+        self.g -= rhs.g;
+        self.b -= rhs.b;
+    }
+}
 impl ops::Mul for Color {
     type Output = Self;
 
@@ -95,6 +285,27 @@ impl ops::MulAssign<f64> for Color {
         self.b *= rhs;
     }
 }
+impl ops::Div for Color {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        Self::new(self.r / rhs.r, self.g / rhs.g, self.b / rhs.b)
+    }
+}
+impl ops::DivAssign for Color {
+    fn div_assign(&mut self, rhs: Self) {
+        self.r /= rhs.r;
+        self.g /= rhs.g;
+        self.b /= rhs.b;
+    }
+}
+impl ops::Div<f64> for Color {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        Self::new(self.r / rhs, self.g / rhs, self.b / rhs)
+    }
+}
 impl ops::DivAssign<f64> for Color {
     fn div_assign(&mut self, rhs: f64) {
         self.r /= rhs;
@@ -1,7 +1,10 @@
 use std::num::{NonZeroU16, NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, ensure, Context, Result};
+use image::{GrayImage, Luma};
 use once_cell::sync::OnceCell;
 use rand::Rng;
 use structopt::StructOpt;
@@ -9,10 +12,18 @@ use strum::VariantNames;
 use strum_macros::Display as StrumDisplay;
 use strum_macros::{EnumString, EnumVariantNames};
 
-use crate::material::{Checkered, Dielectric, Lambertian, Metal};
-use crate::material::{NoiseAdapter, PerlinNoise, ValueNoise};
-use crate::shape::{MovingSphere, Sphere};
-use crate::{Axis, Camera, Color, CrateRng, HitList, Vec3};
+use crate::material::{Blend, BrickNormals, BumpMapped, Checkered, ColorRamp, Conductor};
+use crate::material::{DepthDebug, Dielectric, DiffuseLight, DispersiveDielectric, Filter};
+use crate::material::{Gradient, ImageTexture};
+use crate::material::{Lambertian, Masked, Metal, NoiseAdapter, NormalMapped, OrenNayar, Pbr};
+use crate::material::{PerlinNoise, PerlinNoise4, Phong, Plastic, SharedMaterial, SimplexNoise};
+use crate::material::{Subsurface, Transformed, Triplanar, TwoSided, UvCheckered, UvGradient};
+use crate::material::{ValueNoise, Velvet, Wood, Wrap};
+use crate::mesh::{Heightfield, TriangleMesh};
+use crate::shape::{Bumpy, Cuboid, MovingSphere, RotateY, Sphere, Translate};
+use crate::shape::{Ellipsoid, OverrideMaterial, Triangle, XYRect, XZRect, YZRect};
+use crate::{Aperture, Axis, Background, Camera, CameraBuilder, Color, CrateRng, HitList, AABB};
+use crate::{EnvironmentMap, Hittable, ResultExt, SunLight, Vec3};
 
 static CONFIG: OnceCell<Config> = OnceCell::new();
 
@@ -23,6 +34,7 @@ pub fn GLOBAL() -> &'static Config {
 }
 
 #[derive(Clone, Debug, StructOpt)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config {
     #[structopt(short, long, default_value = "1024", display_order = 0)]
     /// Width of render
@@ -59,6 +71,63 @@ pub struct Config {
     /// Use a specific seed for the rng.
     pub seed: Option<u64>,
 
+    #[structopt(short, long, parse(from_os_str))]
+    /// Save the render to this file instead of showing it in a window
+    pub output: Option<PathBuf>,
+
+    #[structopt(long, default_value = "2", parse(try_from_str = parse_gamma))]
+    /// Gamma used to encode the image. 2.2 suits most displays, and 1 keeps the output linear
+    pub gamma: f64,
+
+    #[structopt(long)]
+    /// Render an animation with this many frames. Frames are saved next to `output` with the
+    /// frame number appended, e.g. `out_0001.png`
+    pub frames: Option<NonZeroU32>,
+
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    /// Where the camera moves to by the last frame of an animation, e.g. `13,2,3`
+    pub end_origin: Option<Vec3>,
+
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    /// Where the camera looks at by the last frame of an animation
+    pub end_look_at: Option<Vec3>,
+
+    #[structopt(long, value_name = "far")]
+    /// Render the distance from the camera as grayscale instead, going from white at the camera
+    /// to black at this distance. Useful for debugging intersections
+    pub debug_depth: Option<f64>,
+
+    #[structopt(long, possible_values = Aov::VARIANTS)]
+    /// Render an arbitrary output variable instead of the shaded image. `depth` is the distance
+    /// to the first hit, going from white at the nearest hit to black at the farthest hit and
+    /// for misses. `normal` is the surface normal at the first hit mapped to RGB, and black for
+    /// misses. `variance` is the standard deviation of each pixel's samples, showing where the
+    /// render is still noisy. `stderror` is the standard error of each pixel's average, i.e. the
+    /// standard deviation over the square root of the number of samples, showing how far it is
+    /// from converging
+    pub aov: Option<Aov>,
+
+    #[structopt(short, long)]
+    /// Orbit the camera by dragging with the mouse and zoom with the scroll wheel. The image is
+    /// re-rendered a sample at a time while the camera stays still
+    pub interactive: bool,
+
+    #[structopt(short = "j", long, default_value = "0")]
+    /// Number of threads to render with. 0 uses all cores
+    pub threads: usize,
+
+    #[structopt(long, parse(try_from_str = parse_direction))]
+    /// Direction towards a sun lighting the scene, e.g. `1,1,0.5`. There's no sun if not given
+    pub sun_dir: Option<Vec3>,
+
+    #[structopt(long, default_value = "#ffffff", parse(try_from_str = Color::from_hex))]
+    /// Color of the sun, written as `#rrggbb`
+    pub sun_color: Color,
+
+    #[structopt(long, default_value = "0.5")]
+    /// Angular radius of the sun in degrees. Larger suns cast softer shadows
+    pub sun_angle: f64,
+
     #[structopt(
         default_value = "Random",
         // Using this instead of possible_values because possible_values doesn't wrap properly
@@ -66,6 +135,117 @@ pub struct Config {
     )]
     /// The scene to render
     pub scene: Scene,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Light the scene with an equirectangular Radiance HDR (.hdr) image instead of the scene's
+    /// background
+    pub background: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Grayscale image to build the Terrain scene from, where white is highest. A random
+    /// terrain is used if not given
+    pub heightmap: Option<PathBuf>,
+
+    #[structopt(long, default_value = "4")]
+    /// Height of the white parts of the heightmap
+    pub height_scale: f64,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Image of rock to texture the Terrain scene with, projected from along each axis and
+    /// tiled every 4 units. The terrain is grass and snow if not given
+    pub rock_texture: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Render the scene described by a RON or JSON file instead. Requires the `serde` feature
+    pub scene_file: Option<PathBuf>,
+}
+
+impl Config {
+    /// Create the camera, world, lights and background of the scene to render. The camera is
+    /// checked to be valid.
+    pub fn create_scene(&self, rng: &mut CrateRng) -> Result<SceneParts> {
+        let aspect_ratio = self.aspect_ratio();
+        let (camera, world, lights, background) = match &self.scene_file {
+            Some(path) => load_scene_file(path, aspect_ratio)?,
+            None => {
+                let camera = self.scene.camera_builder(aspect_ratio);
+                camera.verify().camera_context(&camera)?;
+                let (world, lights) = self.scene.world(&self.scene_options(), rng)?;
+                (camera, world, lights, self.scene.background())
+            }
+        };
+        let background = match &self.background {
+            Some(path) => Background::Environment(Arc::new(EnvironmentMap::load(path)?)),
+            None => background,
+        };
+
+        match self.debug_depth {
+            Some(far) => {
+                let depth = OverrideMaterial::new(world, DepthDebug::new(0., far));
+                let black = Background::Solid(Color::new(0., 0., 0.));
+                Ok((camera, depth.into_hitlist(), HitList::new(), black))
+            }
+            None => Ok((camera, world, lights, background)),
+        }
+    }
+
+    /// The sun lighting the scene, if any.
+    pub fn sun(&self) -> Option<SunLight> {
+        self.sun_dir
+            .map(|dir| SunLight::new(dir, self.sun_color, self.sun_angle))
+    }
+
+    /// The settings the built-in scenes are built from.
+    pub fn scene_options(&self) -> SceneOptions {
+        SceneOptions {
+            heightmap: self.heightmap.clone(),
+            height_scale: self.height_scale,
+            rock_texture: self.rock_texture.clone(),
+        }
+    }
+
+    /// The aspect ratio of the rendered image.
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width.get() as f64 / self.height.get() as f64
+    }
+}
+
+/// Settings that some of the built-in scenes are built from. Only the `Terrain` scene uses them
+/// so far.
+#[derive(Clone, Debug)]
+pub struct SceneOptions {
+    /// Grayscale image to build the terrain from, where white is highest. The terrain is random
+    /// if it's `None`.
+    pub heightmap: Option<PathBuf>,
+    /// Height of the white parts of the heightmap
+    pub height_scale: f64,
+    /// Image of rock to texture the terrain with. It's grass and snow if `None`.
+    pub rock_texture: Option<PathBuf>,
+}
+impl Default for SceneOptions {
+    fn default() -> Self {
+        Self {
+            heightmap: None,
+            height_scale: 4.,
+            rock_texture: None,
+        }
+    }
+}
+
+/// The camera, world, lights to sample and background of a scene.
+pub type SceneParts = (CameraBuilder, HitList, HitList, Background);
+
+#[cfg(feature = "serde")]
+fn load_scene_file(path: &Path, aspect_ratio: f64) -> Result<SceneParts> {
+    crate::scene_file::SceneDesc::load(path)?.create(aspect_ratio)
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_scene_file(path: &Path, _aspect_ratio: f64) -> Result<SceneParts> {
+    anyhow::bail!(
+        "Loading the scene file {:?} requires the `serde` feature",
+        path
+    )
 }
 
 fn parse_scene(s: &str) -> Result<Scene> {
@@ -78,11 +258,59 @@ fn parse_scene(s: &str) -> Result<Scene> {
     })
 }
 
+fn parse_vec3(s: &str) -> Result<Vec3> {
+    let fields = s
+        .split(',')
+        .map(|f| f.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!("\"{}\" isn't a vector: {}", s, err))?;
+    match fields[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(anyhow!(
+            "\"{}\" isn't a vector. Expected 3 fields, e.g. 1,2,3",
+            s
+        )),
+    }
+}
+
+fn parse_direction(s: &str) -> Result<Vec3> {
+    Vec3::checked_normalized(parse_vec3(s)?)
+        .map_err(|_| anyhow!("\"{}\" isn't a direction. It has a length of 0", s))
+}
+
+fn parse_gamma(s: &str) -> Result<f64> {
+    let gamma = s
+        .parse::<f64>()
+        .map_err(|err| anyhow!("\"{}\" isn't a gamma: {}", s, err))?;
+    ensure!(
+        gamma > 0. && gamma.is_finite(),
+        "Gamma must be greater than 0, but it's {}",
+        gamma
+    );
+    Ok(gamma)
+}
+
 fn invert_bool(i: u64) -> bool {
     i == 0
 }
 
+/// Arbitrary output variables, i.e. images of something other than the shaded scene.
+#[derive(Copy, Clone, Debug, StrumDisplay, EnumString, EnumVariantNames, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Aov {
+    /// Distance from the camera to the first hit
+    Depth,
+    /// Outward surface normal at the first hit
+    Normal,
+    /// Standard deviation of the samples of each pixel
+    Variance,
+    /// Standard error of the average of each pixel's samples
+    StdError,
+}
+
 #[derive(Copy, Clone, Debug, StrumDisplay, EnumString, EnumVariantNames, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Scene {
     Random,
     TwoSpheres,
@@ -98,55 +326,154 @@ pub enum Scene {
     PerlinNoisePink,
     PerlinTurbulence,
     PerlinMarbled,
+    BumpyMetal,
+    CornellBox,
+    FrostedGlass,
+    WornMetal,
+    RoughDiffuse,
+    LightIntensity,
+    PbrGrid,
+    Bokeh,
+    HarlequinGlass,
+    Prism,
+    Conductors,
+    NormalMap,
+    Bricks,
+    BumpyDiffuse,
+    Drapery,
+    Wax,
+    PoolBalls,
+    RustedMetal,
+    Earth,
+    ChromeSphere,
+    PlasticComparison,
+    UvDebug,
+    SimplexVsPerlin,
+    BoilingMarble,
+    WoodPlanks,
+    Lava,
+    Woodwork,
+    Terrain,
+    MossyRocks,
+    SkyPrism,
 }
 
 impl Scene {
-    pub fn create(self, rng: &mut CrateRng) -> (Camera, HitList) {
-        let camera = self.camera().expect("Invalid camera for Scene");
-        (camera, self.world(rng))
+    pub fn create(
+        self,
+        aspect_ratio: f64,
+        options: &SceneOptions,
+        rng: &mut CrateRng,
+    ) -> Result<(Camera, HitList, HitList)> {
+        let camera = self.camera(aspect_ratio)?;
+        let (world, lights) = self.world(options, rng)?;
+        Ok((camera, world, lights))
+    }
+
+    pub fn background(self) -> Background {
+        use Scene::*;
+        match self {
+            CornellBox | LightIntensity | Bokeh | Prism | Wax | Lava => {
+                Background::Solid(Color::new(0., 0., 0.))
+            }
+            _ => Background::Sky,
+        }
+    }
+
+    pub fn camera(self, aspect_ratio: f64) -> Result<Camera> {
+        self.camera_builder(aspect_ratio)
+            .build()
+            .map_err(|err| err.context(self))
     }
 
-    pub fn camera(self) -> Result<Camera> {
+    pub fn camera_builder(self, aspect_ratio: f64) -> CameraBuilder {
         use Scene::*;
-        let result = match self {
-            Random => Camera::builder()
+        let mut builder = Camera::builder();
+        match self {
+            Random => builder
                 .origin([13., 2., 3.])
                 .look_at([0., 0., 0.])
                 .vfov_degrees(20.)
                 .aperture(0.1)
                 .focus_dist(10.)
-                .shutter_time(0.0..1.0)
-                .build(),
-            TwoSpheres => Camera::builder()
+                .shutter_time(0.0..1.0),
+            TwoSpheres => builder
                 .origin([13., 2., 3.])
                 .look_at([0., 0., 0.])
                 .vfov_degrees(20.)
-                .focus_dist(10.)
-                .build(),
-            Balls => Camera::builder()
+                .focus_dist(10.),
+            Balls => builder
                 .origin([-2., 1.5, 1.])
                 .look_at([-0.2, 0., -1.2])
-                .vfov_degrees(40.)
-                .build(),
-            BirdsEyeView => Camera::builder()
+                .vfov_degrees(40.),
+            BirdsEyeView => builder
                 .origin([0., 20., 0.])
                 .look_at([0., 0., 0.])
-                .view_up_degrees(15., Axis::Y)
-                .build(),
-            _ => Camera::builder()
+                .view_up_degrees(15., Axis::Y),
+            PbrGrid => builder
+                .origin([0., 0., 12.])
+                .look_at([0., 0., 0.])
+                .vfov_degrees(30.),
+            Bokeh => builder
+                .origin([0., 1., 6.])
+                .look_at([0., 1., 0.])
+                .vfov_degrees(40.)
+                .aperture(0.6)
+                .aperture_shape(Aperture::Polygon { sides: 6 }),
+            NormalMap | Bricks | RustedMetal => builder
+                .origin([0., 2., 4.])
+                .look_at([0., 1., 0.])
+                .vfov_degrees(40.),
+            Conductors => builder
+                .origin([0., 2., 10.])
+                .look_at([0., 0.8, 0.])
+                .vfov_degrees(30.),
+            Prism => builder
+                .origin([0., 1.5, 8.])
+                .look_at([0., 1., 0.])
+                .vfov_degrees(30.),
+            SkyPrism => builder
+                .origin([0., 5., 4.6])
+                .look_at([0., 1.1, 0.])
+                .vfov_degrees(35.),
+            Terrain => builder
+                .origin([0., 9., 15.])
+                .look_at([0., 1., 0.])
+                .vfov_degrees(40.),
+            Woodwork => builder
+                .origin([-5., 3., 5.])
+                .look_at([0., 0.8, 0.])
+                .vfov_degrees(35.),
+            WoodPlanks => builder
+                .origin([-6., 3., 4.])
+                .look_at([0., 0.5, 0.])
+                .vfov_degrees(40.),
+            PoolBalls => builder
+                .origin([-9., 6., 6.])
+                .look_at([2., 0., 0.])
+                .vfov_degrees(35.),
+            CornellBox => builder
+                .origin([278., 278., -800.])
+                .look_at([278., 278., 0.])
+                .vfov_degrees(40.),
+            _ => builder
                 .origin([13., 2., 3.])
                 .look_at([0., 0., 0.])
-                .vfov_degrees(30.)
-                .build(),
+                .vfov_degrees(30.),
         };
 
-        result.map_err(|err| err.context(self))
+        builder.aspect_ratio(aspect_ratio);
+        builder
     }
 
-    pub fn world(self, rng: &mut CrateRng) -> HitList {
+    /// The world of the scene and its lights, which are sampled directly to reduce noise. The
+    /// lights are also part of the world. Fails if a file the scene is built from can't be
+    /// loaded.
+    pub fn world(self, options: &SceneOptions, rng: &mut CrateRng) -> Result<(HitList, HitList)> {
         use Scene::*;
+        let mut lights = HitList::new();
 
-        match self {
+        let world = match self {
             Random => {
                 let mut world = HitList::new();
                 let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
@@ -157,6 +484,8 @@ impl Scene {
                 ));
 
                 let mut list = HitList::new();
+                // All the glass spheres share one material
+                let glass: SharedMaterial = Arc::new(Dielectric::new(1.5));
                 for a in -11..11 {
                     for b in -11..11 {
                         let (x, z) = (0.9 * rng.gen::<f64>(), 0.9 * rng.gen::<f64>());
@@ -177,12 +506,12 @@ impl Scene {
                             list.push(Sphere::new(center, 0.2, Metal::new(albedo, fuzz)));
                         } else {
                             // glass
-                            list.push(Sphere::new(center, 0.2, Dielectric::new(1.5)));
+                            list.push(Sphere::new(center, 0.2, glass.clone()));
                         }
                     }
                 }
 
-                list.push(Sphere::from([0., 1., 0.], 1., Dielectric::new(1.5)));
+                list.push(Sphere::from([0., 1., 0.], 1., glass));
                 list.push(Sphere::from(
                     [-4., 1., 0.],
                     1.,
@@ -201,11 +530,9 @@ impl Scene {
             TwoSpheres => {
                 let mut world = HitList::new();
                 let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
-                world.push(Sphere::from(
-                    [0., -10., 0.],
-                    10.,
-                    Lambertian::new(checker.clone()),
-                ));
+                world.push(Sphere::from([0., -10., 0.], 10., Lambertian::new(checker)));
+                // Tile the top sphere in uv space so the pattern follows its surface.
+                let checker = UvCheckered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
                 world.push(Sphere::from([0., 10., 0.], 10., Lambertian::new(checker)));
 
                 world
@@ -217,7 +544,11 @@ impl Scene {
                     100.,
                     Lambertian::new(Color::new(0.8, 0.8, 0.)),
                 ));
-                world.push(Sphere::from([0., 0., -1.], 0.5, Dielectric::new(1.5)));
+                world.push(Sphere::from(
+                    [0., 0., -1.],
+                    0.5,
+                    Dielectric::absorbing(1.5, Color::new(1.2, 0.2, 1.)),
+                ));
                 world.push(Sphere::from(
                     [1.5, 0., -1.],
                     0.5,
@@ -226,7 +557,7 @@ impl Scene {
                 world.push(Sphere::from(
                     [-1.05, 0., -1.],
                     0.5,
-                    Lambertian::new(Color::new(0.1, 0.2, 0.5)),
+                    Phong::new(Color::new(0.1, 0.2, 0.5), Color::new(0.4, 0.4, 0.4), 200.),
                 ));
 
                 world.push(Sphere::from(
@@ -234,11 +565,10 @@ impl Scene {
                     0.5,
                     Metal::from([0.8, 0.6, 0.2], 0.),
                 ));
-                world.push(Sphere::from(
-                    [-1.05, 0., -2.5],
-                    0.5,
-                    Lambertian::new(Color::new(0.1, 0.2, 0.5)),
-                ));
+                // Fades from blue at the bottom to orange at the top
+                let mut fade = Gradient::new([0.1, 0.2, 0.5], [0.9, 0.4, 0.1], Axis::Y, -0.5..0.5);
+                fade.smooth = true;
+                world.push(Sphere::from([-1.05, 0., -2.5], 0.5, Lambertian::new(fade)));
 
                 world
             }
@@ -254,7 +584,7 @@ impl Scene {
             }
             ValueNoise1 => {
                 let mut world = HitList::new();
-                let noise = ValueNoise::new(GLOBAL().seed, 4.);
+                let noise = ValueNoise::from_rng(rng, 4.);
                 world.push(Sphere::from(
                     [0., -1000., 0.],
                     1000.,
@@ -275,7 +605,7 @@ impl Scene {
             }
             ValueNoise2 => {
                 let mut world = HitList::new();
-                let noise = ValueNoise::new(GLOBAL().seed, 4.).into_arc();
+                let noise = ValueNoise::from_rng(rng, 4.).into_arc();
                 world.push(Sphere::from(
                     [0., -1000., 0.],
                     1000.,
@@ -287,7 +617,7 @@ impl Scene {
             }
             ValueNoisePink => {
                 let mut world = HitList::new();
-                let noise = ValueNoise::new(GLOBAL().seed, 2.).fBm(2., 0.5, 5).into_arc();
+                let noise = ValueNoise::from_rng(rng, 2.).fBm(2., 0.5, 5).into_arc();
                 world.push(Sphere::from(
                     [0., -1000., 0.],
                     1000.,
@@ -299,7 +629,7 @@ impl Scene {
             }
             ValueTurbulence => {
                 let mut world = HitList::new();
-                let noise = ValueNoise::new(GLOBAL().seed, 5.)
+                let noise = ValueNoise::from_rng(rng, 5.)
                     .turbulence(1.8, 0.35, 5)
                     .into_arc();
                 world.push(Sphere::from(
@@ -313,7 +643,7 @@ impl Scene {
             }
             ValueMarbled => {
                 let mut world = HitList::new();
-                let noise = ValueNoise::new(GLOBAL().seed, 3.)
+                let noise = ValueNoise::from_rng(rng, 3.)
                     .fBm(2., 0.5, 2)
                     .marbled((2., 2.5), 0.5)
                     .into_arc();
@@ -328,7 +658,7 @@ impl Scene {
             }
             PerlinNoise1 => {
                 let mut world = HitList::new();
-                let noise = PerlinNoise::new(GLOBAL().seed, 4.);
+                let noise = PerlinNoise::from_rng(rng, 4.);
                 world.push(Sphere::from(
                     [0., -1000., 0.],
                     1000.,
@@ -349,19 +679,16 @@ impl Scene {
             }
             PerlinNoise2 => {
                 let mut world = HitList::new();
-                let noise = PerlinNoise::new(GLOBAL().seed, 4.).into_arc();
-                world.push(Sphere::from(
-                    [0., -1000., 0.],
-                    1000.,
-                    Lambertian::new(noise.clone()),
-                ));
-                world.push(Sphere::from([0., 2., 0.], 2., Lambertian::new(noise)));
+                let noise = PerlinNoise::from_rng(rng, 4.);
+                let material = Arc::new(Lambertian::new(noise));
+                world.push(Sphere::from([0., -1000., 0.], 1000., material.clone()));
+                world.push(Sphere::from([0., 2., 0.], 2., material));
 
                 world
             }
             PerlinNoisePink => {
                 let mut world = HitList::new();
-                let noise = PerlinNoise::new(GLOBAL().seed, 2.).fBm(2., 0.5, 5).into_arc();
+                let noise = PerlinNoise::from_rng(rng, 2.).fBm(2., 0.5, 5).into_arc();
                 world.push(Sphere::from(
                     [0., -1000., 0.],
                     1000.,
@@ -373,7 +700,7 @@ impl Scene {
             }
             PerlinTurbulence => {
                 let mut world = HitList::new();
-                let noise = PerlinNoise::new(GLOBAL().seed, 5.)
+                let noise = PerlinNoise::from_rng(rng, 5.)
                     .turbulence(2., 0.5, 7)
                     .into_arc();
                 world.push(Sphere::from(
@@ -387,7 +714,7 @@ impl Scene {
             }
             PerlinMarbled => {
                 let mut world = HitList::new();
-                let noise = PerlinNoise::new(GLOBAL().seed, 2.)
+                let noise = PerlinNoise::from_rng(rng, 2.)
                     .fBm(2., 0.5, 5)
                     .marbled((2., 0.), 2.0)
                     .into_arc();
@@ -400,7 +727,688 @@ impl Scene {
 
                 world
             }
-        }
+            BumpyMetal => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                let metal = || Metal::from([0.8, 0.6, 0.2], 0.);
+                world.push(Sphere::from([0., 1., -1.1], 1., metal()));
+                let noise = PerlinNoise::from_rng(rng, 8.);
+                world.push(Bumpy::new(
+                    Sphere::from([0., 1., 1.1], 1., metal()),
+                    noise,
+                    0.05,
+                ));
+
+                world
+            }
+            CornellBox => {
+                let mut world = HitList::new();
+                let red = Lambertian::new(Color::new(0.65, 0.05, 0.05));
+                let white = || Lambertian::new(Color::new(0.73, 0.73, 0.73));
+                let green = Lambertian::new(Color::new(0.12, 0.45, 0.15));
+                // The light's front face points up at the ceiling, so only its back face emits.
+                let light = TwoSided::new(
+                    Lambertian::new(Color::new(0., 0., 0.)),
+                    DiffuseLight::white(15.),
+                );
+
+                world.push(YZRect::new(0.0..555., 0.0..555., 555., green));
+                world.push(YZRect::new(0.0..555., 0.0..555., 0., red));
+                let light = Arc::new(XZRect::new(213.0..343., 227.0..332., 554., light));
+                world.push(Arc::clone(&light));
+                lights.push(light);
+                world.push(XZRect::new(0.0..555., 0.0..555., 0., white()));
+                world.push(XZRect::new(0.0..555., 0.0..555., 555., white()));
+                world.push(XYRect::new(0.0..555., 0.0..555., 555., white()));
+
+                let tall = Cuboid::from([0., 0., 0.], [165., 330., 165.], white());
+                let tall = Translate::new(RotateY::new(tall, 15.), Vec3::new(265., 0., 295.));
+                world.push(tall);
+                let short = Cuboid::from([0., 0., 0.], [165., 165., 165.], white());
+                let short = Translate::new(RotateY::new(short, -18.), Vec3::new(130., 0., 65.));
+                world.push(short);
+
+                world
+            }
+            FrostedGlass => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                world.push(Sphere::from([0., 1., -1.1], 1., Dielectric::new(1.5)));
+                world.push(Sphere::from(
+                    [0., 1., 1.1],
+                    1.,
+                    Dielectric::frosted(1.5, 0.3),
+                ));
+
+                world
+            }
+            WornMetal => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                // Rough at the bottom of the sphere and smooth at the top
+                let fuzz = Gradient::new([1., 1., 1.], [0., 0., 0.], Axis::Y, 0.0..2.);
+                let metal = Metal::fuzz_texture(Color::new(0.8, 0.8, 0.8), fuzz);
+                world.push(Sphere::from([0., 1., 0.], 1., metal));
+
+                world
+            }
+            RoughDiffuse => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // Clay-colored spheres that get rougher from left to right
+                let clay = Color::new(0.7, 0.4, 0.25);
+                let spheres = [(2.2, 0.), (0., 0.3), (-2.2, 1.)];
+                world.extend(spheres.iter().map(|&(z, sigma)| {
+                    let material = OrenNayar::new(clay, sigma);
+                    Sphere::from([0., 1., z], 1., material)
+                }));
+
+                world
+            }
+            LightIntensity => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // A dim and a bright light with a matte sphere between them
+                let dim = Arc::new(Sphere::from([0., 1., 2.2], 1., DiffuseLight::white(1.)));
+                let bright = Arc::new(Sphere::from([0., 1., -2.2], 1., DiffuseLight::white(10.)));
+                world.push(Arc::clone(&dim));
+                world.push(Arc::clone(&bright));
+                lights.push(dim);
+                lights.push(bright);
+                let white = Lambertian::new(Color::new(0.73, 0.73, 0.73));
+                world.push(Sphere::from([0., 0.5, 0.], 0.5, white));
+
+                // A light panel with a noise texture
+                let noise = PerlinNoise::from_rng(rng, 2.);
+                let panel = DiffuseLight::new(noise, 1.);
+                let panel = Arc::new(XYRect::new(-3.0..3., 0.0..3., -5., panel));
+                world.push(Arc::clone(&panel));
+                lights.push(panel);
+
+                world
+            }
+            Bokeh => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., 1., 0.],
+                    1.,
+                    Lambertian::new(Color::new(0.7, 0.3, 0.2)),
+                ));
+                world.push(XZRect::new(-3.0..3., -3.0..3., 5., DiffuseLight::white(2.)));
+
+                // Small lights far out of focus
+                world.extend((0..40).map(|_| {
+                    let center = [
+                        rng.gen_range(-25., 25.),
+                        rng.gen_range(-10., 15.),
+                        rng.gen_range(-60., -40.),
+                    ];
+                    let color = Color::from_hsv(rng.gen_range(0., 360.), 0.6, 1.);
+                    Sphere::from(center, 0.3, DiffuseLight::new(color, 4.))
+                }));
+
+                world
+            }
+            HarlequinGlass => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                // Glass whose transmitted light alternates between red and blue diamonds
+                let tint = UvCheckered::color(8., [0.9, 0.2, 0.2], [0.2, 0.3, 0.9]);
+                let glass = Dielectric::tinted(1.5, tint);
+                world.push(Sphere::from([0., 1., 0.], 1., glass));
+
+                world
+            }
+            NormalMap => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                // Facets that alternately tilt left and right, like a quilted ball
+                let albedo = UvCheckered::color(4., [0.8, 0.6, 0.3], [0.7, 0.2, 0.2]);
+                let normals = UvCheckered::color(24., [0.9, 0.5, 0.7], [0.1, 0.5, 0.7]);
+                let material = NormalMapped::new(Lambertian::new(albedo), normals);
+                world.push(Sphere::from([0., 1., 0.], 1., material));
+
+                world
+            }
+            BumpyDiffuse => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                // The same bumps on a diffuse and a metal sphere
+                let noise = PerlinNoise::from_rng(rng, 8.).into_arc();
+                let diffuse = Lambertian::new(Color::new(0.7, 0.4, 0.25));
+                let diffuse = BumpMapped::new(diffuse, noise.clone(), 0.05);
+                world.push(Sphere::from([0., 1., -1.1], 1., diffuse));
+                let metal = BumpMapped::new(Metal::from([0.8, 0.6, 0.2], 0.), noise, 0.05);
+                world.push(Sphere::from([0., 1., 1.1], 1., metal));
+
+                world
+            }
+            Drapery => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // Deep red velvet whose rim catches the light
+                let (red, pink) = (Color::new(0.4, 0.02, 0.05), Color::new(0.9, 0.6, 0.6));
+                let velvet = Velvet::new(red, pink, 1.);
+                world.push(Sphere::from([0., 1.5, 0.], 1.5, velvet));
+
+                world
+            }
+            Wax => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // A wax sphere lit from behind, with a thin wax disk next to it
+                let wax = |center: [f64; 3], radii: [f64; 3]| {
+                    let (center, radii) = (Vec3::from(center), Vec3::from(radii));
+                    let bounds = AABB::new(center - radii, center + radii);
+                    Subsurface::new(Color::new(0.9, 0.75, 0.5), 0.3, bounds)
+                };
+                world.push(Sphere::from([0., 1., 0.], 1., wax([0., 1., 0.], [1.; 3])));
+                let (center, radii) = ([0., 0.9, -2.3], [0.1, 0.8, 0.8]);
+                world.push(Ellipsoid::from(center, radii, wax(center, radii)));
+                let light = Arc::new(Sphere::from([-5., 1.5, -1.], 1., DiffuseLight::white(8.)));
+                world.push(Arc::clone(&light));
+                lights.push(light);
+
+                world
+            }
+            SimplexVsPerlin => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // Simplex noise on the left and Perlin noise on the right, at a low frequency
+                // where Perlin noise's grid shows
+                let simplex = SimplexNoise::from_rng(rng, 1.5);
+                world.push(Sphere::from([0., 2., 2.2], 2., Lambertian::new(simplex)));
+                let perlin = PerlinNoise::from_rng(rng, 1.5);
+                world.push(Sphere::from([0., 2., -2.2], 2., Lambertian::new(perlin)));
+
+                world
+            }
+            BoilingMarble => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // The veins churn over the course of an animation rendered with `--frames`
+                let noise = PerlinNoise4::from_rng(rng, 2.)
+                    .fBm(2., 0.5, 5)
+                    .marbled((2., 0.), 2.0);
+                world.push(Sphere::from([0., 2., 0.], 2., Lambertian::new(noise)));
+
+                world
+            }
+            WoodPlanks => {
+                let mut world = HitList::new();
+                // Planks running along x, each with its own grain. The noise is stretched along
+                // x so the grain follows the planks.
+                let light = Arc::new(Lambertian::new(Color::new(0.6, 0.38, 0.2)));
+                let dark = Arc::new(Lambertian::new(Color::new(0.35, 0.18, 0.08)));
+                world.extend((0..10).map(|i| {
+                    let grain = PerlinNoise::from_rng(rng, Vec3::new(0.3, 6., 6.)).fBm(2., 0.5, 4);
+                    let z = i as f64 - 5.;
+                    XZRect::new(
+                        -10.0..10.,
+                        z + 0.02..z + 0.98,
+                        0.,
+                        Masked::new(light.clone(), dark.clone(), grain),
+                    )
+                }));
+                // Shows through the gaps between the planks
+                world.push(XZRect::new(
+                    -10.0..10.,
+                    -5.0..5.,
+                    -0.05,
+                    Lambertian::new(Color::new(0.05, 0.03, 0.02)),
+                ));
+                world.push(Sphere::from(
+                    [0., 1., 0.],
+                    1.,
+                    Metal::from([0.8, 0.8, 0.8], 0.05),
+                ));
+
+                world
+            }
+            Lava => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // Cooled black crust between glowing cracks
+                let turbulence = PerlinNoise::from_rng(rng, 1.5).turbulence(2., 0.5, 6);
+                let stops = vec![
+                    (0.0, Color::new(1., 0.9, 0.3)),
+                    (0.06, Color::new(1., 0.35, 0.02)),
+                    (0.15, Color::new(0.02, 0.01, 0.01)),
+                    (1.0, Color::new(0., 0., 0.)),
+                ];
+                let lava = ColorRamp::new(turbulence, stops).expect("Lava stops are valid");
+                let lava = Arc::new(Sphere::from([0., 2., 0.], 2., DiffuseLight::new(lava, 4.)));
+                world.push(Arc::clone(&lava));
+                lights.push(lava);
+
+                world
+            }
+            Terrain => {
+                let mut world = HitList::new();
+                // Grass that turns to snow towards the peaks
+                let height = options.height_scale;
+                let mut snow = Gradient::new(
+                    [0.2, 0.35, 0.1],
+                    [0.9, 0.9, 0.9],
+                    Axis::Y,
+                    0.5 * height..0.8 * height,
+                );
+                snow.smooth = true;
+                let material: SharedMaterial = match &options.rock_texture {
+                    Some(path) => {
+                        let mut rock = ImageTexture::load(path)?;
+                        rock.filter = Filter::Bilinear;
+                        let mut rock = Transformed::new(Triplanar::new(rock, 4.));
+                        rock.scale = Vec3::from(0.25);
+                        Arc::new(Lambertian::new(rock))
+                    }
+                    None => Arc::new(Lambertian::new(snow)),
+                };
+
+                // 20 units across
+                let spacing = |(width, depth): (u32, u32)| {
+                    let cells = width.max(depth).max(2) - 1;
+                    let spacing = 20. / cells as f64;
+                    (spacing, spacing)
+                };
+                let image = match &options.heightmap {
+                    Some(path) => image::open(path)
+                        .with_context(|| format!("Couldn't load heightmap {:?}", path))?
+                        .to_luma8(),
+                    None => {
+                        let noise = PerlinNoise::from_rng(rng, 4.).fBm(2., 0.5, 6);
+                        GrayImage::from_fn(129, 129, |i, j| {
+                            let p = Vec3::new(i as f64, 0., j as f64) / 128.;
+                            Luma([(noise.eval(p) * 255.) as u8])
+                        })
+                    }
+                };
+                let spacing = spacing(image.dimensions());
+                world.push(Heightfield::new(&image, spacing, height, material, rng)?);
+
+                world
+            }
+            MossyRocks => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.3, 0.25, 0.2)),
+                ));
+
+                // Patches of moss growing over gray rock
+                let rock = Arc::new(PerlinNoise::from_rng(rng, 3.).turbulence(2., 0.5, 5));
+                let moss = Color::new(0.1, 0.25, 0.04);
+                let fbm = PerlinNoise::from_rng(rng, 1.5).fBm(2., 0.5, 4);
+                let stops = vec![(0.5, Color::new(0., 0., 0.)), (0.6, Color::new(1., 1., 1.))];
+                let patches = Arc::new(ColorRamp::new(fbm, stops).expect("Moss stops are valid"));
+                let rocks = [
+                    ([0., 0.5, 0.], 1.),
+                    ([-1.2, 0.2, 1.6], 0.6),
+                    ([1.5, 0.1, -1.2], 0.5),
+                ];
+                world.extend(rocks.iter().map(|&(center, radius)| {
+                    let mossy = Blend::new(rock.clone(), moss, patches.clone());
+                    Sphere::from(center, radius, Lambertian::new(mossy))
+                }));
+
+                world
+            }
+            SkyPrism => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.2, 0.2, 0.2)),
+                ));
+
+                // Triangular prism lying along x with its edge pointing down. Seen from above,
+                // it bends the view up to the horizon, which each channel sees at a different
+                // height, so the sky's edge splits into bands of color.
+                let prism = TriangleMesh {
+                    vertices: vec![
+                        Vec3::new(-2., 1.8, 0.7),
+                        Vec3::new(-2., 1.8, -0.7),
+                        Vec3::new(-2., 0.4, 0.),
+                        Vec3::new(2., 1.8, 0.7),
+                        Vec3::new(2., 1.8, -0.7),
+                        Vec3::new(2., 0.4, 0.),
+                    ],
+                    faces: vec![
+                        [0, 1, 2],
+                        [3, 5, 4],
+                        [0, 4, 1],
+                        [0, 3, 4],
+                        [0, 5, 3],
+                        [0, 2, 5],
+                        [1, 5, 2],
+                        [1, 4, 5],
+                    ],
+                    ..Default::default()
+                };
+                world.push(prism.triangles(DispersiveDielectric::new(1.5, 1.53, 1.56)));
+
+                world
+            }
+            Woodwork => {
+                let mut world = HitList::new();
+                let (light, dark) = (Color::new(0.65, 0.45, 0.25), Color::new(0.35, 0.2, 0.08));
+                // The floor's grain runs along x and the sphere's rings circle y
+                let floor = Wood::from_rng(rng, light, dark)
+                    .with_axis(Axis::X)
+                    .with_rings(3.)
+                    .with_distortion(0.3);
+                world.push(XZRect::new(
+                    -20.0..20.,
+                    -20.0..20.,
+                    0.,
+                    Lambertian::new(floor),
+                ));
+                let ball = Wood::from_rng(rng, light, dark).with_rings(6.);
+                world.push(Sphere::from([0., 1., 0.], 1., Lambertian::new(ball)));
+
+                world
+            }
+            UvDebug => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // One of each shape showing its texture coordinates, where red is `u` and green
+                // is `v`. `u` goes around spheres twice as far as `v` goes from pole to pole.
+                let uv = |freq_u| {
+                    let white = Color::new(0.9, 0.9, 0.9);
+                    Lambertian::new(UvCheckered::stretched(freq_u, 8., UvGradient {}, white))
+                };
+                world.push(Sphere::from([0., 1., 2.], 1., uv(16.)));
+                world.push(Ellipsoid::from([0., 1., 0.], [0.6, 1., 0.4], uv(16.)));
+                world.push(Triangle::from(
+                    [0., 0.2, -0.7],
+                    [0., 0.2, -2.1],
+                    [0., 1.8, -1.4],
+                    uv(8.),
+                ));
+                world.push(YZRect::new(0.2..1.8, -3.9..-2.4, 0., uv(8.)));
+                world.push(Translate::new(
+                    RotateY::new(Cuboid::from([-0.6; 3], [0.6; 3], uv(8.)), 30.),
+                    Vec3::new(0., 0.6, -5.),
+                ));
+
+                world
+            }
+            PlasticComparison => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                // The same blue as metal, plastic, and diffuse from left to right, and then a
+                // plastic with a denser coat
+                let blue = Color::new(0.1, 0.25, 0.7);
+                world.push(Sphere::from([0., 1., 3.3], 1., Metal::new(blue, 0.)));
+                world.push(Sphere::from([0., 1., 1.1], 1., Plastic::new(blue, 0.)));
+                world.push(Sphere::from([0., 1., -1.1], 1., Lambertian::new(blue)));
+                let dense = Plastic::with_ref_index(blue, 0., 2.);
+                world.push(Sphere::from([0., 1., -3.3], 1., dense));
+
+                world
+            }
+            ChromeSphere => {
+                let mut world = HitList::new();
+                // Best with `--background`, which the chrome sphere reflects and which lights
+                // the diffuse one
+                let chrome = Metal::from([0.95, 0.95, 0.95], 0.);
+                world.push(Sphere::from([0., 0., -1.1], 1., chrome));
+                let white = Lambertian::new(Color::new(0.8, 0.8, 0.8));
+                world.push(Sphere::from([0., 0., 1.1], 1., white));
+
+                world
+            }
+            Earth => {
+                let mut world = HitList::new();
+                // Equirectangular map of the earth, e.g. from NASA's Blue Marble. It isn't part
+                // of the repository, so it has to be downloaded there first.
+                let path = Path::new("images/earthmap.png");
+                let mut map = ImageTexture::load(path)
+                    .context("The Earth scene needs a map of the earth at images/earthmap.png")?;
+                // Smooths out the pixels up close, and the seam where the map wraps around
+                map.filter = Filter::Bilinear;
+                // Don't blend the poles together
+                map.wrap = (Wrap::Repeat, Wrap::Clamp);
+                world.push(Sphere::from([0., 0., 0.], 2., Lambertian::new(map)));
+
+                world
+            }
+            RustedMetal => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                // Patches of rust wherever the turbulence is strong
+                let noise = PerlinNoise::from_rng(rng, 1.5).turbulence(2., 0.5, 7);
+                let metal = Metal::from([0.7, 0.75, 0.8], 0.1);
+                let rust = Lambertian::new(Color::new(0.45, 0.15, 0.03));
+                let mut material = Masked::new(rust, metal, noise);
+                material.threshold = 0.25;
+                world.push(Sphere::from([0., 1., 0.], 1., material));
+
+                world
+            }
+            PoolBalls => {
+                let mut world = HitList::new();
+                let felt = Lambertian::new(Color::new(0.05, 0.3, 0.1));
+                world.push(Sphere::from([0., -1000., 0.], 1000., felt));
+
+                // Racked up in a triangle with the eight ball in the middle, and the cue ball in
+                // front of the rack
+                let colors = [
+                    [0.9, 0.7, 0.05],
+                    [0.05, 0.1, 0.6],
+                    [0.7, 0.05, 0.05],
+                    [0.3, 0.05, 0.4],
+                    [0.9, 0.3, 0.02],
+                    [0.02, 0.35, 0.1],
+                    [0.35, 0.05, 0.05],
+                    [0.01, 0.01, 0.01],
+                ];
+                let radius = 0.5;
+                let rack = (0..5).flat_map(|row| (0..=row).map(move |ball| (row, ball)));
+                world.extend(rack.enumerate().map(|(i, (row, ball))| {
+                    let x = 3f64.sqrt() * radius * row as f64;
+                    let z = radius * (2 * ball - row) as f64;
+                    // The eight ball is the fifth ball in the rack
+                    let color = match i {
+                        4 => colors[7],
+                        i if i < 4 => colors[i],
+                        i => colors[(i - 1) % 7],
+                    };
+                    let material = Plastic::new(Color::from(color), 0.);
+                    Sphere::from([x, radius, z], radius, material)
+                }));
+                let cue = Plastic::new(Color::new(0.9, 0.9, 0.85), 0.);
+                world.push(Sphere::from([-4., radius, 0.5], radius, cue));
+
+                world
+            }
+            Bricks => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                let brick = Lambertian::new(Color::new(0.6, 0.25, 0.15));
+                let material = NormalMapped::new(brick, BrickNormals::new(16., 16., 0.2));
+                world.push(Sphere::from([0., 1., 0.], 1., material));
+
+                world
+            }
+            Conductors => {
+                let mut world = HitList::new();
+                let checker = Checkered::color(10., [0.2, 0.3, 0.1], [0.9, 0.9, 0.9]);
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(checker),
+                ));
+
+                let metals: [fn() -> Conductor; 4] = [
+                    Conductor::gold,
+                    Conductor::silver,
+                    Conductor::copper,
+                    Conductor::aluminum,
+                ];
+                world.extend(metals.iter().enumerate().map(|(i, metal)| {
+                    let x = 2.2 * (i as f64 - 1.5);
+                    Sphere::from([x, 1., 0.], 1., metal())
+                }));
+
+                world
+            }
+            Prism => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1000., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // Triangular prism of strongly dispersive glass standing on its end
+                let prism = TriangleMesh {
+                    vertices: vec![
+                        Vec3::new(-1., 0., 0.6),
+                        Vec3::new(1., 0., 0.6),
+                        Vec3::new(0., 0., 0.),
+                        Vec3::new(-1., 2.5, 0.6),
+                        Vec3::new(1., 2.5, 0.6),
+                        Vec3::new(0., 2.5, 0.),
+                    ],
+                    faces: vec![
+                        [0, 2, 1],
+                        [3, 4, 5],
+                        [0, 1, 4],
+                        [0, 4, 3],
+                        [1, 2, 5],
+                        [1, 5, 4],
+                        [2, 0, 3],
+                        [2, 3, 5],
+                    ],
+                    ..Default::default()
+                };
+                world.push(prism.triangles(Dielectric::dispersive(1.5, 20.)));
+
+                // A thin strip of white light behind the prism, seen through it as a rainbow
+                let strip = DiffuseLight::white(4.);
+                world.push(XYRect::new(-1.85..-1.8, 0.0..4., -8., strip));
+                let backdrop = DiffuseLight::white(0.05);
+                world.push(XYRect::new(-20.0..20., 0.0..20., -9., backdrop));
+
+                world
+            }
+            PbrGrid => {
+                let mut world = HitList::new();
+                world.push(Sphere::from(
+                    [0., -1003., 0.],
+                    1000.,
+                    Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+                ));
+
+                // Metallic increases from left to right and roughness from bottom to top
+                let base = Color::new(0.9, 0.3, 0.2);
+                let grid = (0..5).flat_map(|i| (0..5).map(move |j| (i, j)));
+                world.extend(grid.map(|(i, j)| {
+                    let (metallic, roughness) = (i as f64 / 4., j as f64 / 4.);
+                    let center = [i as f64 - 2., j as f64 - 2., 0.];
+                    Sphere::from(center, 0.4, Pbr::new(base, metallic, roughness))
+                }));
+
+                world
+            }
+        };
+        Ok((world, lights))
     }
 }
 
@@ -423,4 +1431,74 @@ mod parse_test {
         "two_spheres".parse::<Scene>().unwrap_err();
         "Two_spheres".parse::<Scene>().unwrap_err();
     }
+
+    #[test]
+    fn aov() {
+        assert_eq!("depth".parse::<Aov>().unwrap(), Aov::Depth);
+        assert_eq!("normal".parse::<Aov>().unwrap(), Aov::Normal);
+        assert_eq!("variance".parse::<Aov>().unwrap(), Aov::Variance);
+        assert_eq!("stderror".parse::<Aov>().unwrap(), Aov::StdError);
+        assert_eq!(Aov::VARIANTS, ["depth", "normal", "variance", "stderror"]);
+    }
+
+    #[test]
+    fn gamma() {
+        assert_eq!(parse_gamma("2.2").unwrap(), 2.2);
+        parse_gamma("0").unwrap_err();
+        parse_gamma("-1").unwrap_err();
+        parse_gamma("inf").unwrap_err();
+        parse_gamma("a").unwrap_err();
+    }
+
+    #[test]
+    fn vec3() {
+        assert_eq!(parse_vec3("1,-2.5, 3").unwrap(), Vec3::new(1., -2.5, 3.));
+        parse_vec3("1,2").unwrap_err();
+        parse_vec3("1,2,3,4").unwrap_err();
+        parse_vec3("1,a,3").unwrap_err();
+    }
+
+    #[test]
+    fn seeded_scenes_are_reproducible() {
+        use crate::render::ray_color;
+        use crate::Ray;
+        use rand::SeedableRng;
+
+        // Looks at the noise textured ground
+        let ray = Ray::from([0., 1., 5.], [0.3, -1., -2.], 0.);
+        let render = |seed| {
+            let scene_rng = &mut CrateRng::seed_from_u64(seed);
+            let options = SceneOptions::default();
+            let (world, _) = Scene::PerlinTurbulence.world(&options, scene_rng).unwrap();
+            let background = Scene::PerlinTurbulence.background();
+            let (lights, mut rng) = (HitList::new(), CrateRng::seed_from_u64(0));
+            (0..50)
+                .map(|_| ray_color(&world, &lights, &background, None, &ray, 10, &mut rng).r)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(render(42), render(42));
+        assert_ne!(render(42), render(43));
+    }
+
+    #[test]
+    fn terrain_is_built_from_its_options() {
+        use rand::SeedableRng;
+
+        let rng = &mut CrateRng::seed_from_u64(0);
+        let (world, _) = Scene::Terrain.world(&SceneOptions::default(), rng).unwrap();
+        assert_eq!(world.len(), 1);
+
+        let options = SceneOptions {
+            heightmap: Some(PathBuf::from("no_such_heightmap.png")),
+            ..SceneOptions::default()
+        };
+        let err = format!("{:?}", Scene::Terrain.world(&options, rng).unwrap_err());
+        assert!(err.contains("no_such_heightmap.png"), "{}", err);
+    }
+
+    #[test]
+    fn direction() {
+        assert_eq!(parse_direction("0,0,-2").unwrap(), Vec3::new(0., 0., -1.));
+        parse_direction("0,0,0").unwrap_err();
+    }
 }
@@ -1,9 +1,11 @@
 use std::cmp::Ordering;
 use std::fmt::Debug;
+use std::iter::FromIterator;
 use std::mem;
 use std::ops::Range;
+use std::sync::Arc;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 
 use crate::shape::Dummy;
 use crate::{Axis, CrateRng, Material, Ray, Vec3};
@@ -59,16 +61,33 @@ impl<'a> Hit<'a> {
         }
         Self::new(point, normal, t, front_face, material, u, v)
     }
+
+    /// A copy of the hit with a different normal, e.g. one perturbed by a normal map.
+    pub fn with_normal(&self, normal: Vec3) -> Self {
+        Self { normal, ..*self }
+    }
 }
 
 pub trait Hittable: Sync + Debug {
     /// Returns the hit determined by a ray. If there is no hit or the hit's time isn't contained
     /// by `hit_time`, returns `None`.
-    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit>;
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>>;
     /// Returns the bounding box for the `Hittable`.  
     /// `shutter_time` affects the bounding_box of moving `Hittable`s (e.g. `MovingSphere`).
     fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB>;
 
+    /// Probability density, with respect to solid angle, of `random_dir` sampling `dir` from
+    /// `origin`. Is 0 for `Hittable`s that can't be sampled.
+    fn pdf_value(&self, _origin: Vec3, _dir: Vec3) -> f64 {
+        0.
+    }
+
+    /// Samples a direction from `origin` towards the `Hittable`, e.g. to send rays straight to a
+    /// light. `Hittable`s that can't be sampled return an arbitrary direction.
+    fn random_dir(&self, _origin: Vec3, _rng: &mut CrateRng) -> Vec3 {
+        Vec3::UNIT_X
+    }
+
     /// Create a HitList from this Hittable
     fn into_hitlist(self) -> HitList
     where
@@ -91,16 +110,49 @@ impl HitList {
         self.0.push(Box::new(val))
     }
 
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Box<dyn Hittable>> {
+        self.0.iter()
+    }
+
     /// Converts `self` into a BVH
     pub fn into_bvh(self, shutter_time: &Range<f64>, rng: &mut CrateRng) -> BVH {
         BVH::from_list(self, shutter_time, rng)
     }
 }
-impl Hittable for HitList {
-    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit> {
+impl FromIterator<Box<dyn Hittable>> for HitList {
+    fn from_iter<I: IntoIterator<Item = Box<dyn Hittable>>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+impl<H: Hittable + 'static> Extend<H> for HitList {
+    fn extend<I: IntoIterator<Item = H>>(&mut self, iter: I) {
+        for val in iter {
+            self.push(val);
+        }
+    }
+}
+impl<'a> IntoIterator for &'a HitList {
+    type Item = &'a Box<dyn Hittable>;
+    type IntoIter = std::slice::Iter<'a, Box<dyn Hittable>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+/// `Vec` and `HitList` delegate to this impl.
+impl Hittable for [Box<dyn Hittable>] {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
         let mut range = hit_time.clone();
         let mut closest = None;
-        for obj in &self.0 {
+        for obj in self.iter() {
             if let Some(hit) = obj.hit(ray, &range) {
                 range.end = hit.time;
                 closest = Some(hit);
@@ -109,13 +161,30 @@ impl Hittable for HitList {
         closest
     }
 
+    /// Each child is equally likely to be sampled.
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self.iter().map(|obj| obj.pdf_value(origin, dir)).sum();
+        sum / self.len() as f64
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        if self.is_empty() {
+            return Vec3::UNIT_X;
+        }
+        let i = rng.gen_range(0, self.len());
+        self[i].random_dir(origin, rng)
+    }
+
     fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
-        if self.0.is_empty() {
+        if self.is_empty() {
             return None;
         }
 
         let mut ret_bound: Option<AABB> = None;
-        for obj in &self.0 {
+        for obj in self.iter() {
             if let Some(bound_box) = obj.bounding_box(shutter_time) {
                 // Compute bounding box
                 if let Some(ret) = &mut ret_bound {
@@ -133,6 +202,58 @@ impl Hittable for HitList {
         ret_bound
     }
 }
+impl Hittable for Vec<Box<dyn Hittable>> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        self.as_slice().hit(ray, hit_time)
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.as_slice().pdf_value(origin, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.as_slice().random_dir(origin, rng)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        self.as_slice().bounding_box(shutter_time)
+    }
+}
+impl Hittable for HitList {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        self.0.as_slice().hit(ray, hit_time)
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.0.as_slice().pdf_value(origin, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.0.as_slice().random_dir(origin, rng)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        self.0.as_slice().bounding_box(shutter_time)
+    }
+}
+/// Lets one shape be in several lists, e.g. in both the world and its lights.
+impl<T: Hittable + Send + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        <T as Hittable>::hit(self, ray, hit_time)
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        <T as Hittable>::pdf_value(self, origin, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        <T as Hittable>::random_dir(self, origin, rng)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        <T as Hittable>::bounding_box(self, shutter_time)
+    }
+}
 
 /// Axis-Aligned Bounding Box
 #[derive(Clone, Debug)]
@@ -146,24 +267,27 @@ impl AABB {
     }
 
     pub fn surrounding(&self, other: &AABB) -> Self {
-        let small = Vec3::new(
-            self.min.x.min(other.min.x),
-            self.min.y.min(other.min.y),
-            self.min.z.min(other.min.z),
-        );
-        let big = Vec3::new(
-            self.max.x.max(other.max.x),
-            self.max.y.max(other.max.y),
-            self.max.z.max(other.max.z),
-        );
+        AABB::new(self.min.min(other.min), self.max.max(other.max))
+    }
 
-        AABB::new(small, big)
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let size = self.max - self.min;
+        2. * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// The axis along which the box is longest. Ties go to the earlier axis.
+    pub fn longest_axis(&self) -> Axis {
+        (self.max - self.min).max_component_axis().1
     }
 
     pub fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> bool {
         let mut range = hit_time.clone();
 
-        let mut hit = |axis| {
+        let hit = |axis| {
             let inv_dir = 1.0 / ray.dir[axis];
             let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_dir;
             let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_dir;
@@ -176,11 +300,7 @@ impl AABB {
             range.end > range.start
         };
 
-        if !hit(Axis::X) || !hit(Axis::Y) || !hit(Axis::Z) {
-            return false;
-        }
-
-        true
+        Axis::all().all(hit)
     }
 
     fn compare_axis(&self, other: &AABB, axis: Axis) -> Ordering {
@@ -219,49 +339,91 @@ impl BVH {
         Self::new(bound_box, left, right)
     }
 
-    /// Construct the BVH
+    /// Construct the BVH. Large lists are split up on multiple threads, and the result only
+    /// depends on `rng`, not on how many threads there are.
     pub fn from_list(hitlist: HitList, shutter_time: &Range<f64>, rng: &mut CrateRng) -> Self {
-        Self::inner_list(hitlist.0, shutter_time, rng)
+        let err_msg = "No bounding box in BVH construction!";
+        assert!(
+            !hitlist.is_empty(),
+            "Can't construct a BVH from an empty list"
+        );
+
+        // Plan the tree using only the bounding boxes, since `Hittable`s can't be sent between
+        // threads. Then move the `Hittable`s into it.
+        let bounds: Vec<AABB> = hitlist
+            .iter()
+            .map(|hittable| hittable.bounding_box(shutter_time).expect(err_msg))
+            .collect();
+        let plan = Plan::new((0..bounds.len()).collect(), &bounds, rng.gen());
+        let mut hittables: Vec<_> = hitlist.0.into_iter().map(Some).collect();
+        Self::from_plan(plan, &mut hittables, shutter_time)
     }
 
-    // Recursively create the tree
-    fn inner_list(
-        mut hitlist: Vec<Box<dyn Hittable>>,
+    fn from_plan(
+        plan: Plan,
+        hittables: &mut [Option<Box<dyn Hittable>>],
         shutter_time: &Range<f64>,
-        rng: &mut CrateRng,
     ) -> Self {
-        let err_msg = "No bounding box in BVH construction!";
-
-        // Only 1 available hittable for BVH node. Make the other one a dummy hittable.
-        if hitlist.len() == 1 {
-            // Make the left node the Dummy so less work is done in BVH::hit()
-            let left = Box::new(Dummy {});
-            let right = hitlist.pop().unwrap();
-            let bound_box = right.bounding_box(shutter_time).expect(err_msg);
-            return Self::new(bound_box, left, right);
+        let mut take = |i: usize| hittables[i].take().unwrap();
+        match plan {
+            Plan::One(i) => {
+                // Make the left node the Dummy so less work is done in BVH::hit()
+                let right = take(i);
+                let bound_box = right.bounding_box(shutter_time).unwrap();
+                Self::new(bound_box, Box::new(Dummy {}), right)
+            }
+            Plan::Two(left, right) => Self::from(take(left), take(right), shutter_time),
+            Plan::Split(left, right) => {
+                let left = Self::from_plan(*left, hittables, shutter_time);
+                let right = Self::from_plan(*right, hittables, shutter_time);
+                Self::from(Box::new(left), Box::new(right), shutter_time)
+            }
         }
+    }
+}
 
-        let (left, right);
-        if hitlist.len() == 2 {
-            left = hitlist.pop().unwrap();
-            right = hitlist.pop().unwrap();
-        } else {
-            let axis = rng.gen();
-            hitlist.sort_unstable_by(|a, b| {
-                let a = a.bounding_box(shutter_time).expect(err_msg);
-                let b = b.bounding_box(shutter_time).expect(err_msg);
-                a.compare_axis(&b, axis)
-            });
-            let second_half = hitlist.split_off(hitlist.len() / 2);
-            left = Box::new(Self::inner_list(hitlist, shutter_time, rng));
-            right = Box::new(Self::inner_list(second_half, shutter_time, rng));
+/// The shape of a BVH, with the leaves being indices into the list it's built from.
+enum Plan {
+    One(usize),
+    Two(usize, usize),
+    Split(Box<Plan>, Box<Plan>),
+}
+impl Plan {
+    /// Lists at least this long build their two halves in parallel.
+    const PARALLEL_LEN: usize = 4096;
+
+    // Recursively split the list in half along a random axis
+    fn new(mut indices: Vec<usize>, bounds: &[AABB], seed: u64) -> Self {
+        match indices[..] {
+            [i] => return Plan::One(i),
+            // The last one goes on the left
+            [right, left] => return Plan::Two(left, right),
+            _ => {}
         }
 
-        Self::from(left, right, shutter_time)
+        // Each half gets its own seed so that it can be built on any thread.
+        let mut rng = CrateRng::seed_from_u64(seed);
+        let axis = rng.gen();
+        let (left_seed, right_seed) = (rng.gen(), rng.gen());
+        indices.sort_unstable_by(|&a, &b| bounds[a].compare_axis(&bounds[b], axis));
+        let second_half = indices.split_off(indices.len() / 2);
+
+        let (left, right) = if indices.len() + second_half.len() >= Self::PARALLEL_LEN {
+            rayon::join(
+                || Self::new(indices, bounds, left_seed),
+                || Self::new(second_half, bounds, right_seed),
+            )
+        } else {
+            (
+                Self::new(indices, bounds, left_seed),
+                Self::new(second_half, bounds, right_seed),
+            )
+        };
+        Plan::Split(Box::new(left), Box::new(right))
     }
 }
 impl Hittable for BVH {
-    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
         if !self.bound_box.hit(ray, hit_time) {
             return None;
         }
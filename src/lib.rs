@@ -1,20 +1,42 @@
+pub mod background;
 pub mod color;
 pub mod config;
 pub mod hit;
 pub mod material;
+pub mod mesh;
+pub mod render;
+#[cfg(feature = "serde")]
+pub mod scene_file;
 pub mod screen;
 pub mod shape;
 pub mod vec3;
 
+pub use background::{Background, EnvironmentMap, SunLight};
 pub use color::Color;
 pub use config::Config;
 pub use hit::{Hit, HitList, Hittable, AABB, BVH};
-pub use material::{Material, Scatter, Texture};
-pub use screen::{Camera, CameraBuilder, Screen};
+pub use material::{Material, Scatter, TexCoord, Texture};
+pub use screen::{Aperture, Camera, CameraBuilder, Screen};
 pub use vec3::{Axis, Vec3};
 
 pub type CrateRng = rand::rngs::SmallRng;
 
+/// Returns a seed for the rng of the pixel at `(x, y)`. Every pixel gets an independent seed,
+/// so the result doesn't depend on the image's width or how the image is split up for
+/// rendering.
+pub fn pixel_seed(x: usize, y: usize, seed: u64) -> u64 {
+    splitmix64(splitmix64(splitmix64(seed) ^ x as u64) ^ y as u64)
+}
+
+/// The SplitMix64 mixing function.
+/// <https://prng.di.unimi.it/splitmix64.c>
+fn splitmix64(z: u64) -> u64 {
+    let z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 use anyhow::{Context, Result};
 
 #[derive(Clone)]
@@ -22,16 +44,39 @@ pub struct Ray {
     pub origin: Vec3,
     pub dir: Vec3,
     pub time: f64,
+    /// Wavelength (in nanometers) of the light carried by the ray, or `None` for white light.
+    /// Dispersive materials pick one for the rest of the path. Other materials ignore it.
+    pub wavelength: Option<f64>,
+    /// Whether the ray comes straight from the camera. Rays scattered by materials aren't.
+    pub primary: bool,
+    /// How far the ray's origin is from the camera along the path, so textures can tell how
+    /// far away they're seen from.
+    pub distance: f64,
 }
 impl Ray {
     pub fn new(origin: Vec3, dir: Vec3, time: f64) -> Self {
-        Self { origin, dir, time }
+        Self {
+            origin,
+            dir,
+            time,
+            wavelength: None,
+            primary: false,
+            distance: 0.,
+        }
     }
 
     pub fn from(origin: [f64; 3], dir: [f64; 3], time: f64) -> Self {
         Self::new(origin.into(), dir.into(), time)
     }
 
+    /// Ray carrying light of a single wavelength (in nanometers).
+    pub fn spectral(origin: Vec3, dir: Vec3, time: f64, wavelength: f64) -> Self {
+        Self {
+            wavelength: Some(wavelength),
+            ..Self::new(origin, dir, time)
+        }
+    }
+
     pub fn at(&self, t: f64) -> Vec3 {
         self.origin + t * self.dir
     }
@@ -1,16 +1,20 @@
 use std::f64;
 use std::io::{self, Write};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
 use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
 
-use raytracing::config;
-use raytracing::{Color, CrateRng, HitList, Hittable, Ray, Screen, Vec3};
+use raytracing::config::Aov;
+use raytracing::render::{first_hit_distance, first_hit_normal, ray_color, SampleStats};
+use raytracing::{config, pixel_seed, Background, Camera, Color, CrateRng};
+use raytracing::{HitList, Ray, ResultExt, Screen, SunLight, Vec3};
 
 fn main() {
     #[allow(non_snake_case)]
@@ -23,117 +27,485 @@ fn main() {
 
     let width = CFG.width.get();
     let height = CFG.height.get();
-    let (camera, world) = CFG.scene.create(&mut rng);
+    let (camera, world, lights, background) = CFG.create_scene(&mut rng).unwrap_or_else(|err| {
+        eprintln!("Error: {:?}", err);
+        process::exit(1);
+    });
+    let tracer = Tracer {
+        world,
+        lights,
+        background,
+        sun: CFG.sun(),
+    };
+
+    // Stop rendering early on Ctrl-C and keep what's done. A second Ctrl-C exits immediately.
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let handler_cancelled = cancelled.clone();
+    ctrlc::set_handler(move || {
+        if handler_cancelled.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    })
+    .expect("Couldn't set the Ctrl-C handler");
+
+    // A thread count of 0 lets rayon use all cores.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(CFG.threads)
+        .build()
+        .expect("Couldn't build the render thread pool");
+
+    if let Some(frames) = CFG.frames {
+        let frames = frames.get();
+        let output = CFG
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("out.png"));
+        for frame in 1..=frames {
+            // How far along the animation the frame is
+            let t = if frames == 1 {
+                0.
+            } else {
+                (frame - 1) as f64 / (frames - 1) as f64
+            };
+            let camera = camera
+                .build_between(CFG.end_origin, CFG.end_look_at, t)
+                .camera_context(&camera)
+                .unwrap_or_else(|err| {
+                    eprintln!("Error: {:?}", err);
+                    process::exit(1);
+                });
 
+            eprintln!("Frame {}/{}", frame, frames);
+            let mut screen = Screen::new(width, height);
+            let seed = rng.gen();
+            pool.install(|| render(&mut screen, &camera, &tracer, seed, &cancelled));
+            save(&screen, &frame_path(&output, frame));
+            if cancelled.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+        return;
+    }
+
+    let mut builder = camera;
+    let mut camera = builder.build().expect("Camera should already be verified");
     let mut screen = Screen::new(width, height);
-    let rows_done = Arc::new(AtomicUsize::new(0));
+    let seed = rng.gen();
+    pool.install(|| render(&mut screen, &camera, &tracer, seed, &cancelled));
+
+    if let Some(path) = &CFG.output {
+        save(&screen, path);
+        return;
+    }
+
+    // Display the screen
+    let options = WindowOptions {
+        resize: true,
+        ..WindowOptions::default()
+    };
+    let mut window = Window::new("Raytracing", width, height, options).unwrap();
+    window.limit_update_rate(Some(CFG.delay));
+    let mut buffer = screen.encode(CFG.gamma);
+    let mut orbit = builder.orbit_params().filter(|_| CFG.interactive);
+    // Number of single sample passes blended into the screen since the camera last moved. An
+    // AOV is done in one pass.
+    let total_passes = match CFG.aov {
+        Some(_) => 1,
+        None => u32::from(CFG.samples.get()),
+    };
+    let mut passes = total_passes;
+    // Where the mouse was while dragging
+    let mut dragging = None;
+    // The window's new size and when it was last changed
+    let mut resized: Option<((usize, usize), Instant)> = None;
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        // Press S to save a screenshot
+        if window.is_key_pressed(Key::S, KeyRepeat::No) {
+            let path = screenshot_path();
+            match screen.save(&path, CFG.gamma) {
+                Ok(()) => eprintln!("Saved screenshot to {:?}", path),
+                Err(err) => eprintln!("Error: {:?}", err),
+            }
+        }
+
+        let mut moved = false;
+        if let Some(orbit) = &mut orbit {
+            if orbit_controls(&window, orbit, &mut dragging) {
+                let (target, radius, azimuth, elevation) = *orbit;
+                builder.orbit(target, radius, azimuth, elevation);
+                moved = true;
+            }
+        }
+
+        // Wait for the window to stop changing size before re-rendering at the new size.
+        let size = window.get_size();
+        if size == (screen.width, screen.height) || size.0 == 0 || size.1 == 0 {
+            resized = None;
+        } else if resized.map(|(pending, _)| pending) != Some(size) {
+            resized = Some((size, Instant::now()));
+        }
+        if let Some(((width, height), time)) = resized {
+            if time.elapsed() >= RESIZE_DELAY {
+                // The buffer has to match the new screen even if the camera can't be rebuilt
+                screen = Screen::new(width, height);
+                buffer = screen.encode(CFG.gamma);
+                passes = 0;
+                builder.aspect_from(width, height);
+                resized = None;
+                moved = true;
+            }
+        }
+
+        if moved {
+            match builder.build() {
+                Ok(moved) => {
+                    camera = moved;
+                    passes = 0;
+                }
+                Err(err) => eprintln!("Error: {:?}", err),
+            }
+        }
+        // Refine the image while the camera stays still
+        if passes < total_passes {
+            let seed = seed.wrapping_add(passes.into());
+            pool.install(|| match CFG.aov {
+                Some(aov) => render_aov(&mut screen, &camera, &tracer, seed, aov),
+                None => render_pass(&mut screen, &camera, &tracer, seed, passes),
+            });
+            passes += 1;
+            buffer = screen.encode(CFG.gamma);
+        }
+
+        window
+            .update_with_buffer(&buffer, screen.width, screen.height)
+            .unwrap();
+    }
+}
+
+/// How long the window has to keep the same size before the render is redone at that size.
+const RESIZE_DELAY: Duration = Duration::from_millis(300);
+/// Degrees the camera orbits per pixel the mouse is dragged.
+const DRAG_DEGREES: f64 = 0.3;
+/// Factor the camera's distance is multiplied by per step of the scroll wheel towards the scene.
+const ZOOM: f64 = 0.9;
+/// Closest the camera can be zoomed towards what it orbits, since it can't be built at it.
+const MIN_RADIUS: f64 = 0.01;
+
+/// Orbits the camera when the mouse is dragged with the left button and zooms when scrolling.
+/// `orbit` holds the arguments to `CameraBuilder::orbit`. Returns whether the camera moved.
+fn orbit_controls(
+    window: &Window,
+    (_, radius, azimuth, elevation): &mut (Vec3, f64, f64, f64),
+    dragging: &mut Option<(f32, f32)>,
+) -> bool {
+    let mut moved = false;
+    let mouse = window.get_mouse_pos(MouseMode::Discard);
+    *dragging = match (mouse, window.get_mouse_down(MouseButton::Left)) {
+        (Some((x, y)), true) => {
+            if let Some((last_x, last_y)) = *dragging {
+                let (dx, dy) = (f64::from(x - last_x), f64::from(y - last_y));
+                *azimuth += DRAG_DEGREES * dx;
+                *elevation = (*elevation + DRAG_DEGREES * dy).clamp(-89., 89.);
+                moved |= dx != 0. || dy != 0.;
+            }
+            Some((x, y))
+        }
+        _ => None,
+    };
+    if let Some((_, scroll)) = window.get_scroll_wheel() {
+        *radius = (*radius * ZOOM.powf(f64::from(scroll))).max(MIN_RADIUS);
+        moved |= scroll != 0.;
+    }
+    moved
+}
 
-    let thread_progress = rows_done.clone();
+/// The parts of the scene that rays are traced through.
+struct Tracer {
+    world: HitList,
+    lights: HitList,
+    background: Background,
+    sun: Option<SunLight>,
+}
+impl Tracer {
+    /// Averages `samples` rays through the pixel at `(x, y)` on a screen of `width` by `height`.
+    /// Also returns the statistics of the samples.
+    fn sample_pixel(
+        &self,
+        camera: &Camera,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        samples: u16,
+        rng: &mut CrateRng,
+    ) -> (Color, SampleStats) {
+        #[allow(non_snake_case)]
+        let CFG: &'static _ = config::GLOBAL();
+        let mut stats = SampleStats::new();
+        for _ in 0..samples {
+            let (rand_i, rand_j): (f64, f64) = if !CFG.antialias {
+                (0., 0.)
+            } else {
+                (rng.gen(), rng.gen())
+            };
+            let i = (x as f64 + rand_i) / (width as f64 - 1.);
+            let j = 1. - (y as f64 + rand_j) / (height as f64 - 1.);
+
+            let ray = camera.get_ray(i, j, rng);
+            let sample = ray_color(
+                &self.world,
+                &self.lights,
+                &self.background,
+                self.sun.as_ref(),
+                &ray,
+                CFG.max_depth.get(),
+                rng,
+            );
+            stats.push(sample);
+        }
+        (stats.mean(), stats)
+    }
+}
+
+/// Traces a ray through the center of each pixel and applies `first_hit` to it, so the edges of
+/// objects stay sharp.
+fn trace_centers<T, F>(screen: &Screen, camera: &Camera, seed: u64, first_hit: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&Ray) -> T + Sync,
+{
+    let (width, height) = (screen.width, screen.height);
+    (0..width * height)
+        .into_par_iter()
+        .map(|pixel| {
+            let (x, y) = (pixel % width, pixel / width);
+            let mut rng = CrateRng::seed_from_u64(pixel_seed(x, y, seed));
+            let i = (x as f64 + 0.5) / (width as f64 - 1.);
+            let j = 1. - (y as f64 + 0.5) / (height as f64 - 1.);
+            first_hit(&camera.get_ray(i, j, &mut rng))
+        })
+        .collect()
+}
+
+/// Renders an arbitrary output variable instead of the shaded image.
+///
+/// `Aov::Depth` renders the distance from the camera to the first hit through each pixel as
+/// grayscale. The distances are normalized so the nearest hit is white, and the farthest hit and
+/// misses are black.
+///
+/// `Aov::Normal` maps each component of the outward surface normal at the first hit from `-1..1`
+/// to `0..1`. Misses are black.
+///
+/// `Aov::Variance` takes the full number of samples through each pixel and renders the standard
+/// deviation of each channel, so noisy pixels are bright and converged ones are black.
+/// `Aov::StdError` renders the standard error of each channel's average instead, which also
+/// shrinks as more samples are taken.
+fn render_aov(screen: &mut Screen, camera: &Camera, tracer: &Tracer, seed: u64, aov: Aov) {
+    let width = screen.width;
+    let colors: Vec<Color> = match aov {
+        Aov::Depth => {
+            let depths = trace_centers(screen, camera, seed, |ray| {
+                first_hit_distance(&tracer.world, ray)
+            });
+            let finite = depths.iter().copied().filter(|depth| depth.is_finite());
+            let near = finite.clone().fold(f64::INFINITY, f64::min);
+            let far = finite.fold(f64::NEG_INFINITY, f64::max);
+            let gray = |depth: f64| {
+                if depth.is_finite() && far > near {
+                    1. - (depth - near) / (far - near)
+                } else if depth.is_finite() {
+                    1.
+                } else {
+                    0.
+                }
+            };
+            depths
+                .into_iter()
+                .map(|depth| {
+                    let gray = gray(depth);
+                    Color::new(gray, gray, gray)
+                })
+                .collect()
+        }
+        Aov::Normal => trace_centers(screen, camera, seed, |ray| {
+            first_hit_normal(&tracer.world, ray).map_or(Color::new(0., 0., 0.), |normal| {
+                let Vec3 { x, y, z } = 0.5 * (normal + Vec3::new(1., 1., 1.));
+                Color::new(x, y, z)
+            })
+        }),
+        Aov::Variance | Aov::StdError => {
+            #[allow(non_snake_case)]
+            let CFG: &'static _ = config::GLOBAL();
+            let size = (width, screen.height);
+            (0..width * screen.height)
+                .into_par_iter()
+                .map(|pixel| {
+                    let (x, y) = (pixel % width, pixel / width);
+                    let mut rng = CrateRng::seed_from_u64(pixel_seed(x, y, seed));
+                    let samples = CFG.samples.get();
+                    let (_, stats) = tracer.sample_pixel(camera, (x, y), size, samples, &mut rng);
+                    if aov == Aov::Variance {
+                        stats.std_dev()
+                    } else {
+                        stats.std_error()
+                    }
+                })
+                .collect()
+        }
+    };
+
+    for (row, colors) in screen.rows_mut().zip(colors.chunks_exact(width)) {
+        row.copy_from_slice(colors);
+    }
+}
+
+/// Blends a single sample per pixel into the screen, which already holds the average of
+/// `pass` samples. Used to quickly render previews that get refined over time.
+fn render_pass(screen: &mut Screen, camera: &Camera, tracer: &Tracer, seed: u64, pass: u32) {
+    let size = (screen.width, screen.height);
+    screen.par_tiles_mut().for_each(|tile| {
+        for (x, y, pix) in tile.into_pixels() {
+            let mut rng = CrateRng::seed_from_u64(pixel_seed(x, y, seed));
+            let (sample, _) = tracer.sample_pixel(camera, (x, y), size, 1, &mut rng);
+            *pix = pix.lerp(sample, 1. / (pass + 1) as f64);
+        }
+    });
+}
+
+/// Render the world onto the screen, stopping early if `cancelled` gets set.
+fn render(
+    screen: &mut Screen,
+    camera: &Camera,
+    tracer: &Tracer,
+    seed: u64,
+    cancelled: &Arc<AtomicBool>,
+) {
+    #[allow(non_snake_case)]
+    let CFG: &'static _ = config::GLOBAL();
+    if let Some(aov) = CFG.aov {
+        render_aov(screen, camera, tracer, seed, aov);
+        return;
+    }
+    let (width, height) = (screen.width, screen.height);
+    let pixels = width * height;
+    let pixels_done = Arc::new(AtomicUsize::new(0));
+
+    let thread_progress = pixels_done.clone();
+    let thread_cancelled = cancelled.clone();
     // Spawn a new thread for monitoring progress.
     let progress = thread::spawn(move || {
+        // Weight of the newest sample in the moving average of the completion rate.
+        const SMOOTHING: f64 = 0.05;
         let mut time = Instant::now();
+        let mut last_done = 0;
+        // Pixels completed per second
+        let mut rate: Option<f64> = None;
         loop {
             let delta = time.elapsed();
             if delta < CFG.delay {
                 thread::sleep(CFG.delay - delta);
-                time = Instant::now();
             }
+            let elapsed = time.elapsed().as_secs_f64();
+            time = Instant::now();
+
+            let done = thread_progress.load(Ordering::SeqCst);
+            let current = (done - last_done) as f64 / elapsed;
+            last_done = done;
+            let rate = *rate.insert(rate.map_or(current, |rate| {
+                SMOOTHING * current + (1. - SMOOTHING) * rate
+            }));
+            let eta = if rate > 0. {
+                format_duration((pixels - done) as f64 / rate)
+            } else {
+                String::from("?")
+            };
 
-            let rows = thread_progress.load(Ordering::SeqCst);
             // Clear the line before printing.
             // http://ascii-table.com/ansi-escape-sequences.php
             print!(
-                "\x1B[K\rRows remaining: {}/{} ({:.2}%)",
-                height - rows,
-                height,
-                (height - rows) as f64 / height as f64 * 100.,
+                "\x1B[K\rPixels remaining: {}/{} ({:.2}%), {:.0} pixels/s, ETA {}",
+                pixels - done,
+                pixels,
+                (pixels - done) as f64 / pixels as f64 * 100.,
+                rate,
+                eta,
             );
             io::stdout().flush().unwrap();
 
             // Exit when threads are done.
-            if rows == height {
+            if done == pixels || thread_cancelled.load(Ordering::SeqCst) {
                 break;
             }
         }
     });
 
-    let seed: u64 = rng.gen();
     // Time the render
     let time = Instant::now();
-    // Parallelize over each row
+    // Parallelize over tiles of the screen
     screen
-        .par_rows_mut()
-        .enumerate()
-        .for_each_with(rows_done, |counter, (y, row)| {
-            // Complete each row and then increment the counter.
-
-            // Initialize rng based off of row number
-            let seed = seed.wrapping_add(1).wrapping_mul(y as u64);
-            let mut rng = CrateRng::seed_from_u64(seed);
-            for (x, pix) in row.iter_mut().enumerate() {
-                let mut avg = Color::new(0., 0., 0.);
-                for _ in 0..CFG.samples.get() {
-                    let (rand_i, rand_j): (f64, f64) = if !CFG.antialias {
-                        (0., 0.)
-                    } else {
-                        (rng.gen(), rng.gen())
-                    };
-                    let i = (x as f64 + rand_i) / (width as f64 - 1.);
-                    let j = 1. - (y as f64 + rand_j) / (height as f64 - 1.);
-
-                    let ray = camera.get_ray(i, j, &mut rng);
-                    let sample = ray_color(&world, &ray, &mut rng);
-                    avg += sample;
+        .par_tiles_mut()
+        .for_each_with(pixels_done, |counter, tile| {
+            // Complete each tile and then increment the counter.
+            let tile_pixels = tile.width * tile.height;
+
+            for (x, y, pix) in tile.into_pixels() {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
                 }
-                avg /= CFG.samples.get() as f64;
-                *pix = avg;
+                // Initialize rng based off of the pixel's position
+                let mut rng = CrateRng::seed_from_u64(pixel_seed(x, y, seed));
+                let samples = CFG.samples.get();
+                *pix = tracer
+                    .sample_pixel(camera, (x, y), (width, height), samples, &mut rng)
+                    .0;
             }
-            counter.fetch_add(1, Ordering::SeqCst);
+            counter.fetch_add(tile_pixels, Ordering::SeqCst);
         });
     let time = time.elapsed();
     progress.join().unwrap();
     eprintln!("\nRending time elapsed: {:.2} seconds", time.as_secs_f64());
-
-    // Display the screen
-    let mut window = Window::new("Raytracing", width, height, WindowOptions::default()).unwrap();
-    window.limit_update_rate(Some(CFG.delay));
-    let buffer = screen.encode();
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        window
-            .update_with_buffer(&buffer, screen.width, screen.height)
-            .unwrap();
+    if cancelled.load(Ordering::SeqCst) {
+        eprintln!("Render cancelled. Unfinished pixels are black.");
     }
 }
 
-/// Iterative version of the diffuse ray calculation.
-/// Used because the recursive method blew the stack every time.
-fn ray_color(world: &HitList, ray: &Ray, rng: &mut CrateRng) -> Color {
-    let mut color = Color::default();
-    let mut ray = ray.clone();
-    let mut bounces = config::GLOBAL().max_depth.get();
-
-    // NOTE: Tweak the beginning of the range to deal with shadow acne.
-    while let Some(hit) = world.hit(&ray, &(0.001..f64::INFINITY)) {
-        if let Some(scatter) = hit.material.scatter(&ray, &hit, rng) {
-            color *= scatter.albedo;
-            ray = scatter.ray;
-        } else {
-            // Ray got absorbed so no light is reflected.
-            color *= 0.;
-            break;
-        }
+/// Save the screen to `path`, exiting on failure.
+fn save(screen: &Screen, path: &Path) {
+    #[allow(non_snake_case)]
+    let CFG: &'static _ = config::GLOBAL();
+    screen.save(path, CFG.gamma).unwrap_or_else(|err| {
+        eprintln!("Error: {:?}", err);
+        process::exit(1);
+    });
+    eprintln!("Saved render to {:?}", path);
+}
 
-        bounces -= 1;
-        if bounces == 0 {
-            color *= 0.;
-            break;
-        }
-    }
+/// A file name in the current directory with the time in milliseconds since the Unix epoch, e.g.
+/// `screenshot_1602720000123.png`, so screenshots taken in quick succession don't overwrite each
+/// other.
+fn screenshot_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    PathBuf::from(format!("screenshot_{}.png", millis))
+}
 
-    // Calculate color of the sky
-    let unit_dir = Vec3::normalized(ray.dir);
-    let t = 0.5 * (unit_dir.y + 1.);
-    let sky = (1. - t) * Color::new(1., 1., 1.) + t * Color::new(0.5, 0.7, 1.);
+/// Appends the frame number to the file name of `output`, e.g. `out.png` becomes
+/// `out_0001.png`.
+fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output.extension().unwrap_or_else(|| "png".as_ref());
+    let name = format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy());
+    output.with_file_name(name)
+}
 
-    sky * color
+/// Formats a number of seconds like `1h 02m 03s`.
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, secs)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
 }
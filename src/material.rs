@@ -1,25 +1,185 @@
+use std::collections::HashMap;
+use std::f64::consts;
 use std::fmt::Debug;
+use std::ops::Range;
+use std::path::Path;
 use std::sync::Arc;
 
+use anyhow::{ensure, Context, Result};
+use image::RgbImage;
 use rand::distributions::{Distribution, Uniform};
 use rand::{Rng, SeedableRng};
 
-use crate::{Color, CrateRng, F64Ext, Hit, Ray, Vec3};
+use crate::color::srgb_to_linear;
+use crate::vec3::Onb;
+use crate::{Axis, Color, CrateRng, F64Ext, Hit, Ray, Vec3, AABB};
 
 /// A scattered ray and its color information
 pub struct Scatter {
     pub albedo: Color,
     pub ray: Ray,
+    /// Probability density of sampling `ray`. `None` for materials that don't sample from a
+    /// distribution, e.g. specular reflection.
+    pub pdf: Option<f64>,
 }
 impl Scatter {
     pub fn new(albedo: Color, ray: Ray) -> Self {
-        Self { albedo, ray }
+        Self {
+            albedo,
+            ray,
+            pdf: None,
+        }
+    }
+
+    pub fn with_pdf(mut self, pdf: f64) -> Self {
+        self.pdf = Some(pdf);
+        self
     }
 }
 
 pub trait Material: Sync + Debug {
     /// A material will either absorb a ray (`None`) or scatter it.
     fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter>;
+
+    /// The light given off by the material at `hit`. Most materials don't emit any light.
+    fn emitted(&self, _ray: &Ray, _hit: &Hit) -> Color {
+        Color::new(0., 0., 0.)
+    }
+
+    /// The probability density of `scatter` producing the ray `scattered`. Only meaningful for
+    /// materials whose `Scatter` has a `pdf`.
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &Hit, _scattered: &Ray) -> f64 {
+        0.
+    }
+
+    /// The light reflected towards the incoming ray from light arriving along `dir`, i.e. the
+    /// BRDF times the cosine of the angle between `dir` and the normal. Light sampling swaps
+    /// the direction of a `Scatter` that has a `pdf` for one towards a light, and weights it
+    /// by this instead of the albedo, which was only meant for the material's own direction.
+    /// So materials whose `Scatter` has a `pdf` must implement it, such that the albedo of
+    /// scattering along `dir` averages to `eval(dir) / scattering_pdf(dir)`.
+    fn eval(&self, _ray: &Ray, _hit: &Hit, _dir: Vec3) -> Color {
+        Color::new(0., 0., 0.)
+    }
+}
+impl<T: Material + Send + ?Sized> Material for Arc<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        // Use fully qualified syntax to prevent recursion
+        <T as Material>::scatter(self, ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        <T as Material>::emitted(self, ray, hit)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        <T as Material>::scattering_pdf(self, ray, hit, scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        <T as Material>::eval(self, ray, hit, dir)
+    }
+}
+impl<T: Material + ?Sized> Material for Box<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        // Use fully qualified syntax to prevent recursion
+        <T as Material>::scatter(self, ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        <T as Material>::emitted(self, ray, hit)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        <T as Material>::scattering_pdf(self, ray, hit, scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        <T as Material>::eval(self, ray, hit, dir)
+    }
+}
+
+/// A material that can be shared by many shapes, possibly of different material types.
+pub type SharedMaterial = Arc<dyn Material + Send>;
+
+/// Materials looked up by name, so that one definition can be shared by many shapes. Lookups
+/// hash the name, so do them while building a scene rather than per ray.
+#[derive(Debug, Default)]
+pub struct MaterialRegistry {
+    materials: HashMap<String, SharedMaterial>,
+}
+impl MaterialRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with some common materials: `"glass"`, `"mirror"`, `"gold"`, `"light"`, and
+    /// `"white_diffuse"`, `"red_diffuse"` and `"green_diffuse"` with the Cornell box's colors.
+    pub fn with_defaults() -> Self {
+        let defaults: [(&str, SharedMaterial); 7] = [
+            ("glass", Arc::new(Dielectric::new(1.5))),
+            (
+                "mirror",
+                Arc::new(Metal::new(Color::new(0.95, 0.95, 0.95), 0.)),
+            ),
+            ("gold", Arc::new(Conductor::gold())),
+            ("light", Arc::new(DiffuseLight::white(4.))),
+            (
+                "white_diffuse",
+                Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))),
+            ),
+            (
+                "red_diffuse",
+                Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))),
+            ),
+            (
+                "green_diffuse",
+                Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))),
+            ),
+        ];
+        let mut registry = Self::new();
+        for (name, material) in defaults.iter() {
+            registry
+                .register(name, material.clone())
+                .expect("Default material names are unique");
+        }
+        registry
+    }
+
+    /// Adds a material under `name`. Fails if a material is already registered with that name.
+    pub fn register(&mut self, name: &str, material: SharedMaterial) -> Result<()> {
+        ensure!(
+            !self.materials.contains_key(name),
+            "A material named \"{}\" is already registered",
+            name
+        );
+        self.materials.insert(name.to_owned(), material);
+        Ok(())
+    }
+
+    /// The material registered under `name`, if any.
+    pub fn get(&self, name: &str) -> Option<SharedMaterial> {
+        self.materials.get(name).cloned()
+    }
+}
+
+/// Samples a direction around `hit.normal` for diffuse reflection. Returns the direction and
+/// its probability density.
+fn cosine_scatter(hit: &Hit, rng: &mut CrateRng) -> (Vec3, f64) {
+    let dir = Onb::from_w(hit.normal).local(Vec3::rand_cosine_direction(rng));
+    // Guard against rounding producing a degenerate direction.
+    if dir.near_zero() {
+        (hit.normal, consts::FRAC_1_PI)
+    } else {
+        (dir, cosine_pdf(hit, dir))
+    }
+}
+
+/// Probability density of `cosine_scatter` returning `dir`.
+fn cosine_pdf(hit: &Hit, dir: Vec3) -> f64 {
+    let cos = hit.normal.dot(Vec3::normalized(dir));
+    cos.max(0.) * consts::FRAC_1_PI
 }
 
 #[derive(Debug)]
@@ -32,515 +192,3841 @@ impl<T> Lambertian<T> {
         Self { albedo }
     }
 }
+impl<T: Texture> Lambertian<T> {
+    fn albedo(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.albedo.value(&TexCoord::from_hit(ray, hit))
+    }
+}
 impl<T: Texture> Material for Lambertian<T> {
     fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
-        let scatter_dir = hit.normal + Vec3::rand_unit_sphere(rng);
+        let (scatter_dir, pdf) = cosine_scatter(hit, rng);
         let scattered = Ray::new(hit.point, scatter_dir, ray.time);
-        let albedo = self.albedo.value(hit.u, hit.v, hit.point);
-        Some(Scatter::new(albedo, scattered))
+        Some(Scatter::new(self.albedo(ray, hit), scattered).with_pdf(pdf))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        cosine_pdf(hit, scattered.dir)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.albedo(ray, hit) * cosine_pdf(hit, dir)
     }
 }
 
 #[derive(Debug)]
-pub struct Metal {
-    pub albedo: Color,
-    /// The fuzziness of the Metal. Is between `0.0` and `1.0`
-    pub fuzz: f64,
+/// Rough diffuse reflection using the Oren–Nayar approximation. Unlike `Lambertian`, rough
+/// surfaces (e.g. clay or the moon) look flatter since they reflect more light back towards
+/// the viewer.
+pub struct OrenNayar<T> {
+    pub albedo: T,
+    /// Standard deviation of the microfacet angles in radians. `0` is the same as `Lambertian`.
+    pub sigma: f64,
 }
-impl Metal {
-    pub fn new(albedo: Color, fuzz: f64) -> Self {
-        let fuzz = fuzz.min(1.);
-        Self { albedo, fuzz }
+impl<T> OrenNayar<T> {
+    pub fn new(albedo: T, sigma: f64) -> Self {
+        Self { albedo, sigma }
     }
 
-    pub fn from(a: [f64; 3], fuzz: f64) -> Self {
-        Self::new(a.into(), fuzz)
+    /// How much brighter or darker than `Lambertian` the surface is for light arriving along
+    /// `dir` and leaving back along `ray`.
+    fn weight(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> f64 {
+        let sigma2 = self.sigma.powi(2);
+        let a = 1. - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        if b == 0. {
+            return a;
+        }
+
+        let normal = hit.normal;
+        let incoming = -Vec3::normalized(ray.dir);
+        let outgoing = Vec3::normalized(dir);
+        let cos_i = incoming.dot(normal).clamp(-1., 1.);
+        let cos_o = outgoing.dot(normal).clamp(-1., 1.);
+        let (theta_i, theta_o) = (cos_i.acos(), cos_o.acos());
+        let alpha = theta_i.max(theta_o);
+        let beta = theta_i.min(theta_o);
+
+        // Cosine of the difference in azimuth, found by projecting onto the tangent plane.
+        let proj_i = incoming - cos_i * normal;
+        let proj_o = outgoing - cos_o * normal;
+        let norms = proj_i.norm() * proj_o.norm();
+        let cos_phi = if norms > 0. {
+            (proj_i.dot(proj_o) / norms).max(0.)
+        } else {
+            0.
+        };
+
+        a + b * cos_phi * alpha.sin() * beta.tan()
     }
 }
-impl Material for Metal {
+impl<T: Texture> Material for OrenNayar<T> {
     fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
-        let fuzz = self.fuzz * Vec3::rand_unit_sphere(rng);
-        let reflected = ray.dir.reflect(hit.normal) + fuzz;
-        let mut scattered = Ray::new(hit.point, reflected, ray.time);
+        // Sample the same way as Lambertian and weight the albedo instead.
+        let (scatter_dir, pdf) = cosine_scatter(hit, rng);
+        let scattered = Ray::new(hit.point, scatter_dir, ray.time);
+        let albedo = self.albedo.value(&TexCoord::from_hit(ray, hit));
+        let weight = self.weight(ray, hit, scatter_dir);
+        Some(Scatter::new(albedo * weight, scattered).with_pdf(pdf))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        cosine_pdf(hit, scattered.dir)
+    }
 
-        if scattered.dir.dot(hit.normal) <= 0. {
-            // NOTE: Deviating from the book here.
-            // The fuzz scattered below the surface. Correct it.
-            scattered.dir -= 2. * fuzz;
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        let albedo = self.albedo.value(&TexCoord::from_hit(ray, hit));
+        albedo * (self.weight(ray, hit, dir) * cosine_pdf(hit, dir))
+    }
+}
+
+#[derive(Debug)]
+/// A cheap approximation of subsurface scattering for wax, skin, or jade. Light enters the
+/// surface, travels a random distance into the object, and scatters from there, so it bleeds
+/// through thin parts of the object.
+pub struct Subsurface {
+    pub albedo: Color,
+    /// Mean distance light travels into the object before scattering back out.
+    pub scatter_distance: f64,
+    /// Bounding box of the object. Scattering points are kept inside it so that light doesn't
+    /// leak across gaps next to thin parts.
+    pub bounds: AABB,
+}
+impl Subsurface {
+    pub fn new(albedo: Color, scatter_distance: f64, bounds: AABB) -> Self {
+        Self {
+            albedo,
+            scatter_distance,
+            bounds,
+        }
+    }
+}
+impl Material for Subsurface {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        // Rays leaving the object from a displaced point pass through the surface unchanged.
+        if !hit.front_face {
+            let through = Ray::new(hit.point, ray.dir, ray.time);
+            return Some(Scatter::new(Color::default(), through));
         }
+
+        // Exponentially distributed depth with a mean of `scatter_distance`
+        let depth = -self.scatter_distance * (1. - rng.gen::<f64>()).ln();
+        let origin = hit.point - depth * hit.normal;
+        let origin = origin.max(self.bounds.min).min(self.bounds.max);
+        // Leave diffusely through the surface that was hit. The displaced origin doesn't match
+        // `hit.point`, so light sampling doesn't apply and the scatter has no pdf.
+        let (dir, _) = cosine_scatter(hit, rng);
+        let scattered = Ray::new(origin, dir, ray.time);
         Some(Scatter::new(self.albedo, scattered))
     }
 }
 
 #[derive(Debug)]
-pub struct Dielectric {
-    pub ref_index: f64,
+/// Diffuse reflection with a sheen that brightens towards the silhouette, like velvet or other
+/// fabrics. This is the opposite of Fresnel reflection off metals, which is strongest where the
+/// surface faces the viewer.
+pub struct Velvet<T> {
+    pub albedo: T,
+    /// The color the albedo is blended towards at grazing angles.
+    pub sheen: Color,
+    /// How much of the sheen is blended in at the silhouette.
+    pub sheen_strength: f64,
 }
-impl Dielectric {
-    pub fn new(ref_index: f64) -> Self {
-        Self { ref_index }
-    }
+impl<T> Velvet<T> {
+    /// How quickly the sheen falls off away from the silhouette.
+    const EXPONENT: i32 = 5;
 
-    pub fn schlick(cos: f64, eta_i_over_eta_t: f64) -> f64 {
-        let r0 = (1. - eta_i_over_eta_t) / (1. + eta_i_over_eta_t);
-        let r0 = r0 * r0;
-        r0 + (1. - r0) * (1. - cos).powi(5)
+    pub fn new(albedo: T, sheen: Color, sheen_strength: f64) -> Self {
+        Self {
+            albedo,
+            sheen,
+            sheen_strength,
+        }
+    }
+}
+impl<T: Texture> Velvet<T> {
+    /// The albedo with the sheen blended in. Only depends on the incoming ray.
+    fn albedo(&self, ray: &Ray, hit: &Hit) -> Color {
+        let albedo = self.albedo.value(&TexCoord::from_hit(ray, hit));
+
+        let cos = Vec3::normalized(ray.dir).dot(hit.normal).abs();
+        let t = (self.sheen_strength * (1. - cos).powi(Self::EXPONENT)).clamp(0., 1.);
+        let albedo = albedo.lerp(self.sheen, t);
+        // Don't let the sheen reflect more light than it receives.
+        Color::new(albedo.r.min(1.), albedo.g.min(1.), albedo.b.min(1.))
     }
 }
-impl Material for Dielectric {
+impl<T: Texture> Material for Velvet<T> {
     fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
-        let eta_i_over_eta_t = if hit.front_face {
-            1. / self.ref_index
-        } else {
-            self.ref_index
-        };
-        let unit_dir = Vec3::normalized(ray.dir);
-        let cos_theta = (-unit_dir).dot(hit.normal).min(1.0);
-        let sin_theta = (1. - cos_theta.powi(2)).sqrt();
+        let (scatter_dir, pdf) = cosine_scatter(hit, rng);
+        let scattered = Ray::new(hit.point, scatter_dir, ray.time);
+        Some(Scatter::new(self.albedo(ray, hit), scattered).with_pdf(pdf))
+    }
 
-        let dir = if eta_i_over_eta_t * sin_theta > 1.0
-            || rng.gen::<f64>() < Self::schlick(cos_theta, eta_i_over_eta_t)
-        {
-            unit_dir.reflect(hit.normal)
-        } else {
-            unit_dir.refract(hit.normal, eta_i_over_eta_t)
-        };
+    fn scattering_pdf(&self, _ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        cosine_pdf(hit, scattered.dir)
+    }
 
-        let scattered = Ray::new(hit.point, dir, ray.time);
-        Some(Scatter::new(Color::default(), scattered))
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.albedo(ray, hit) * cosine_pdf(hit, dir)
     }
 }
 
 #[derive(Debug)]
-/// Used for debugging. Sets albedo to black and the "scattered" ray to the incident ray.
-pub struct DbgBlack {}
-impl Material for DbgBlack {
-    fn scatter(&self, ray: &Ray, _hit: &Hit, _rng: &mut CrateRng) -> Option<Scatter> {
-        // Just return the in-ray with albedo set to black
-        Some(Scatter::new(Color::new(0., 0., 0.), ray.clone()))
-    }
+/// Scatters rays uniformly in every direction. Meant for volumes, but also gives surfaces a
+/// chalky look.
+pub struct Isotropic<T> {
+    pub albedo: T,
 }
-
-// ===== Textures =====
-pub trait Texture: Sync + Debug {
-    fn value(&self, u: f64, v: f64, point: Vec3) -> Color;
+impl<T> Isotropic<T> {
+    pub fn new(albedo: T) -> Self {
+        Self { albedo }
+    }
 }
-impl<T: Texture + Send + Debug> Texture for Arc<T> {
-    fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
-        // Use fully qualified syntax to prevent recursion
-        <T as Texture>::value(self, u, v, point)
+impl<T: Texture> Material for Isotropic<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        let scattered = Ray::new(hit.point, Vec3::rand_unit_sphere(rng), ray.time);
+        let albedo = self.albedo.value(&TexCoord::from_hit(ray, hit));
+        Some(Scatter::new(albedo, scattered))
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Checkered<O, E> {
-    pub freq: f64,
-    pub odd: O,
-    pub even: E,
+#[derive(Debug)]
+pub struct Metal<T = Color, F = Color> {
+    pub albedo: T,
+    /// The fuzziness of the Metal. Is between `0.0` and `1.0`
+    pub fuzz: f64,
+    /// Overrides `fuzz` at each hit with the luminance of the texture, clamped to `[0, 1]`.
+    pub fuzz_texture: Option<F>,
 }
-impl<O, E> Checkered<O, E> {
-    pub fn new(freq: f64, odd: O, even: E) -> Self {
-        Self { freq, odd, even }
+impl Metal {
+    pub fn from(a: [f64; 3], fuzz: f64) -> Self {
+        Self::new(a.into(), fuzz)
     }
 }
-impl Checkered<Color, Color> {
-    pub fn color<T: Into<Color>, U: Into<Color>>(freq: f64, odd: T, even: U) -> Self {
+impl<T> Metal<T> {
+    /// Metal whose color is `albedo`, which can be a `Color` or vary over the surface like any
+    /// other `Texture`.
+    pub fn new(albedo: T, fuzz: f64) -> Self {
+        let fuzz = fuzz.min(1.);
         Self {
-            freq,
-            even: even.into(),
-            odd: odd.into(),
+            albedo,
+            fuzz,
+            fuzz_texture: None,
         }
     }
 }
-impl<O: Texture, E: Texture> Texture for Checkered<O, E> {
-    fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
-        let mut parity = (point.x * self.freq).sin() < 0.;
-        parity ^= (point.y * self.freq).sin() < 0.;
-        parity ^= (point.z * self.freq).sin() < 0.;
-        if parity {
-            self.odd.value(u, v, point)
-        } else {
-            self.even.value(u, v, point)
+impl<T, F> Metal<T, F> {
+    /// Metal whose fuzziness varies over its surface, e.g. to make it look worn.
+    pub fn fuzz_texture(albedo: T, fuzz_texture: F) -> Self {
+        Self {
+            albedo,
+            fuzz: 0.,
+            fuzz_texture: Some(fuzz_texture),
         }
     }
 }
+impl<T: Texture, F: Texture> Material for Metal<T, F> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        let fuzz = match &self.fuzz_texture {
+            Some(texture) => {
+                let fuzz = texture.value(&TexCoord::from_hit(ray, hit)).luminance();
+                fuzz.clamp(0., 1.)
+            }
+            None => self.fuzz,
+        };
+        Some(Scatter::new(
+            self.albedo.value(&TexCoord::from_hit(ray, hit)),
+            fuzzy_reflect(ray, hit, fuzz, rng),
+        ))
+    }
+}
 
-/// A callback function used to vary a noise.
-type Callback<N> = dyn Fn(&N, Vec3) -> f64 + Send + Sync;
-
-/// Common noise patterns
-pub trait NoiseAdapter: Sized {
-    fn noise(&self, p: Vec3) -> f64;
-    fn freq(&self) -> f64;
-    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>>;
+/// Reflects `ray` about `hit.normal`, blurred by a random offset of up to `fuzz`.
+fn fuzzy_reflect(ray: &Ray, hit: &Hit, fuzz: f64, rng: &mut CrateRng) -> Ray {
+    let fuzz = fuzz * Vec3::rand_unit_sphere(rng);
+    let reflected = Vec3::normalized(ray.dir).reflect_unit(hit.normal);
+    // NOTE: Deviating from the book here by keeping the fuzz above the surface.
+    let dir = offset_dir(reflected, fuzz, hit.normal, true);
+    Ray::new(hit.point, dir, ray.time)
+}
 
-    fn into_arc(self) -> Arc<Self> {
-        Arc::new(self)
+/// Offsets `dir` by `fuzz`, flipping the offset if it would move `dir` to the wrong side of the
+/// surface, i.e. below it when `above` or above it otherwise.
+/// Falls back to `dir` when the offset cancels it out, since a degenerate direction turns into
+/// NaNs once it's normalized.
+fn offset_dir(dir: Vec3, fuzz: Vec3, normal: Vec3, above: bool) -> Vec3 {
+    let mut offset = dir + fuzz;
+    if (offset.dot(normal) > 0.) != above {
+        offset -= 2. * fuzz;
     }
+    if offset.near_zero() {
+        dir
+    } else {
+        offset
+    }
+}
 
-    #[allow(non_snake_case)]
-    /// Fractional brownian noise maker.
-    /// Replaces the noises's callback.
-    fn fBm(mut self, lacunarity: f64, gain: f64, layers: usize) -> Self {
-        assert!(layers != 0, "fBm: Can't have 0 layers.");
-        assert!(0. < gain && gain < 1., "fBm: Gain must be in range (0, 1).");
-        // Get the maxiumum possible value of `sum` for later.
-        // Equal to `layers` terms in the geometric series where "a=1, r=gain"
-        let max = (1. - gain.powi(layers as i32)) / (1. - gain);
+/// Schlick's approximation of the fraction of light reflected off of a dielectric, where `cos`
+/// is the cosine of the angle between the incoming ray and the normal.
+pub fn schlick(cos: f64, eta_i_over_eta_t: f64) -> f64 {
+    let r0 = (1. - eta_i_over_eta_t) / (1. + eta_i_over_eta_t);
+    let r0 = r0 * r0;
+    r0 + (1. - r0) * (1. - cos).powi(5)
+}
 
-        // This callback will compute the fractal sum
-        let callback = move |noise: &Self, mut p: Vec3| {
-            let mut sum = 0.;
-            let mut amplitude = 1.;
-            for _ in 0..layers {
-                sum += noise.noise(p) * amplitude;
-                p *= lacunarity;
-                amplitude *= gain;
-            }
+#[derive(Debug)]
+/// Metal whose reflectance follows the Fresnel equations for a conductor with the complex index
+/// of refraction `eta + ik`, measured separately for each color channel. Unlike `Metal`, its
+/// color changes with the viewing angle and washes out towards white at grazing angles.
+/// # Example
+/// ```
+/// # use raytracing::material::Conductor;
+/// let gold = Conductor::gold();
+/// let (head_on, grazing) = (gold.fresnel(1.), gold.fresnel(0.05));
+/// assert!(head_on.r > head_on.b);
+/// assert!(grazing.b > head_on.b);
+/// ```
+pub struct Conductor {
+    pub eta: Color,
+    /// Extinction coefficient, the imaginary part of the index of refraction
+    pub k: Color,
+    /// Fuzziness of the reflection, like `Metal`'s fuzz. Is between `0.0` and `1.0`
+    pub roughness: f64,
+}
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            eta,
+            k,
+            roughness: roughness.clamp(0., 1.),
+        }
+    }
 
-            // Normalize the sum to the range [0, 1]
-            sum / max
-        };
+    // Indices of refraction sampled at the wavelengths of red, green and blue light.
+    // <https://refractiveindex.info>
+    pub fn gold() -> Self {
+        let eta = Color::new(0.143, 0.374, 1.442);
+        Self::new(eta, Color::new(3.983, 2.385, 1.603), 0.)
+    }
 
-        *self.callback() = Some(Box::new(callback));
-        self
+    pub fn silver() -> Self {
+        let eta = Color::new(0.155, 0.117, 0.138);
+        Self::new(eta, Color::new(4.828, 3.122, 2.147), 0.)
     }
 
-    /// Turbulent noise maker.
-    /// Replaces the noises's callback.
-    fn turbulence(mut self, lacunarity: f64, gain: f64, layers: usize) -> Self {
-        assert!(layers != 0, "fBm: Can't have 0 layers.");
-        assert!(0. < gain && gain < 1., "fBm: Gain must be in range (0, 1).");
-        // Get the maxiumum possible value of `sum` for later.
-        // Equal to `layers` terms in the geometric series where "a=1, r=gain"
-        let max = (1. - gain.powi(layers as i32)) / (1. - gain);
+    pub fn copper() -> Self {
+        let eta = Color::new(0.200, 0.924, 1.102);
+        Self::new(eta, Color::new(3.912, 2.452, 2.142), 0.)
+    }
 
-        // This callback will compute the fractal sum
-        let callback = move |noise: &Self, mut p: Vec3| {
-            let mut sum = 0.;
-            let mut amplitude = 1.;
-            for _ in 0..layers {
-                // Convert noise into signed noise by mapping to range [-1., 1]
-                let layer = 2. * noise.noise(p) - 1.;
-                // then taking its absolute value to make turbulent noise
-                sum += layer.abs() * amplitude;
-                p *= lacunarity;
-                amplitude *= gain;
-            }
+    pub fn aluminum() -> Self {
+        let eta = Color::new(1.657, 0.880, 0.521);
+        Self::new(eta, Color::new(9.224, 6.270, 4.837), 0.)
+    }
 
-            // Normalize the sum to the range [0, 1]
-            sum / max
+    /// Fraction of unpolarized light reflected in each channel, where `cos` is the cosine of the
+    /// angle between the incoming light and the normal.
+    pub fn fresnel(&self, cos: f64) -> Color {
+        let reflectance = |eta: f64, k: f64| {
+            let cos2 = cos * cos;
+            let sin2 = 1. - cos2;
+            let t0 = eta * eta - k * k - sin2;
+            let a2_plus_b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+            let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+
+            let (t1, t2) = (a2_plus_b2 + cos2, 2. * cos * a);
+            let rs = (t1 - t2) / (t1 + t2);
+            let (t3, t4) = (cos2 * a2_plus_b2 + sin2 * sin2, t2 * sin2);
+            let rp = rs * (t3 - t4) / (t3 + t4);
+            0.5 * (rs + rp)
         };
+        let (eta, k) = (self.eta, self.k);
+        Color::new(
+            reflectance(eta.r, k.r),
+            reflectance(eta.g, k.g),
+            reflectance(eta.b, k.b),
+        )
+    }
+}
+impl Material for Conductor {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        let cos = (-Vec3::normalized(ray.dir)).dot(hit.normal).clamp(0., 1.);
+        let scattered = fuzzy_reflect(ray, hit, self.roughness, rng);
+        Some(Scatter::new(self.fresnel(cos), scattered))
+    }
+}
 
-        *self.callback() = Some(Box::new(callback));
-        self
+#[derive(Debug)]
+/// Shiny plastic. Each scatter is either a diffuse bounce or a glossy reflection around the
+/// mirror direction that gets tighter as `shininess` increases. To conserve energy,
+/// `diffuse + specular` shouldn't be greater than 1 in any channel.
+pub struct Phong {
+    pub diffuse: Color,
+    pub specular: Color,
+    pub shininess: f64,
+}
+impl Phong {
+    pub fn new(diffuse: Color, specular: Color, shininess: f64) -> Self {
+        Self {
+            diffuse,
+            specular,
+            shininess,
+        }
     }
 
-    /// Marbled noise.
-    /// Wraps the noise's callback.
-    fn marbled(mut self, (sin_freq, phase_shift): (f64, f64), noise_gain: f64) -> Self
-    where
-        Self: 'static,
-    {
-        // Take the noise's callback. If there was none, create one that does nothing.
-        let callback = self
-            .callback()
-            .take()
-            .unwrap_or_else(|| Box::new(|noise: &Self, p: Vec3| noise.noise(p)));
+    /// Probability of sampling the specular lobe instead of the diffuse one.
+    fn specular_chance(&self) -> f64 {
+        let (diffuse, specular) = (self.diffuse.luminance(), self.specular.luminance());
+        if specular <= 0. {
+            0.
+        } else {
+            specular / (diffuse + specular)
+        }
+    }
 
-        let gain = self.freq() * noise_gain;
-        let marbled = move |noise: &Self, p: Vec3| {
-            let noise = callback(noise, p);
-            // Perturb/shift the phase of the sine function using the noise.
-            let noise = gain * noise + p.z;
-            // Computer and normalize sine to range [0, 1]
-            let noise = sin_freq * noise + phase_shift;
-            (noise.sin() + 1.) * 0.5
+    /// Probability density of the specular lobe, which is proportional to
+    /// `cos(alpha)^shininess` where `alpha` is the angle between `dir` and `mirror`.
+    fn specular_pdf(&self, mirror: Vec3, dir: Vec3) -> f64 {
+        let cos_alpha = mirror.dot(Vec3::normalized(dir)).max(0.);
+        (self.shininess + 1.) / (2. * consts::PI) * cos_alpha.powf(self.shininess)
+    }
+
+    /// Normalizes the specular lobe so that it doesn't reflect more than `specular`.
+    fn norm(&self) -> f64 {
+        (self.shininess + 2.) / (self.shininess + 1.)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> f64 {
+        let chance = self.specular_chance();
+        let mirror = Vec3::normalized(ray.dir).reflect_unit(hit.normal);
+        (1. - chance) * cosine_pdf(hit, dir) + chance * self.specular_pdf(mirror, dir)
+    }
+}
+impl Material for Phong {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        let chance = self.specular_chance();
+        let (dir, albedo) = if rng.gen::<f64>() >= chance {
+            let (dir, _) = cosine_scatter(hit, rng);
+            (dir, self.diffuse / (1. - chance))
+        } else {
+            // Power-cosine sampling around the mirror direction
+            let mirror = Vec3::normalized(ray.dir).reflect_unit(hit.normal);
+            let cos_alpha = rng.gen::<f64>().powf(1. / (self.shininess + 1.));
+            let sin_alpha = (1. - cos_alpha.powi(2)).sqrt();
+            let (sin_phi, cos_phi) = (2. * consts::PI * rng.gen::<f64>()).sin_cos();
+            let local = Vec3::new(sin_alpha * cos_phi, sin_alpha * sin_phi, cos_alpha);
+            let dir = Onb::from_w(mirror).local(local);
+
+            // The lobe can sample below the surface, which reflects nothing. The direction is
+            // still returned so that light sampling can pick a light instead.
+            let cos_theta = dir.dot(hit.normal).max(0.);
+            (dir, self.specular * (self.norm() * cos_theta / chance))
         };
 
-        *self.callback() = Some(Box::new(marbled));
-        self
+        let scattered = Ray::new(hit.point, dir, ray.time);
+        Some(Scatter::new(albedo, scattered).with_pdf(self.pdf(ray, hit, dir)))
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        self.pdf(ray, hit, scattered.dir)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        let mirror = Vec3::normalized(ray.dir).reflect_unit(hit.normal);
+        let cos_theta = Vec3::normalized(dir).dot(hit.normal).max(0.);
+        let specular = self.norm() * self.specular_pdf(mirror, dir) * cos_theta;
+        self.diffuse * cosine_pdf(hit, dir) + self.specular * specular
     }
 }
-/// 3D Value Noise
-pub struct ValueNoise {
-    randoms: [f64; Self::SIZE],
-    /// The permutations table.
+
+#[derive(Debug)]
+/// Glossy plastic: a diffuse base under a clear coat. The coat reflects white light following
+/// the Fresnel equations, so it's barely visible head on but turns into a mirror at grazing
+/// angles. `roughness` blurs the reflections like `Metal`'s fuzz.
+pub struct Plastic<T> {
+    pub albedo: T,
+    /// Is between `0.0` (perfectly smooth) and `1.0`
+    pub roughness: f64,
+    /// Index of refraction of the clear coat. Higher values reflect more light head on, e.g.
+    /// `1.5` reflects 4% and `2.0` reflects 11%.
+    pub ref_index: f64,
+}
+impl<T> Plastic<T> {
+    /// Plastic with a clear coat whose index of refraction is `1.5`
+    pub fn new(albedo: T, roughness: f64) -> Self {
+        Self::with_ref_index(albedo, roughness, 1.5)
+    }
+
+    pub fn with_ref_index(albedo: T, roughness: f64, ref_index: f64) -> Self {
+        Self {
+            albedo,
+            roughness: roughness.clamp(0., 1.),
+            ref_index,
+        }
+    }
+}
+impl<T: Texture> Material for Plastic<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        let cos = (-Vec3::normalized(ray.dir)).dot(hit.normal).clamp(0., 1.);
+        if rng.gen::<f64>() < schlick(cos, 1. / self.ref_index) {
+            let reflected = fuzzy_reflect(ray, hit, self.roughness, rng);
+            return Some(Scatter::new(Color::default(), reflected));
+        }
+
+        let (dir, pdf) = cosine_scatter(hit, rng);
+        let scattered = Ray::new(hit.point, dir, ray.time);
+        let albedo = self.albedo.value(&TexCoord::from_hit(ray, hit));
+        Some(Scatter::new(albedo, scattered).with_pdf(pdf))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        // Only the diffuse lobe has a pdf
+        cosine_pdf(hit, scattered.dir)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        // The diffuse lobe's, since the clear coat was already skipped with its reflectance's
+        // chance
+        let albedo = self.albedo.value(&TexCoord::from_hit(ray, hit));
+        albedo * cosine_pdf(hit, dir)
+    }
+}
+
+#[derive(Debug)]
+/// The metallic-roughness model used by glTF. `metallic` blends between a dielectric with a
+/// diffuse base and a metal, and `roughness` controls how blurry reflections are. Both are
+/// between `0.0` and `1.0`.
+pub struct Pbr<T> {
+    pub base_color: T,
+    pub metallic: f64,
+    pub roughness: f64,
+}
+impl<T> Pbr<T> {
+    pub fn new(base_color: T, metallic: f64, roughness: f64) -> Self {
+        Self {
+            base_color,
+            metallic,
+            roughness,
+        }
+    }
+}
+impl<T: Texture> Pbr<T> {
+    /// The reflectance head on, the diffuse lobe's color divided by its chance of being
+    /// sampled, and the chance of sampling the specular lobe instead. `None` if nothing is
+    /// reflected.
+    fn lobes(&self, ray: &Ray, hit: &Hit) -> Option<(Color, Color, f64)> {
+        let white = Color::new(1., 1., 1.);
+        let base = self.base_color.value(&TexCoord::from_hit(ray, hit));
+        let metallic = self.metallic.clamp(0., 1.);
+        // Dielectrics reflect about 4% of light head on
+        let f0 = Color::new(0.04, 0.04, 0.04).lerp(base, metallic);
+        let cos_view = (-Vec3::normalized(ray.dir)).dot(hit.normal).max(1e-6);
+        let fresnel = schlick_color(f0, cos_view);
+        let diffuse = base * (1. - metallic) * (white - fresnel);
+
+        // Pick a lobe based on how much light each one reflects
+        let specular_weight = fresnel.luminance();
+        let diffuse_weight = diffuse.luminance();
+        if specular_weight + diffuse_weight <= 0. {
+            return None;
+        }
+        let chance = specular_weight / (specular_weight + diffuse_weight);
+        let diffuse = if chance < 1. {
+            diffuse / (1. - chance)
+        } else {
+            Color::new(0., 0., 0.)
+        };
+        Some((f0, diffuse, chance))
+    }
+}
+impl<T: Texture> Material for Pbr<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        let (f0, diffuse, chance) = self.lobes(ray, hit)?;
+        if rng.gen::<f64>() >= chance {
+            let (dir, pdf) = cosine_scatter(hit, rng);
+            let scattered = Ray::new(hit.point, dir, ray.time);
+            return Some(Scatter::new(diffuse, scattered).with_pdf(pdf));
+        }
+
+        // Sample a microfacet normal from the GGX distribution and reflect off of it
+        let alpha = self.roughness.clamp(0., 1.).powi(2);
+        let alpha2 = alpha * alpha;
+        let u = rng.gen::<f64>();
+        let cos_h = ((1. - u) / (1. + (alpha2 - 1.) * u)).sqrt();
+        let sin_h = (1. - cos_h * cos_h).max(0.).sqrt();
+        let (sin_phi, cos_phi) = (2. * consts::PI * rng.gen::<f64>()).sin_cos();
+        let local = Vec3::new(sin_h * cos_phi, sin_h * sin_phi, cos_h);
+        let half = Onb::from_w(hit.normal).local(local);
+        let mut dir = Vec3::normalized(ray.dir).reflect_unit(half);
+
+        // Rough microfacets can reflect below the surface. Mirror those directions back above
+        // it instead of losing their light.
+        let mut cos_light = dir.dot(hit.normal);
+        if cos_light <= 0. {
+            dir -= 2. * cos_light * hit.normal;
+            cos_light = -cos_light;
+        }
+        // Smith masking-shadowing for GGX
+        let view = -Vec3::normalized(ray.dir);
+        let cos_view = view.dot(hit.normal).max(1e-6);
+        let g1 = |cos: f64| 2. * cos / (cos + (alpha2 + (1. - alpha2) * cos * cos).sqrt());
+        let cos_vh = view.dot(half).max(0.);
+        let weight = g1(cos_light) * g1(cos_view) * cos_vh / (cos_view * cos_h);
+        let albedo = schlick_color(f0, cos_vh) * (weight / chance);
+
+        Some(Scatter::new(albedo, Ray::new(hit.point, dir, ray.time)))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        // Only the diffuse lobe has a pdf
+        cosine_pdf(hit, scattered.dir)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        // The diffuse lobe's, since the specular lobe was already skipped with its chance
+        self.lobes(ray, hit)
+            .map_or(Color::new(0., 0., 0.), |(_, diffuse, _)| {
+                diffuse * cosine_pdf(hit, dir)
+            })
+    }
+}
+
+/// Schlick's approximation of the fraction of light reflected in each channel off of a
+/// surface that reflects `f0` head on, where `cos` is the cosine of the angle between the light
+/// and the normal.
+fn schlick_color(f0: Color, cos: f64) -> Color {
+    f0 + (Color::new(1., 1., 1.) - f0) * (1. - cos).powi(5)
+}
+
+#[derive(Clone, Debug)]
+pub struct Dielectric<T = Color> {
+    pub ref_index: f64,
+    /// Absorption coefficient of each color channel per unit distance travelled inside the
+    /// material. Light that travels `d` units through it keeps `exp(-attenuation * d)` of each
+    /// channel, so coefficients above 1 are fine.
+    pub attenuation: Color,
+    /// How frosted the Dielectric is. Is between `0.0` (perfectly smooth) and `1.0`
+    pub roughness: f64,
+    /// Color of rays transmitted through the surface. Reflected rays aren't tinted.
+    pub tint: T,
+    /// Abbe number of the material, where lower numbers spread the colors of refracted light
+    /// further apart. Crown glass is around 60 and flint glass around 30. `None` disables
+    /// dispersion.
+    pub abbe: Option<f64>,
+}
+impl Dielectric {
+    /// Perfectly clear dielectric
+    pub fn new(ref_index: f64) -> Self {
+        Self::absorbing(ref_index, Color::new(0., 0., 0.))
+    }
+
+    /// Dielectric that absorbs light following the Beer–Lambert law, so thicker parts of the
+    /// object are more strongly tinted.
+    pub fn absorbing(ref_index: f64, attenuation: Color) -> Self {
+        Self {
+            ref_index,
+            attenuation,
+            roughness: 0.,
+            tint: Color::new(1., 1., 1.),
+            abbe: None,
+        }
+    }
+
+    /// Frosted glass, which blurs the reflected and refracted rays like `Metal`'s fuzz.
+    pub fn frosted(ref_index: f64, roughness: f64) -> Self {
+        Self {
+            roughness: roughness.clamp(0., 1.),
+            ..Self::new(ref_index)
+        }
+    }
+
+    /// Glass that splits white light into its colors, like a prism. `ref_index` is the index of
+    /// refraction for yellow light.
+    ///
+    /// Each path through the glass carries a single randomly chosen wavelength, so at low sample
+    /// counts the split light is speckled with pixels of one saturated color. Smooth rainbows
+    /// take a few hundred samples per pixel, e.g. the `Prism` scene's variance drops about 14x
+    /// going from 16 to 256 samples.
+    pub fn dispersive(ref_index: f64, abbe: f64) -> Self {
+        Self {
+            abbe: Some(abbe),
+            ..Self::new(ref_index)
+        }
+    }
+}
+impl<T> Dielectric<T> {
+    /// Stained glass, where the color of the transmitted light varies over the surface.
+    pub fn tinted(ref_index: f64, tint: T) -> Self {
+        Self {
+            ref_index,
+            attenuation: Color::new(0., 0., 0.),
+            roughness: 0.,
+            tint,
+            abbe: None,
+        }
+    }
+
+    /// Index of refraction for light of the given wavelength (in nanometers), using Cauchy's
+    /// equation `n = A + B / wavelength^2` fitted to `ref_index` and `abbe`.
+    pub fn ref_index_at(&self, wavelength: f64) -> f64 {
+        // The Fraunhofer d, F and C lines used to define the Abbe number
+        const D: f64 = 587.6;
+        const F: f64 = 486.1;
+        const C: f64 = 656.3;
+        match self.abbe {
+            Some(abbe) => {
+                let b = (self.ref_index - 1.) / (abbe * (F.powi(-2) - C.powi(-2)));
+                self.ref_index + b * (wavelength.powi(-2) - D.powi(-2))
+            }
+            None => self.ref_index,
+        }
+    }
+}
+impl<T: Texture> Material for Dielectric<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        // A dispersive material picks a single wavelength for the rest of the path, and tints it
+        // by that wavelength's color.
+        let (wavelength, spectrum) = match (self.abbe, ray.wavelength) {
+            (Some(_), None) => {
+                let range = Color::WAVELENGTHS;
+                let wavelength = rng.gen_range(range.start, range.end);
+                (Some(wavelength), Color::from_wavelength(wavelength))
+            }
+            _ => (ray.wavelength, Color::default()),
+        };
+        let ref_index = wavelength.map_or(self.ref_index, |w| self.ref_index_at(w));
+        let eta_i_over_eta_t = if hit.front_face {
+            1. / ref_index
+        } else {
+            ref_index
+        };
+        let unit_dir = Vec3::normalized(ray.dir);
+        let cos_theta = (-unit_dir).dot(hit.normal).min(1.0);
+        let sin_theta = (1. - cos_theta.powi(2)).sqrt();
+
+        let reflect = eta_i_over_eta_t * sin_theta > 1.0
+            || rng.gen::<f64>() < schlick(cos_theta, eta_i_over_eta_t);
+        let dir = if reflect {
+            unit_dir.reflect_unit(hit.normal)
+        } else {
+            unit_dir.refract_unit(hit.normal, eta_i_over_eta_t)
+        };
+
+        // Don't touch the rng when smooth so that the output is the same as before roughness.
+        let dir = if self.roughness > 0. {
+            let fuzz = self.roughness * Vec3::rand_unit_sphere(rng);
+            // Reflected rays must stay above the surface and refracted rays below it.
+            offset_dir(dir, fuzz, hit.normal, reflect)
+        } else {
+            dir
+        };
+
+        // When hitting the back face, the ray has been travelling through the material.
+        let mut albedo = if hit.front_face {
+            Color::default()
+        } else {
+            let distance = hit.time * ray.dir.norm();
+            let Color { r, g, b } = self.attenuation;
+            Color::new(
+                (-r * distance).exp(),
+                (-g * distance).exp(),
+                (-b * distance).exp(),
+            )
+        };
+        if !reflect {
+            albedo *= self.tint.value(&TexCoord::from_hit(ray, hit));
+        }
+        albedo *= spectrum;
+
+        let mut scattered = Ray::new(hit.point, dir, ray.time);
+        scattered.wavelength = wavelength;
+        Some(Scatter::new(albedo, scattered))
+    }
+}
+
+/// Glass with a separate index of refraction for each color channel, e.g. for prisms and gems
+/// that split white light into its colors. Unlike `Dielectric::dispersive`, which samples the
+/// whole spectrum, it splits light into just three colors.
+///
+/// Each path through the glass follows a single randomly chosen channel, carrying three times
+/// that channel's light and none of the others so that the average stays the same. So at low
+/// sample counts the glass is speckled with pure red, green and blue pixels, and it takes a few
+/// hundred samples per pixel for them to blend. The noise falls with the square root of the
+/// sample count, e.g. the `SkyPrism` scene's mean standard error drops from about 0.045 at 16
+/// samples to 0.011 at 256.
+#[derive(Clone, Debug)]
+pub struct DispersiveDielectric {
+    pub ior_r: f64,
+    pub ior_g: f64,
+    pub ior_b: f64,
+}
+impl DispersiveDielectric {
+    /// Wavelengths (in nanometers) that stand in for the red, green and blue channels on rays
+    /// that follow a single channel.
+    const WAVELENGTHS: [f64; 3] = [650., 550., 450.];
+
+    pub fn new(ior_r: f64, ior_g: f64, ior_b: f64) -> Self {
+        Self {
+            ior_r,
+            ior_g,
+            ior_b,
+        }
+    }
+
+    /// The channel that light of the given wavelength (in nanometers) counts towards.
+    fn channel(wavelength: f64) -> usize {
+        if wavelength >= 600. {
+            0
+        } else if wavelength >= 500. {
+            1
+        } else {
+            2
+        }
+    }
+}
+impl Material for DispersiveDielectric {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        // Pick a channel for the rest of the path, unless a dispersive material already has. The
+        // ray's wavelength keeps track of it.
+        let (wavelength, albedo) = match ray.wavelength {
+            None => {
+                let channel = rng.gen_range(0, 3);
+                let mut albedo = [0.; 3];
+                albedo[channel] = 3.;
+                (Self::WAVELENGTHS[channel], albedo.into())
+            }
+            Some(wavelength) => (wavelength, Color::default()),
+        };
+        let ref_index = [self.ior_r, self.ior_g, self.ior_b][Self::channel(wavelength)];
+
+        let mut scatter = Dielectric::new(ref_index).scatter(ray, hit, rng)?;
+        scatter.albedo *= albedo;
+        scatter.ray.wavelength = Some(wavelength);
+        Some(scatter)
+    }
+}
+
+#[derive(Debug)]
+/// Light source that emits `emit` scaled by `intensity` and doesn't reflect light.
+pub struct DiffuseLight<T> {
+    pub emit: T,
+    pub intensity: f64,
+}
+impl<T> DiffuseLight<T> {
+    pub fn new(emit: T, intensity: f64) -> Self {
+        Self { emit, intensity }
+    }
+}
+impl DiffuseLight<Color> {
+    /// A white light. `intensity` can be greater than 1 for lights brighter than white.
+    pub fn white(intensity: f64) -> Self {
+        Self::new(Color::new(1., 1., 1.), intensity)
+    }
+}
+impl<T: Texture> Material for DiffuseLight<T> {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _rng: &mut CrateRng) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.emit.value(&TexCoord::from_hit(ray, hit)) * self.intensity
+    }
+}
+
+#[derive(Debug)]
+/// Light that the camera can't see directly. Camera rays pass straight through it, while
+/// scattered rays see `inner` as usual, so a large light can brighten the scene without
+/// showing up in the image.
+pub struct HiddenEmitter<M> {
+    pub inner: M,
+}
+impl<M> HiddenEmitter<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+impl<M: Material> Material for HiddenEmitter<M> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        if ray.primary {
+            let passed = Ray {
+                origin: hit.point,
+                ..ray.clone()
+            };
+            return Some(Scatter::new(Color::default(), passed));
+        }
+        self.inner.scatter(ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        if ray.primary {
+            return Color::new(0., 0., 0.);
+        }
+        self.inner.emitted(ray, hit)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        self.inner.scattering_pdf(ray, hit, scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.inner.eval(ray, hit, dir)
+    }
+}
+
+#[derive(Debug)]
+/// Used for debugging. Shows the outward surface normal as a color: each component is mapped
+/// from `-1..1` to `0..1`. Doesn't scatter, so the result is the same for every sample.
+pub struct NormalDebug {}
+impl Material for NormalDebug {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _rng: &mut CrateRng) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, _ray: &Ray, hit: &Hit) -> Color {
+        // Undo the flip that makes the normal face the incident ray.
+        let normal = if hit.front_face {
+            hit.normal
+        } else {
+            -hit.normal
+        };
+        let Vec3 { x, y, z } = 0.5 * (normal + Vec3::new(1., 1., 1.));
+        Color::new(x, y, z)
+    }
+}
+
+#[derive(Debug)]
+/// Used for debugging. Shows the distance from the ray's origin as gray, going from white at
+/// `near` to black at `far`. Doesn't scatter, so on camera rays this gives a depth map.
+pub struct DepthDebug {
+    pub near: f64,
+    pub far: f64,
+}
+impl DepthDebug {
+    pub fn new(near: f64, far: f64) -> Self {
+        Self { near, far }
+    }
+}
+impl Material for DepthDebug {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _rng: &mut CrateRng) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        // `hit.time` is in units of the ray's direction, which isn't always normalized.
+        let distance = hit.time * ray.dir.norm();
+        let gray = (1. - (distance - self.near) / (self.far - self.near)).clamp(0., 1.);
+        Color::new(gray, gray, gray)
+    }
+}
+
+#[derive(Debug)]
+/// Uses the `front` material for hits on the front face and `back` for hits on the back face.
+/// The front face is the side the shape's outward normal points to.
+pub struct TwoSided<F, B> {
+    pub front: F,
+    pub back: B,
+}
+impl<F, B> TwoSided<F, B> {
+    pub fn new(front: F, back: B) -> Self {
+        Self { front, back }
+    }
+}
+impl<F: Material, B: Material> Material for TwoSided<F, B> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        if hit.front_face {
+            self.front.scatter(ray, hit, rng)
+        } else {
+            self.back.scatter(ray, hit, rng)
+        }
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        if hit.front_face {
+            self.front.emitted(ray, hit)
+        } else {
+            self.back.emitted(ray, hit)
+        }
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        if hit.front_face {
+            self.front.scattering_pdf(ray, hit, scattered)
+        } else {
+            self.back.scattering_pdf(ray, hit, scattered)
+        }
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        if hit.front_face {
+            self.front.eval(ray, hit, dir)
+        } else {
+            self.back.eval(ray, hit, dir)
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Uses material `a` where the luminance of `mask` is at least `threshold` and `b` elsewhere,
+/// e.g. for rust patches on painted metal.
+pub struct Masked<A, B, T> {
+    pub a: A,
+    pub b: B,
+    pub mask: T,
+    /// Defaults to `0.5`
+    pub threshold: f64,
+}
+impl<A, B, T> Masked<A, B, T> {
+    pub fn new(a: A, b: B, mask: T) -> Self {
+        Self {
+            a,
+            b,
+            mask,
+            threshold: 0.5,
+        }
+    }
+}
+impl<A: Material, B: Material, T: Texture> Masked<A, B, T> {
+    fn pick(&self, ray: &Ray, hit: &Hit) -> &dyn Material {
+        if self.mask.value(&TexCoord::from_hit(ray, hit)).luminance() >= self.threshold {
+            &self.a
+        } else {
+            &self.b
+        }
+    }
+}
+impl<A: Material, B: Material, T: Texture> Material for Masked<A, B, T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        self.pick(ray, hit).scatter(ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.pick(ray, hit).emitted(ray, hit)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        self.pick(ray, hit).scattering_pdf(ray, hit, scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.pick(ray, hit).eval(ray, hit, dir)
+    }
+}
+
+/// Basis whose `u` and `v` axes point towards increasing `u` and `v` on the surface, and whose
+/// `w` axis is `normal`. Follows a `Sphere`'s uv parameterization around the y axis.
+fn tangent_frame(normal: Vec3) -> Onb {
+    let tangent = Vec3::UNIT_Y.cross(normal);
+    // The tangent vanishes at the poles, so pick any frame there.
+    if tangent.near_zero() {
+        return Onb::from_w(normal);
+    }
+    let tangent = Vec3::normalized(tangent);
+    Onb {
+        u: tangent,
+        v: normal.cross(tangent),
+        w: normal,
+    }
+}
+
+/// The normal at `coord` perturbed by the slope of the luminance of `height`, scaled by
+/// `strength`. Shared by `BumpMapped` and `Bumpy`.
+pub(crate) fn bump_normal<T: Texture + ?Sized>(
+    height: &T,
+    strength: f64,
+    coord: &TexCoord,
+) -> Vec3 {
+    /// Step size used for the finite differences.
+    const DELTA: f64 = 1e-4;
+    /// Bound on the tangential part of the perturbation so that the normal stays away from the
+    /// tangent plane. Equal to `tan(80 deg)`.
+    const MAX_SLOPE: f64 = 5.67;
+
+    let frame = tangent_frame(coord.normal);
+    let height = |u, v, point| {
+        let coord = TexCoord {
+            u,
+            v,
+            point,
+            ..*coord
+        };
+        height.value(&coord).luminance()
+    };
+
+    // Step both the texture coordinates and the point, so that both uv-mapped and solid
+    // textures (e.g. noise) have a slope.
+    let (u, v, p) = (coord.u, coord.v, coord.point);
+    let h = height(u, v, p);
+    let slope_u = (height(u + DELTA, v, p + DELTA * frame.u) - h) / DELTA;
+    let slope_v = (height(u, v + DELTA, p + DELTA * frame.v) - h) / DELTA;
+    let mut offset = strength * (slope_u * frame.u + slope_v * frame.v);
+
+    // The offset is parallel to the tangent plane, so as long as it's bounded the perturbed
+    // normal stays on the same side of the surface.
+    let len = offset.norm();
+    if len > MAX_SLOPE {
+        offset *= MAX_SLOPE / len;
+    }
+    Vec3::normalized(coord.normal - offset)
+}
+
+#[derive(Debug)]
+/// Adds surface detail to `material` by perturbing the normal with a tangent-space normal map.
+/// Each texel's RGB is mapped from `0..1` to `-1..1` and gives the normal's components along the
+/// tangent (increasing `u`), bitangent (increasing `v`) and the surface normal.
+pub struct NormalMapped<M, T> {
+    pub material: M,
+    pub normal_map: T,
+}
+impl<M, T> NormalMapped<M, T> {
+    pub fn new(material: M, normal_map: T) -> Self {
+        Self {
+            material,
+            normal_map,
+        }
+    }
+}
+impl<M: Material, T: Texture> NormalMapped<M, T> {
+    /// The hit with its normal replaced by the one from the normal map.
+    fn mapped<'a>(&self, ray: &Ray, hit: &Hit<'a>) -> Hit<'a> {
+        let Color { r, g, b } = self.normal_map.value(&TexCoord::from_hit(ray, hit));
+        let local = Vec3::new(2. * r - 1., 2. * g - 1., 2. * b - 1.);
+        let mapped = tangent_frame(hit.normal).local(local);
+        // Keep the original normal if the mapped one would face away from the ray.
+        if mapped.near_zero() || mapped.dot(ray.dir) >= 0. {
+            hit.with_normal(hit.normal)
+        } else {
+            hit.with_normal(Vec3::normalized(mapped))
+        }
+    }
+}
+impl<M: Material, T: Texture> Material for NormalMapped<M, T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        self.material.scatter(ray, &self.mapped(ray, hit), rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.material.emitted(ray, &self.mapped(ray, hit))
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        self.material
+            .scattering_pdf(ray, &self.mapped(ray, hit), scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.material.eval(ray, &self.mapped(ray, hit), dir)
+    }
+}
+
+#[derive(Debug)]
+/// Makes `material` look bumpy by perturbing the normal with the slope of a height texture. Like
+/// `Bumpy`, but as a material so that it works on any shape with uv coordinates.
+pub struct BumpMapped<M, T> {
+    pub material: M,
+    /// The luminance of the texture is used as the height.
+    pub height: T,
+    pub strength: f64,
+}
+impl<M, T> BumpMapped<M, T> {
+    pub fn new(material: M, height: T, strength: f64) -> Self {
+        Self {
+            material,
+            height,
+            strength,
+        }
+    }
+}
+impl<M: Material, T: Texture> BumpMapped<M, T> {
+    /// The hit with its normal perturbed by the height texture.
+    fn bumped<'a>(&self, ray: &Ray, hit: &Hit<'a>) -> Hit<'a> {
+        let coord = TexCoord::from_hit(ray, hit);
+        hit.with_normal(bump_normal(&self.height, self.strength, &coord))
+    }
+}
+impl<M: Material, T: Texture> Material for BumpMapped<M, T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        self.material.scatter(ray, &self.bumped(ray, hit), rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.material.emitted(ray, &self.bumped(ray, hit))
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        self.material
+            .scattering_pdf(ray, &self.bumped(ray, hit), scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.material.eval(ray, &self.bumped(ray, hit), dir)
+    }
+}
+
+#[derive(Debug)]
+/// Used for debugging. Sets albedo to black and the "scattered" ray to the incident ray.
+pub struct DbgBlack {}
+impl Material for DbgBlack {
+    fn scatter(&self, ray: &Ray, _hit: &Hit, _rng: &mut CrateRng) -> Option<Scatter> {
+        // Just return the in-ray with albedo set to black
+        Some(Scatter::new(Color::new(0., 0., 0.), ray.clone()))
+    }
+}
+
+// ===== Textures =====
+/// The color of a surface at a point, e.g. the albedo of a `Lambertian`.
+///
+/// Textures behind an `Arc`, a `Box`, or a reference are textures too, so textures of different
+/// types can be mixed at runtime.
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use raytracing::material::{Checkered, Lambertian};
+/// # use raytracing::{Color, Material, Texture};
+/// let textures: Vec<Box<dyn Texture>> = vec![
+///     Box::new(Color::new(0.5, 0.5, 0.5)),
+///     Box::new(Checkered::color(10., [0., 0., 0.], [1., 1., 1.])),
+/// ];
+/// for albedo in textures {
+///     let _: Box<dyn Material> = Box::new(Lambertian::new(albedo));
+/// }
+/// let shared: Arc<dyn Texture + Send> = Arc::new(Color::new(1., 0., 0.));
+/// let _ = Lambertian::new(shared.clone());
+/// let _ = Lambertian::new(&*shared);
+/// ```
+pub trait Texture: Sync + Debug {
+    fn value(&self, coord: &TexCoord) -> Color;
+}
+impl<T: Texture + Send + ?Sized> Texture for Arc<T> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        // Use fully qualified syntax to prevent recursion
+        <T as Texture>::value(self, coord)
+    }
+}
+impl<T: Texture + ?Sized> Texture for Box<T> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        // Use fully qualified syntax to prevent recursion
+        <T as Texture>::value(self, coord)
+    }
+}
+impl<T: Texture + ?Sized> Texture for &T {
+    fn value(&self, coord: &TexCoord) -> Color {
+        // Use fully qualified syntax to prevent recursion
+        <T as Texture>::value(self, coord)
+    }
+}
+
+/// Where a `Texture` is looked up. Besides the texture coordinates and the point, it carries
+/// what only some textures depend on, which the others ignore.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TexCoord {
+    pub u: f64,
+    pub v: f64,
+    pub point: Vec3,
+    /// The surface's normal, for textures that depend on its orientation, like `Triplanar`
+    pub normal: Vec3,
+    /// The ray's time, for textures that change over time, like `PerlinNoise4`
+    pub time: f64,
+    /// How far the point is from the camera along the path, for textures that are averaged
+    /// over more of their pixels further away so they don't sparkle, like `ImageTexture`
+    pub distance: f64,
+}
+impl TexCoord {
+    /// At time `0` on a surface facing up, right in front of the camera.
+    pub fn new(u: f64, v: f64, point: Vec3) -> Self {
+        Self {
+            u,
+            v,
+            point,
+            normal: Vec3::UNIT_Y,
+            time: 0.,
+            distance: 0.,
+        }
+    }
+
+    /// Where `ray` hit a surface.
+    pub fn from_hit(ray: &Ray, hit: &Hit) -> Self {
+        Self {
+            normal: hit.normal,
+            time: ray.time,
+            distance: ray.distance + hit.time * ray.dir.norm(),
+            ..Self::new(hit.u, hit.v, hit.point)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Checkered<O, E> {
+    pub freq: f64,
+    pub odd: O,
+    pub even: E,
+    /// Defaults to `CheckerSpace::World`
+    pub space: CheckerSpace,
+}
+impl<O, E> Checkered<O, E> {
+    pub fn new(freq: f64, odd: O, even: E) -> Self {
+        Self {
+            freq,
+            odd,
+            even,
+            space: CheckerSpace::World,
+        }
+    }
+}
+impl Checkered<Color, Color> {
+    pub fn color<T: Into<Color>, U: Into<Color>>(freq: f64, odd: T, even: U) -> Self {
+        Self::new(freq, odd.into(), even.into())
+    }
+}
+impl<O: Texture, E: Texture> Texture for Checkered<O, E> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let parity = match self.space {
+            CheckerSpace::World => {
+                let point = coord.point;
+                let mut parity = (point.x * self.freq).sin() < 0.;
+                parity ^= (point.y * self.freq).sin() < 0.;
+                parity ^= (point.z * self.freq).sin() < 0.;
+                parity
+            }
+            CheckerSpace::Uv => uv_parity(coord, self.freq, self.freq),
+        };
+        if parity {
+            self.odd.value(coord)
+        } else {
+            self.even.value(coord)
+        }
+    }
+}
+
+/// Where `Checkered` lays out its tiles.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CheckerSpace {
+    /// Sine waves along each axis of the world, with `freq` radians per unit. Moving an object
+    /// moves it through the pattern.
+    World,
+    /// `freq` tiles along both `u` and `v`, so the pattern stays fixed to the surface wherever
+    /// the object is placed. See `UvCheckered` for different numbers of tiles along each.
+    Uv,
+}
+
+/// Checkered pattern tiled over the texture coordinates instead of world space, so it stays
+/// fixed to the surface.
+#[derive(Clone, Debug)]
+pub struct UvCheckered<O, E> {
+    /// Number of tiles along `u`
+    pub freq_u: f64,
+    /// Number of tiles along `v`
+    pub freq_v: f64,
+    pub odd: O,
+    pub even: E,
+}
+impl<O, E> UvCheckered<O, E> {
+    pub fn new(freq: f64, odd: O, even: E) -> Self {
+        Self::stretched(freq, freq, odd, even)
+    }
+
+    /// Different number of tiles along `u` and `v`, e.g. a `Sphere`'s `u` goes twice as far as
+    /// its `v`, so twice as many tiles along `u` makes them square.
+    pub fn stretched(freq_u: f64, freq_v: f64, odd: O, even: E) -> Self {
+        Self {
+            freq_u,
+            freq_v,
+            odd,
+            even,
+        }
+    }
+}
+impl UvCheckered<Color, Color> {
+    pub fn color<T: Into<Color>, U: Into<Color>>(freq: f64, odd: T, even: U) -> Self {
+        Self::new(freq, odd.into(), even.into())
+    }
+}
+impl<O: Texture, E: Texture> Texture for UvCheckered<O, E> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        if uv_parity(coord, self.freq_u, self.freq_v) {
+            self.odd.value(coord)
+        } else {
+            self.even.value(coord)
+        }
+    }
+}
+
+/// Whether `coord` is on an odd tile of a grid with `freq_u` by `freq_v` tiles over the texture
+/// coordinates. Shared by `UvCheckered` and `Checkered` in `CheckerSpace::Uv`.
+fn uv_parity(coord: &TexCoord, freq_u: f64, freq_v: f64) -> bool {
+    let tiles = (coord.u * freq_u).floor() + (coord.v * freq_v).floor();
+    tiles.rem_euclid(2.) == 1.
+}
+
+/// Used for debugging texture coordinates. `u` is mapped to red and `v` to green.
+#[derive(Clone, Debug)]
+pub struct UvGradient {}
+impl Texture for UvGradient {
+    fn value(&self, coord: &TexCoord) -> Color {
+        Color::new(coord.u, coord.v, 0.)
+    }
+}
+
+/// How `ImageTexture` samples between the centers of its pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Use the pixel the texture coordinates fall in
+    Nearest,
+    /// Blend the four pixels around the texture coordinates. Pixels past the edges are found
+    /// using the texture's `Wrap`.
+    Bilinear,
+}
+
+/// How texture coordinates outside of `[0, 1]` are brought back into range.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wrap {
+    /// Tile the texture, so `1.25` is the same as `0.25`
+    Repeat,
+    /// Stretch the edges of the texture outwards
+    Clamp,
+    /// Tile the texture, flipping every other tile so that neighboring tiles meet at the same
+    /// edge
+    Mirror,
+}
+impl Wrap {
+    /// Brings `t` into `[0, 1]`. NaN becomes `0`.
+    /// # Example
+    /// ```
+    /// # use raytracing::material::Wrap;
+    /// assert_eq!(Wrap::Repeat.apply(1.25), 0.25);
+    /// assert_eq!(Wrap::Clamp.apply(1.25), 1.);
+    /// assert_eq!(Wrap::Mirror.apply(1.25), 0.75);
+    /// assert_eq!(Wrap::Mirror.apply(-0.25), 0.25);
+    /// ```
+    pub fn apply(self, t: f64) -> f64 {
+        if t.is_nan() {
+            return 0.;
+        }
+        match self {
+            Wrap::Repeat => t.rem_euclid(1.),
+            Wrap::Clamp => t.clamp(0., 1.),
+            Wrap::Mirror => {
+                let t = t.rem_euclid(2.);
+                if t > 1. {
+                    2. - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+
+    /// Brings the pixel index `i`, which is a whole number, into `0..len`.
+    fn index(self, i: f64, len: usize) -> usize {
+        let len = len as f64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(len),
+            Wrap::Clamp => i.clamp(0., len - 1.),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2. * len);
+                if i >= len {
+                    2. * len - 1. - i
+                } else {
+                    i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// Image wrapped over the texture coordinates, with `u` going from the left edge to the right
+/// edge and `v` from the bottom edge to the top edge. Coordinates outside of `[0, 1]` are
+/// brought back into range by `wrap`.
+///
+/// The image keeps a pyramid of mip levels, each half the size of the one before down to a
+/// single pixel, which `value_lod` looks up to average over many pixels at once.
+#[derive(Clone, Debug)]
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    /// Defaults to `Filter::Nearest`
+    pub filter: Filter,
+    /// For `u` and `v`. Defaults to `Wrap::Repeat` for both, so that the image tiles seamlessly.
+    pub wrap: (Wrap, Wrap),
+    /// Roughly how many pixels of the texture one pixel of the render covers at a distance of
+    /// one, i.e. the texture's pixels per unit length times the camera's angle per pixel. When
+    /// set, the texture is looked up at `lod = log2(distance * texel_density)`, with the
+    /// distance along the path from the camera, so that it doesn't sparkle far away. Defaults
+    /// to `None`, which always uses the full resolution.
+    pub texel_density: Option<f64>,
+    /// The full image first, followed by the downsampled ones
+    levels: Vec<MipLevel>,
+}
+impl ImageTexture {
+    /// Load an sRGB image, e.g. a PNG.
+    pub fn load(path: &Path) -> Result<Self> {
+        let image = image::open(path).with_context(|| format!("Couldn't load image {:?}", path))?;
+        Ok(image.to_rgb8().into())
+    }
+
+    /// Number of mip levels, including the full image.
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The texture averaged over about `2^lod` of its pixels. Blends the two mip levels around
+    /// `lod`, which is clamped to the available levels.
+    pub fn value_lod(&self, u: f64, v: f64, lod: f64) -> Color {
+        if self.width == 0 || self.height == 0 {
+            // Cyan, to make missing images stand out
+            return Color::new(0., 1., 1.);
+        }
+        let lod = if lod.is_nan() { 0. } else { lod };
+        let lod = lod.clamp(0., (self.levels.len() - 1) as f64);
+        let level = lod.floor();
+        let fine = self.levels[level as usize].sample(u, v, self.filter, self.wrap);
+        if lod == level {
+            fine
+        } else {
+            let coarse = self.levels[level as usize + 1].sample(u, v, self.filter, self.wrap);
+            fine.lerp(coarse, lod - level)
+        }
+    }
+}
+impl From<RgbImage> for ImageTexture {
+    fn from(image: RgbImage) -> Self {
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0;
+                let linear = |c: u8| srgb_to_linear(c as f64 / 255.);
+                Color::new(linear(r), linear(g), linear(b))
+            })
+            .collect();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            pixels,
+        }];
+        if width > 0 && height > 0 {
+            while let Some(level) = levels.last().unwrap().downsample() {
+                levels.push(level);
+            }
+        }
+        Self {
+            width,
+            height,
+            filter: Filter::Nearest,
+            wrap: (Wrap::Repeat, Wrap::Repeat),
+            texel_density: None,
+            levels,
+        }
+    }
+}
+impl Texture for ImageTexture {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let lod = match self.texel_density {
+            Some(density) => (coord.distance * density).log2().max(0.),
+            None => 0.,
+        };
+        self.value_lod(coord.u, coord.v, lod)
+    }
+}
+
+/// One level of an `ImageTexture`'s mip pyramid.
+#[derive(Clone, Debug)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    /// Linear RGB pixels in rows from top to bottom
+    pixels: Vec<Color>,
+}
+impl MipLevel {
+    fn pixel(&self, i: usize, j: usize) -> Color {
+        self.pixels[j * self.width + i]
+    }
+
+    /// Halves the size of the image by averaging each 2x2 block of pixels, or `None` once the
+    /// image is a single pixel. An odd last row or column is averaged with itself.
+    fn downsample(&self) -> Option<Self> {
+        if self.width <= 1 && self.height <= 1 {
+            return None;
+        }
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut pixels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            let (j0, j1) = (2 * j, (2 * j + 1).min(self.height - 1));
+            for i in 0..width {
+                let (i0, i1) = (2 * i, (2 * i + 1).min(self.width - 1));
+                let sum = self.pixel(i0, j0)
+                    + self.pixel(i1, j0)
+                    + self.pixel(i0, j1)
+                    + self.pixel(i1, j1);
+                pixels.push(sum / 4.);
+            }
+        }
+        Some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn sample(&self, u: f64, v: f64, filter: Filter, (wrap_u, wrap_v): (Wrap, Wrap)) -> Color {
+        let (u, v) = (
+            if u.is_nan() { 0. } else { u },
+            if v.is_nan() { 0. } else { v },
+        );
+        // Position in pixels, flipping `v` since the rows start at the top of the image
+        let x = u * self.width as f64;
+        let y = (1. - v) * self.height as f64;
+        match filter {
+            Filter::Nearest => {
+                let i = wrap_u.index(x.floor(), self.width);
+                let j = wrap_v.index(y.floor(), self.height);
+                self.pixel(i, j)
+            }
+            Filter::Bilinear => {
+                // Relative to the pixel centers
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (i0, i1) = (
+                    wrap_u.index(x0, self.width),
+                    wrap_u.index(x0 + 1., self.width),
+                );
+                let (j0, j1) = (
+                    wrap_v.index(y0, self.height),
+                    wrap_v.index(y0 + 1., self.height),
+                );
+
+                let top = self.pixel(i0, j0).lerp(self.pixel(i1, j0), tx);
+                let bottom = self.pixel(i0, j1).lerp(self.pixel(i1, j1), tx);
+                top.lerp(bottom, ty)
+            }
+        }
+    }
+}
+
+/// Tangent-space normal map of a brick wall for `NormalMapped`. Each row of bricks is offset by
+/// half a brick, and the edges of each brick are bevelled down towards the mortar.
+#[derive(Clone, Debug)]
+pub struct BrickNormals {
+    /// Number of rows of bricks over `0..1` in `v`
+    pub rows: f64,
+    /// Number of bricks in each row over `0..1` in `u`
+    pub columns: f64,
+    /// Width of the bevelled edges as a fraction of a brick's height. Bricks are assumed to be
+    /// twice as wide as they are tall.
+    pub bevel: f64,
+}
+impl BrickNormals {
+    pub fn new(rows: f64, columns: f64, bevel: f64) -> Self {
+        Self {
+            rows,
+            columns,
+            bevel,
+        }
+    }
+}
+impl Texture for BrickNormals {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let (u, v) = (coord.u, coord.v);
+        let y = v * self.rows;
+        let offset = if y.floor().rem_euclid(2.) == 1. {
+            0.5
+        } else {
+            0.
+        };
+        let x = u * self.columns + offset;
+        let (x, y) = (x - x.floor(), y - y.floor());
+
+        // Slope of the bevel, pointing away from the brick's center
+        let slope = |t: f64, bevel: f64| {
+            if t < bevel {
+                -1.
+            } else if t > 1. - bevel {
+                1.
+            } else {
+                0.
+            }
+        };
+        let normal = Vec3::normalized(Vec3::new(
+            slope(x, self.bevel / 2.),
+            slope(y, self.bevel),
+            1.,
+        ));
+        let Vec3 { x, y, z } = 0.5 * (normal + Vec3::new(1., 1., 1.));
+        Color::new(x, y, z)
+    }
+}
+
+/// Linear gradient between two colors along an `Axis`.
+/// Points whose coordinate is below `range.start` are `from`, and above `range.end` are `to`.
+/// An empty range steps straight from `from` to `to` at `range.start`.
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    pub from: Color,
+    pub to: Color,
+    pub axis: Axis,
+    pub range: Range<f64>,
+    /// Ease in and out of the colors at the ends of the range instead of changing linearly.
+    /// Off by default.
+    pub smooth: bool,
+}
+impl Gradient {
+    pub fn new<T: Into<Color>, U: Into<Color>>(
+        from: T,
+        to: U,
+        axis: Axis,
+        range: Range<f64>,
+    ) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+            axis,
+            range,
+            smooth: false,
+        }
+    }
+}
+impl Texture for Gradient {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let (start, end) = (self.range.start, self.range.end);
+        let x = coord.point[self.axis];
+        let t = if start == end {
+            if x < start {
+                0.
+            } else {
+                1.
+            }
+        } else {
+            ((x - start) / (end - start)).clamp(0., 1.)
+        };
+        let t = if self.smooth { t.smooth() } else { t };
+        self.from.lerp(self.to, t)
+    }
+}
+
+/// Interpolates the colors at the vertices of a `Triangle` using the barycentric coordinates
+/// stored in the `u` and `v` of its `Hit`.
+#[derive(Clone, Debug)]
+pub struct VertexColorTexture {
+    pub colors: [Color; 3],
+}
+impl VertexColorTexture {
+    pub fn new(c0: Color, c1: Color, c2: Color) -> Self {
+        Self {
+            colors: [c0, c1, c2],
+        }
+    }
+}
+impl Texture for VertexColorTexture {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let (u, v) = (coord.u, coord.v);
+        let [c0, c1, c2] = self.colors;
+        (1. - u - v) * c0 + u * c1 + v * c2
+    }
+}
+
+/// Scales and offsets the point and the texture coordinates before looking up `texture`, so
+/// one texture can be reused at different tilings and positions. `new` starts with no change.
+/// # Example
+/// ```
+/// # use raytracing::{Color, TexCoord, Texture, Vec3};
+/// # use raytracing::material::{Checkered, Transformed};
+/// let checker = Checkered::color(10., [0., 0., 0.], [1., 1., 1.]);
+/// let mut shifted = Transformed::new(checker.clone());
+/// shifted.offset = Vec3::new(0., 0.1, 0.);
+/// let p = Vec3::new(0.2, 0.3, 0.4);
+/// assert_eq!(
+///     shifted.value(&TexCoord::new(0., 0., p)).r,
+///     checker.value(&TexCoord::new(0., 0., p + Vec3::new(0., 0.1, 0.))).r
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Transformed<T> {
+    pub texture: T,
+    /// Multiplies the point along each axis
+    pub scale: Vec3,
+    /// Added to the point after scaling it
+    pub offset: Vec3,
+    /// Multiplies `u` and `v`
+    pub uv_scale: (f64, f64),
+    /// Added to `u` and `v` after scaling them
+    pub uv_offset: (f64, f64),
+    /// Applied to `u` and `v` after offsetting them. Defaults to `None`, which leaves them as
+    /// they are.
+    pub wrap: Option<(Wrap, Wrap)>,
+}
+impl<T> Transformed<T> {
+    pub fn new(texture: T) -> Self {
+        Self {
+            texture,
+            scale: Vec3::new(1., 1., 1.),
+            offset: Vec3::ORIGIN,
+            uv_scale: (1., 1.),
+            uv_offset: (0., 0.),
+            wrap: None,
+        }
+    }
+
+    /// Where `texture` is looked up instead of `coord`.
+    pub fn remap(&self, coord: &TexCoord) -> TexCoord {
+        let u = coord.u * self.uv_scale.0 + self.uv_offset.0;
+        let v = coord.v * self.uv_scale.1 + self.uv_offset.1;
+        let (u, v) = match self.wrap {
+            Some((wrap_u, wrap_v)) => (wrap_u.apply(u), wrap_v.apply(v)),
+            None => (u, v),
+        };
+        TexCoord {
+            u,
+            v,
+            point: coord.point * self.scale + self.offset,
+            ..*coord
+        }
+    }
+}
+impl<T: Texture> Texture for Transformed<T> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        self.texture.value(&self.remap(coord))
+    }
+}
+
+/// Blends two textures, going from `a` where the luminance of `mask` is `0` to `b` where it's
+/// `1`. Unlike `Masked`, which picks between two materials, this mixes textures within a single
+/// material, e.g. moss over rock.
+/// # Example
+/// ```
+/// # use raytracing::material::Blend;
+/// # use raytracing::{Color, TexCoord, Texture, Vec3};
+/// let rock = Color::new(0.4, 0.4, 0.4);
+/// let moss = Color::new(0.1, 0.4, 0.05);
+/// let mossy = Blend::new(rock, moss, Color::new(0.25, 0.25, 0.25));
+/// let c = mossy.value(&TexCoord::new(0., 0., Vec3::ORIGIN));
+/// assert!((c.r - 0.325).abs() < 1e-12);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Blend<A, B, M> {
+    pub a: A,
+    pub b: B,
+    pub mask: M,
+}
+impl<A, B, M> Blend<A, B, M> {
+    pub fn new(a: A, b: B, mask: M) -> Self {
+        Self { a, b, mask }
+    }
+}
+impl<A: Texture, B: Texture, M: Texture> Texture for Blend<A, B, M> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let t = self.mask.value(coord).luminance();
+        self.a.value(coord).lerp(self.b.value(coord), t)
+    }
+}
+
+/// Projects a texture onto a surface from along each axis, using the point's `(y, z)`,
+/// `(x, z)`, and `(x, y)` as the texture coordinates, and blends the three projections by how
+/// much the surface faces each axis. This textures surfaces without texture coordinates, like
+/// meshes and heightfields, without the stretching of a single projection on steep slopes.
+///
+/// The coordinates wrap around every unit, so an `ImageTexture` tiles once per unit. Wrap the
+/// `Triplanar` in a `Transformed` to scale it. A `TexCoord` made with `new` faces up, so the
+/// texture is projected from above.
+/// # Example
+/// ```
+/// # use raytracing::material::{Triplanar, UvGradient};
+/// # use raytracing::{Color, TexCoord, Texture, Vec3};
+/// let triplanar = Triplanar::new(UvGradient {}, 4.);
+/// // A wall facing x shows the texture at (y, z)
+/// let mut coord = TexCoord::new(0., 0., Vec3::new(9., 0.25, 0.5));
+/// coord.normal = Vec3::UNIT_X;
+/// let c = triplanar.value(&coord);
+/// assert_eq!((c.r, c.g), (0.25, 0.5));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Triplanar<T> {
+    pub texture: T,
+    /// Higher values narrow the blend between projections where the surface faces diagonally.
+    /// `1` blends linearly by the normal's components. Negative and NaN values are treated as
+    /// `0`, which blends all three projections equally.
+    pub sharpness: f64,
+}
+impl<T> Triplanar<T> {
+    pub fn new(texture: T, sharpness: f64) -> Self {
+        Self {
+            texture,
+            sharpness: sharpness.max(0.),
+        }
+    }
+}
+impl<T: Texture> Texture for Triplanar<T> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let point = coord.point;
+        // `max` also turns NaN into 0, so that the weights are valid for the up normal below
+        let sharpness = self.sharpness.max(0.);
+        let weights = coord.normal.abs().map(|n| n.powf(sharpness));
+        let total = weights.x + weights.y + weights.z;
+        if total == 0. || !total.is_finite() {
+            return self.value(&TexCoord {
+                normal: Vec3::UNIT_Y,
+                ..*coord
+            });
+        }
+        let lookup = |u: f64, v: f64| {
+            self.texture.value(&TexCoord {
+                u: u.rem_euclid(1.),
+                v: v.rem_euclid(1.),
+                ..*coord
+            })
+        };
+        let mut color = Color::new(0., 0., 0.);
+        if weights.x > 0. {
+            color += weights.x * lookup(point.y, point.z);
+        }
+        if weights.y > 0. {
+            color += weights.y * lookup(point.x, point.z);
+        }
+        if weights.z > 0. {
+            color += weights.z * lookup(point.x, point.y);
+        }
+        color / total
+    }
+}
+
+/// Maps the luminance of a grayscale texture, like one of the noises, through a piecewise
+/// linear ramp of colors, e.g. to turn turbulence into fire.
+#[derive(Clone, Debug)]
+pub struct ColorRamp<N> {
+    pub noise: N,
+    /// Sorted by position. Values before the first stop or after the last take their color.
+    stops: Vec<(f64, Color)>,
+}
+impl<N> ColorRamp<N> {
+    /// Fails unless there's at least one stop and the stops' positions are sorted and within
+    /// `[0, 1]`.
+    pub fn new(noise: N, stops: Vec<(f64, Color)>) -> Result<Self> {
+        ensure!(!stops.is_empty(), "A color ramp needs at least one stop");
+        for &(position, _) in &stops {
+            ensure!(
+                (0. ..=1.).contains(&position),
+                "Color ramp stop at {} isn't within [0, 1]",
+                position
+            );
+        }
+        for pair in stops.windows(2) {
+            ensure!(
+                pair[0].0 <= pair[1].0,
+                "Color ramp stops aren't sorted: {} comes before {}",
+                pair[0].0,
+                pair[1].0
+            );
+        }
+        Ok(Self { noise, stops })
+    }
+
+    pub fn stops(&self) -> &[(f64, Color)] {
+        &self.stops
+    }
+
+    /// The color at `t` along the ramp.
+    pub fn color(&self, t: f64) -> Color {
+        // The first stop past `t`
+        let next = self.stops.iter().position(|&(position, _)| position > t);
+        match next {
+            Some(0) => self.stops[0].1,
+            Some(i) => {
+                let ((from, a), (to, b)) = (self.stops[i - 1], self.stops[i]);
+                a.lerp(b, (t - from) / (to - from))
+            }
+            None => self.stops[self.stops.len() - 1].1,
+        }
+    }
+}
+impl<N: Texture> Texture for ColorRamp<N> {
+    fn value(&self, coord: &TexCoord) -> Color {
+        self.color(self.noise.value(coord).luminance())
+    }
+}
+
+/// Procedural wood made of concentric rings around an `axis` through the origin, alternating
+/// from `light` to `dark`. The rings are warped by turbulence so they wobble like grain.
+/// # Example
+/// ```
+/// # use raytracing::{Axis, Color};
+/// # use raytracing::material::{Lambertian, Wood};
+/// let oak = Wood::new(Some(1), Color::new(0.7, 0.5, 0.3), Color::new(0.4, 0.25, 0.1))
+///     .with_axis(Axis::X)
+///     .with_rings(6.)
+///     .with_distortion(0.5);
+/// let material = Lambertian::new(oak);
+/// ```
+#[derive(Debug)]
+pub struct Wood {
+    pub light: Color,
+    pub dark: Color,
+    /// Axis the tree grew along. Defaults to `Axis::Y`.
+    pub axis: Axis,
+    /// Rings per unit of distance from the axis. Defaults to `4`.
+    pub ring_freq: f64,
+    /// How far the turbulence shifts the rings, in rings. Defaults to `1`.
+    pub distortion: f64,
+    pub noise: PerlinNoise,
+}
+impl Wood {
+    /// Seeds the turbulence with `seed`, or randomly if it's `None`.
+    pub fn new<T: Into<Option<u64>>>(seed: T, light: Color, dark: Color) -> Self {
+        Self::from_rng(&mut seeded_rng(seed.into()), light, dark)
+    }
+
+    /// Seeds the turbulence from `rng`.
+    pub fn from_rng(rng: &mut CrateRng, light: Color, dark: Color) -> Self {
+        Self {
+            light,
+            dark,
+            axis: Axis::Y,
+            ring_freq: 4.,
+            distortion: 1.,
+            noise: PerlinNoise::from_rng(rng, 1.).turbulence(2., 0.5, 4),
+        }
+    }
+
+    pub fn with_axis(mut self, axis: Axis) -> Self {
+        self.axis = axis;
+        self
+    }
+
+    pub fn with_rings(mut self, ring_freq: f64) -> Self {
+        self.ring_freq = ring_freq;
+        self
+    }
+
+    pub fn with_distortion(mut self, distortion: f64) -> Self {
+        self.distortion = distortion;
+        self
+    }
+}
+impl Texture for Wood {
+    fn value(&self, coord: &TexCoord) -> Color {
+        let point = coord.point;
+        let mut across = point;
+        across[self.axis] = 0.;
+        let rings = across.norm() * self.ring_freq + self.noise.eval(point) * self.distortion;
+        self.light.lerp(self.dark, rings - rings.floor())
+    }
+}
+
+/// An rng seeded with `seed`, or from entropy if it's `None`, for seeding noises.
+fn seeded_rng(seed: Option<u64>) -> CrateRng {
+    match seed {
+        Some(seed) => CrateRng::seed_from_u64(seed),
+        None => CrateRng::from_entropy(),
+    }
+}
+
+/// A callback function used to vary a noise. Takes the point and the ray's time.
+type Callback<N> = dyn Fn(&N, Vec3, f64) -> f64 + Send + Sync;
+
+/// Common noise patterns
+pub trait NoiseAdapter: Sized {
+    /// Noise at `p` and `time`. Noises that don't change over time ignore `time`.
+    fn noise(&self, p: Vec3, time: f64) -> f64;
+    fn freq(&self) -> Vec3;
+    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>>;
+
+    fn into_arc(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+
+    #[allow(non_snake_case)]
+    /// Fractional brownian noise maker.
+    /// Replaces the noises's callback.
+    fn fBm(mut self, lacunarity: f64, gain: f64, layers: usize) -> Self {
+        assert!(layers != 0, "fBm: Can't have 0 layers.");
+        assert!(0. < gain && gain < 1., "fBm: Gain must be in range (0, 1).");
+        // Get the maxiumum possible value of `sum` for later.
+        // Equal to `layers` terms in the geometric series where "a=1, r=gain"
+        let max = (1. - gain.powi(layers as i32)) / (1. - gain);
+
+        // This callback will compute the fractal sum
+        let callback = move |noise: &Self, mut p: Vec3, mut time: f64| {
+            let mut sum = 0.;
+            let mut amplitude = 1.;
+            for _ in 0..layers {
+                sum += noise.noise(p, time) * amplitude;
+                p *= lacunarity;
+                time *= lacunarity;
+                amplitude *= gain;
+            }
+
+            // Normalize the sum to the range [0, 1]
+            sum / max
+        };
+
+        *self.callback() = Some(Box::new(callback));
+        self
+    }
+
+    /// Turbulent noise maker.
+    /// Replaces the noises's callback.
+    fn turbulence(mut self, lacunarity: f64, gain: f64, layers: usize) -> Self {
+        assert!(layers != 0, "fBm: Can't have 0 layers.");
+        assert!(0. < gain && gain < 1., "fBm: Gain must be in range (0, 1).");
+        // Get the maxiumum possible value of `sum` for later.
+        // Equal to `layers` terms in the geometric series where "a=1, r=gain"
+        let max = (1. - gain.powi(layers as i32)) / (1. - gain);
+
+        // This callback will compute the fractal sum
+        let callback = move |noise: &Self, mut p: Vec3, mut time: f64| {
+            let mut sum = 0.;
+            let mut amplitude = 1.;
+            for _ in 0..layers {
+                // Convert noise into signed noise by mapping to range [-1., 1]
+                let layer = 2. * noise.noise(p, time) - 1.;
+                // then taking its absolute value to make turbulent noise
+                sum += layer.abs() * amplitude;
+                p *= lacunarity;
+                time *= lacunarity;
+                amplitude *= gain;
+            }
+
+            // Normalize the sum to the range [0, 1]
+            sum / max
+        };
+
+        *self.callback() = Some(Box::new(callback));
+        self
+    }
+
+    /// Marbled noise.
+    /// Wraps the noise's callback.
+    /// The veins run across `z`, so the noise's frequency along `z` scales how much they're
+    /// perturbed.
+    fn marbled(mut self, (sin_freq, phase_shift): (f64, f64), noise_gain: f64) -> Self
+    where
+        Self: 'static,
+    {
+        // Take the noise's callback. If there was none, create one that does nothing.
+        let callback = self
+            .callback()
+            .take()
+            .unwrap_or_else(|| Box::new(|noise: &Self, p: Vec3, time| noise.noise(p, time)));
+
+        let gain = self.freq().z * noise_gain;
+        let marbled = move |noise: &Self, p: Vec3, time| {
+            let noise = callback(noise, p, time);
+            // Perturb/shift the phase of the sine function using the noise.
+            let noise = gain * noise + p.z;
+            // Computer and normalize sine to range [0, 1]
+            let noise = sin_freq * noise + phase_shift;
+            (noise.sin() + 1.) * 0.5
+        };
+
+        *self.callback() = Some(Box::new(marbled));
+        self
+    }
+}
+/// 3D Value Noise
+pub struct ValueNoise {
+    randoms: [f64; Self::SIZE],
+    /// The permutations table.
+    perms: [u16; Self::SIZE * 2],
+    /// Scales the point along each axis. Different frequencies stretch the noise, e.g. for
+    /// wood grain.
+    freq: Vec3,
+    callback: Option<Box<Callback<Self>>>,
+}
+impl ValueNoise {
+    const SIZE: usize = 256;
+    /// Used for calculating the modulo/euclidean remainder by 256.
+    const MASK: isize = 255;
+
+    /// Seeds the noise with `seed`, or randomly if it's `None`. Prefer `from_rng` when building
+    /// several noises, since noises with the same seed look the same.
+    pub fn new<T: Into<Option<u64>>, F: Into<Vec3>>(seed: T, freq: F) -> Self {
+        Self::from_rng(&mut seeded_rng(seed.into()), freq)
+    }
+
+    /// Seeds the noise from `rng`, so that noises built one after another differ.
+    pub fn from_rng<F: Into<Vec3>>(rng: &mut CrateRng, freq: F) -> Self {
+        let mut randoms = [0.0; Self::SIZE];
+        let mut perms = [0; Self::SIZE * 2];
+
+        // Initialize part of the permutation table and then shuffle it
+        for i in 0..Self::SIZE {
+            randoms[i] = rng.gen();
+            perms[i] = i as u16;
+        }
+
+        let index = Uniform::new(0, Self::SIZE);
+        for i in 0..Self::SIZE {
+            let j = index.sample(rng);
+            perms.swap(i, j);
+            // Initialize the rest of the table.
+            perms[i + Self::SIZE] = perms[i];
+        }
+
+        Self {
+            randoms,
+            perms,
+            freq: freq.into(),
+            callback: None,
+        }
+    }
+
+    pub fn hash(&self, x: isize, y: isize, z: isize) -> usize {
+        let perm_xy = self.perms[x as usize] + y as u16;
+        let plus_z = self.perms[perm_xy as usize] + z as u16;
+        self.perms[plus_z as usize] as usize
+    }
+
+    pub fn eval(&self, p: Vec3) -> f64 {
+        self.callback
+            .as_ref()
+            .map(|callback| callback(self, p, 0.))
+            .unwrap_or_else(|| self.noise(p))
+    }
+
+    fn noise(&self, mut p: Vec3) -> f64 {
+        p *= self.freq;
+
+        let floor_p = p.map(|f| f.floor());
+        let t = p - floor_p;
+        let smooth = t.map(|f| f.smooth());
+
+        // The 6 values that determine the cube enclosing the given point
+        // Do bitwise AND to get the euclidean remainder/modulo by 256.
+        let rx0 = floor_p.x as isize & Self::MASK;
+        let ry0 = floor_p.y as isize & Self::MASK;
+        let rz0 = floor_p.z as isize & Self::MASK;
+        let rx1 = (rx0 + 1) & Self::MASK;
+        let ry1 = (ry0 + 1) & Self::MASK;
+        let rz1 = (rz0 + 1) & Self::MASK;
+
+        // The 8 random values at the corners of said cube.
+        let c000 = self.randoms[self.hash(rx0, ry0, rz0)];
+        let c100 = self.randoms[self.hash(rx1, ry0, rz0)];
+        let c010 = self.randoms[self.hash(rx0, ry1, rz0)];
+        let c110 = self.randoms[self.hash(rx1, ry1, rz0)];
+
+        let c001 = self.randoms[self.hash(rx0, ry0, rz1)];
+        let c101 = self.randoms[self.hash(rx1, ry0, rz1)];
+        let c011 = self.randoms[self.hash(rx0, ry1, rz1)];
+        let c111 = self.randoms[self.hash(rx1, ry1, rz1)];
+
+        // lerp along X axis
+        let x00 = smooth.x.lerp(c000, c100);
+        let x10 = smooth.x.lerp(c010, c110);
+        let x01 = smooth.x.lerp(c001, c101);
+        let x11 = smooth.x.lerp(c011, c111);
+
+        // lerp along Y axis
+        let y0 = smooth.y.lerp(x00, x10);
+        let y1 = smooth.y.lerp(x01, x11);
+
+        // finally lerp along Z axis
+        smooth.z.lerp(y0, y1)
+    }
+}
+impl Debug for ValueNoise {
+    /// This struct's fields are too large to be printed.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValueNoise { .. }").finish()
+    }
+}
+impl Texture for ValueNoise {
+    fn value(&self, coord: &TexCoord) -> Color {
+        Color::default() * self.eval(coord.point)
+    }
+}
+impl NoiseAdapter for ValueNoise {
+    fn noise(&self, p: Vec3, _time: f64) -> f64 {
+        self.noise(p)
+    }
+
+    fn freq(&self) -> Vec3 {
+        self.freq
+    }
+
+    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>> {
+        &mut self.callback
+    }
+}
+
+pub struct PerlinNoise {
     perms: [u16; Self::SIZE * 2],
-    freq: f64,
+    /// Scales the point along each axis. Different frequencies stretch the noise, e.g. for
+    /// wood grain.
+    freq: Vec3,
+    callback: Option<Box<Callback<Self>>>,
+}
+impl PerlinNoise {
+    const SIZE: usize = 256;
+    /// Used for calculating the modulo/euclidean remainder by 256.
+    const MASK: isize = 255;
+
+    /// Seeds the noise with `seed`, or randomly if it's `None`. Prefer `from_rng` when building
+    /// several noises, since noises with the same seed look the same.
+    pub fn new<T: Into<Option<u64>>, F: Into<Vec3>>(seed: T, freq: F) -> Self {
+        Self::from_rng(&mut seeded_rng(seed.into()), freq)
+    }
+
+    /// Seeds the noise from `rng`, so that noises built one after another differ.
+    pub fn from_rng<F: Into<Vec3>>(rng: &mut CrateRng, freq: F) -> Self {
+        Self {
+            perms: Self::permutations(rng),
+            freq: freq.into(),
+            callback: None,
+        }
+    }
+
+    /// A shuffled table of `0..256`, repeated twice so that sums of two entries can index it.
+    fn permutations(rng: &mut CrateRng) -> [u16; Self::SIZE * 2] {
+        let mut perms = [0; Self::SIZE * 2];
+        // Initialize part of the permutation table and then shuffle it
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..Self::SIZE {
+            perms[i] = i as u16;
+        }
+
+        let index = Uniform::new(0, Self::SIZE);
+        for i in 0..Self::SIZE {
+            let j = index.sample(rng);
+            perms.swap(i, j);
+            // Initialize the rest of the table.
+            perms[i + Self::SIZE] = perms[i];
+        }
+        perms
+    }
+
+    /// Compute the dot product between a vector and one of the predefined gradients
+    pub fn dot_gradient(perm: usize, v: Vec3) -> f64 {
+        let Vec3 { x, y, z } = v;
+        // Modulo 16
+        let gradient = perm & 15;
+        match gradient {
+            // Product    // GRADIENT
+            0 => x + y,   // (1,1,0)
+            1 => -x + y,  // (-1,1,0)
+            2 => x - y,   // (1,-1,0)
+            3 => -x - y,  // (-1,-1,0)
+            4 => x + z,   // (1,0,1)
+            5 => -x + z,  // (-1,0,1)
+            6 => x - z,   // (1,0,-1)
+            7 => -x - z,  // (-1,0,-1)
+            8 => y + z,   // (0,1,1)
+            9 => -y - z,  // (0,-1,1)
+            10 => y - z,  // (0,1,-1)
+            11 => -y - z, // (0,-1,-1)
+            12 => x + y,  // (1,1,0)
+            13 => -x + y, // (-1,1,0)
+            14 => -y + z, // (0,-1,1)
+            15 => -y - z, // (0,-1,-1)
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn hash(&self, x: isize, y: isize, z: isize) -> usize {
+        let perm_xy = self.perms[x as usize] + y as u16;
+        let plus_z = self.perms[perm_xy as usize] + z as u16;
+        self.perms[plus_z as usize] as usize
+    }
+
+    pub fn eval(&self, p: Vec3) -> f64 {
+        self.callback
+            .as_ref()
+            .map(|callback| callback(self, p, 0.))
+            .unwrap_or_else(|| self.noise(p))
+    }
+
+    fn noise(&self, mut p: Vec3) -> f64 {
+        p *= self.freq;
+
+        let floor_p = p.map(|f| f.floor());
+        let t = p - floor_p;
+        let smooth = t.map(|f| f.smooth());
+
+        // The 6 values that determine the cube enclosing the given point
+        // Do bitwise AND to get the euclidean remainder/modulo by 256.
+        let rx0 = floor_p.x as isize & Self::MASK;
+        let ry0 = floor_p.y as isize & Self::MASK;
+        let rz0 = floor_p.z as isize & Self::MASK;
+        let rx1 = (rx0 + 1) & Self::MASK;
+        let ry1 = (ry0 + 1) & Self::MASK;
+        let rz1 = (rz0 + 1) & Self::MASK;
+
+        let (x0, y0, z0) = (t.x, t.y, t.z);
+        let (x1, y1, z1) = (x0 - 1., y0 - 1., z0 - 1.);
+        // The 8 vectors going from the grid points to the point P
+        let p000 = Vec3::new(x0, y0, z0);
+        let p100 = Vec3::new(x1, y0, z0);
+        let p010 = Vec3::new(x0, y1, z0);
+        let p110 = Vec3::new(x1, y1, z0);
+
+        let p001 = Vec3::new(x0, y0, z1);
+        let p101 = Vec3::new(x1, y0, z1);
+        let p011 = Vec3::new(x0, y1, z1);
+        let p111 = Vec3::new(x1, y1, z1);
+
+        // Dot product each vector with its respective gradient
+        let v000 = Self::dot_gradient(self.hash(rx0, ry0, rz0), p000);
+        let v100 = Self::dot_gradient(self.hash(rx1, ry0, rz0), p100);
+        let v010 = Self::dot_gradient(self.hash(rx0, ry1, rz0), p010);
+        let v110 = Self::dot_gradient(self.hash(rx1, ry1, rz0), p110);
+
+        let v001 = Self::dot_gradient(self.hash(rx0, ry0, rz1), p001);
+        let v101 = Self::dot_gradient(self.hash(rx1, ry0, rz1), p101);
+        let v011 = Self::dot_gradient(self.hash(rx0, ry1, rz1), p011);
+        let v111 = Self::dot_gradient(self.hash(rx1, ry1, rz1), p111);
+
+        // lerp along X axis using the dot product
+        let x00 = smooth.x.lerp(v000, v100);
+        let x10 = smooth.x.lerp(v010, v110);
+        let x01 = smooth.x.lerp(v001, v101);
+        let x11 = smooth.x.lerp(v011, v111);
+
+        // lerp along Y axis
+        let y0 = smooth.y.lerp(x00, x10);
+        let y1 = smooth.y.lerp(x01, x11);
+
+        // finally lerp along Z axis
+        let noise = smooth.z.lerp(y0, y1);
+
+        // normalize noise to range [0, 1]
+        (noise + 1.) * 0.5
+    }
+}
+impl Debug for PerlinNoise {
+    /// This struct's fields are too large to be printed.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PerlinNoise { .. }").finish()
+    }
+}
+impl Texture for PerlinNoise {
+    fn value(&self, coord: &TexCoord) -> Color {
+        Color::default() * self.eval(coord.point)
+    }
+}
+impl NoiseAdapter for PerlinNoise {
+    fn noise(&self, p: Vec3, _time: f64) -> f64 {
+        self.noise(p)
+    }
+
+    fn freq(&self) -> Vec3 {
+        self.freq
+    }
+
+    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>> {
+        &mut self.callback
+    }
+}
+
+/// 4D Perlin Noise over space and time, for textures that change during an animation or
+/// within a motion blurred frame. The time is scaled by the mean of the components of `freq`.
+/// At a fixed time, it looks like `PerlinNoise`.
+pub struct PerlinNoise4 {
+    perms: [u16; PerlinNoise::SIZE * 2],
+    /// Scales the point along each axis. Different frequencies stretch the noise, e.g. for
+    /// wood grain.
+    freq: Vec3,
     callback: Option<Box<Callback<Self>>>,
 }
-impl ValueNoise {
-    const SIZE: usize = 256;
-    /// Used for calculating the modulo/euclidean remainder by 256.
-    const MASK: isize = 255;
+impl PerlinNoise4 {
+    /// Seeds the noise with `seed`, or randomly if it's `None`. Prefer `from_rng` when building
+    /// several noises, since noises with the same seed look the same.
+    pub fn new<T: Into<Option<u64>>, F: Into<Vec3>>(seed: T, freq: F) -> Self {
+        Self::from_rng(&mut seeded_rng(seed.into()), freq)
+    }
+
+    /// Seeds the noise from `rng`, so that noises built one after another differ.
+    pub fn from_rng<F: Into<Vec3>>(rng: &mut CrateRng, freq: F) -> Self {
+        Self {
+            perms: PerlinNoise::permutations(rng),
+            freq: freq.into(),
+            callback: None,
+        }
+    }
+
+    /// Compute the dot product between a vector and one of the 32 gradients that have one
+    /// coordinate set to 0 and the others set to ±1.
+    pub fn dot_gradient(perm: usize, [x, y, z, w]: [f64; 4]) -> f64 {
+        // Modulo 32
+        let gradient = perm & 31;
+        // Bits 3 and 4 pick which coordinate is left out
+        let (a, b, c) = match gradient >> 3 {
+            0 => (y, z, w),
+            1 => (x, z, w),
+            2 => (x, y, w),
+            _ => (x, y, z),
+        };
+        // and the lower 3 bits pick the signs of the rest.
+        let sign = |bit: usize, t: f64| if gradient & bit == 0 { t } else { -t };
+        sign(1, a) + sign(2, b) + sign(4, c)
+    }
+
+    pub fn hash(&self, x: isize, y: isize, z: isize, w: isize) -> usize {
+        let perm_xy = self.perms[x as usize] + y as u16;
+        let plus_z = self.perms[perm_xy as usize] + z as u16;
+        let plus_w = self.perms[plus_z as usize] + w as u16;
+        self.perms[plus_w as usize] as usize
+    }
+
+    pub fn eval(&self, p: Vec3, time: f64) -> f64 {
+        self.callback
+            .as_ref()
+            .map(|callback| callback(self, p, time))
+            .unwrap_or_else(|| self.noise(p, time))
+    }
+
+    fn noise(&self, p: Vec3, time: f64) -> f64 {
+        let Vec3 { x, y, z } = p * self.freq;
+        let time_freq = (self.freq.x + self.freq.y + self.freq.z) / 3.;
+        let p = [x, y, z, time * time_freq];
+
+        let floor_p = p.map(|f| f.floor());
+        // The corner of the hypercube enclosing the point, modulo 256
+        let r0 = floor_p.map(|f| f as isize & PerlinNoise::MASK);
+        let mut t = [0.; 4];
+        let mut smooth = [0.; 4];
+        for i in 0..4 {
+            t[i] = p[i] - floor_p[i];
+            smooth[i] = t[i].smooth();
+        }
+
+        // Dot the vectors going from the 16 grid points to the point with their gradients.
+        // Bit `i` of a corner's index is set when it's on the far side along coordinate `i`.
+        let mut values = [0.; 16];
+        for (corner, value) in values.iter_mut().enumerate() {
+            let mut r = [0; 4];
+            let mut offset = [0.; 4];
+            for i in 0..4 {
+                let far = corner >> i & 1;
+                r[i] = (r0[i] + far as isize) & PerlinNoise::MASK;
+                offset[i] = t[i] - far as f64;
+            }
+            *value = Self::dot_gradient(self.hash(r[0], r[1], r[2], r[3]), offset);
+        }
+
+        // lerp along each coordinate in turn, halving the values left each time
+        let mut len = values.len();
+        for s in smooth.iter() {
+            len /= 2;
+            for j in 0..len {
+                values[j] = s.lerp(values[2 * j], values[2 * j + 1]);
+            }
+        }
+
+        // normalize noise to range [0, 1]
+        ((values[0] + 1.) * 0.5).clamp(0., 1.)
+    }
+}
+impl Debug for PerlinNoise4 {
+    /// This struct's fields are too large to be printed.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PerlinNoise4 { .. }").finish()
+    }
+}
+impl Texture for PerlinNoise4 {
+    fn value(&self, coord: &TexCoord) -> Color {
+        Color::default() * self.eval(coord.point, coord.time)
+    }
+}
+impl NoiseAdapter for PerlinNoise4 {
+    fn noise(&self, p: Vec3, time: f64) -> f64 {
+        self.noise(p, time)
+    }
+
+    fn freq(&self) -> Vec3 {
+        self.freq
+    }
+
+    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>> {
+        &mut self.callback
+    }
+}
+
+/// 3D Simplex Noise. Unlike `PerlinNoise`, it interpolates over tetrahedra instead of cubes,
+/// which avoids the axis-aligned artifacts visible at low frequencies.
+/// <https://weber.itn.liu.se/~stegu/simplexnoise/simplexnoise.pdf>
+pub struct SimplexNoise {
+    perms: [u16; PerlinNoise::SIZE * 2],
+    /// Scales the point along each axis. Different frequencies stretch the noise, e.g. for
+    /// wood grain.
+    freq: Vec3,
+    callback: Option<Box<Callback<Self>>>,
+}
+impl SimplexNoise {
+    /// Skews space so that the simplices line up with a grid of cubes
+    const SKEW: f64 = 1. / 3.;
+    const UNSKEW: f64 = 1. / 6.;
+    /// The middles of the edges of a cube
+    const GRADIENTS: [[f64; 3]; 12] = [
+        [1., 1., 0.],
+        [-1., 1., 0.],
+        [1., -1., 0.],
+        [-1., -1., 0.],
+        [1., 0., 1.],
+        [-1., 0., 1.],
+        [1., 0., -1.],
+        [-1., 0., -1.],
+        [0., 1., 1.],
+        [0., -1., 1.],
+        [0., 1., -1.],
+        [0., -1., -1.],
+    ];
+
+    /// Seeds the noise with `seed`, or randomly if it's `None`. Prefer `from_rng` when building
+    /// several noises, since noises with the same seed look the same.
+    pub fn new<T: Into<Option<u64>>, F: Into<Vec3>>(seed: T, freq: F) -> Self {
+        Self::from_rng(&mut seeded_rng(seed.into()), freq)
+    }
+
+    /// Seeds the noise from `rng`, so that noises built one after another differ.
+    pub fn from_rng<F: Into<Vec3>>(rng: &mut CrateRng, freq: F) -> Self {
+        Self {
+            perms: PerlinNoise::permutations(rng),
+            freq: freq.into(),
+            callback: None,
+        }
+    }
+
+    pub fn eval(&self, p: Vec3) -> f64 {
+        self.callback
+            .as_ref()
+            .map(|callback| callback(self, p, 0.))
+            .unwrap_or_else(|| self.noise(p))
+    }
+
+    fn noise(&self, p: Vec3) -> f64 {
+        // Normalize the noise to `[0, 1]`
+        ((self.signed(p) + 1.) * 0.5).clamp(0., 1.)
+    }
+
+    /// The noise before it's normalized, scaled to about `[-1, 1]`.
+    fn signed(&self, mut p: Vec3) -> f64 {
+        p *= self.freq;
+
+        // Find the cube containing the point in skewed space, and the offset from its first
+        // corner in unskewed space.
+        let skew = (p.x + p.y + p.z) * Self::SKEW;
+        let cell = (p + Vec3::new(skew, skew, skew)).map(|f| f.floor());
+        let unskew = (cell.x + cell.y + cell.z) * Self::UNSKEW;
+        let offset = p - (cell - Vec3::new(unskew, unskew, unskew));
+
+        // The cube is split into 6 tetrahedra. Find the one containing the point by walking
+        // along the axes from the largest to the smallest offset.
+        let Vec3 { x, y, z } = offset;
+        let (second, third) = if x >= y {
+            if y >= z {
+                ([1, 0, 0], [1, 1, 0])
+            } else if x >= z {
+                ([1, 0, 0], [1, 0, 1])
+            } else {
+                ([0, 0, 1], [1, 0, 1])
+            }
+        } else if y < z {
+            ([0, 0, 1], [0, 1, 1])
+        } else if x < z {
+            ([0, 1, 0], [0, 1, 1])
+        } else {
+            ([0, 1, 0], [1, 1, 0])
+        };
+
+        let mask = |f: f64| f as isize & PerlinNoise::MASK;
+        let (i, j, k) = (mask(cell.x), mask(cell.y), mask(cell.z));
+        let corners = [[0, 0, 0], second, third, [1, 1, 1]];
+        let noise: f64 = corners
+            .iter()
+            .enumerate()
+            .map(|(n, &[ci, cj, ck])| {
+                let corner = Vec3::new(ci as f64, cj as f64, ck as f64);
+                let unskew = n as f64 * Self::UNSKEW;
+                let d = offset - corner + Vec3::new(unskew, unskew, unskew);
+                let falloff = 0.6 - d.norm_squared();
+                if falloff <= 0. {
+                    return 0.;
+                }
+                let hash = self.hash(i + ci, j + cj, k + ck);
+                let gradient = Vec3::from(Self::GRADIENTS[hash % 12]);
+                falloff.powi(4) * gradient.dot(d)
+            })
+            .sum();
+
+        32. * noise
+    }
+
+    fn hash(&self, x: isize, y: isize, z: isize) -> usize {
+        let perm_z = self.perms[z as usize] as usize;
+        let perm_yz = self.perms[y as usize + perm_z] as usize;
+        self.perms[x as usize + perm_yz] as usize
+    }
+}
+impl Debug for SimplexNoise {
+    /// This struct's fields are too large to be printed.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimplexNoise { .. }").finish()
+    }
+}
+impl Texture for SimplexNoise {
+    fn value(&self, coord: &TexCoord) -> Color {
+        Color::default() * self.eval(coord.point)
+    }
+}
+impl NoiseAdapter for SimplexNoise {
+    fn noise(&self, p: Vec3, _time: f64) -> f64 {
+        self.noise(p)
+    }
+
+    fn freq(&self) -> Vec3 {
+        self.freq
+    }
+
+    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>> {
+        &mut self.callback
+    }
+}
+
+#[cfg(test)]
+mod material_test {
+    use super::*;
+    use crate::shape::{Sphere, Translate};
+    use crate::Hittable;
+
+    fn hit(material: &dyn Material) -> Hit<'_> {
+        Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, 1., true, material, 0.5, 0.5)
+    }
+
+    #[test]
+    fn oren_nayar_without_roughness_is_lambertian() {
+        let albedo = Color::new(0.7, 0.4, 0.2);
+        let (oren_nayar, lambertian) = (OrenNayar::new(albedo, 0.), Lambertian::new(albedo));
+        let ray = Ray::from([1., 2., 0.5], [-1., -2., -0.5], 0.);
+        let (hit_on, hit_l) = (hit(&oren_nayar), hit(&lambertian));
+        let (mut rng_on, mut rng_l) = (CrateRng::seed_from_u64(3), CrateRng::seed_from_u64(3));
+
+        for _ in 0..1000 {
+            let on = oren_nayar.scatter(&ray, &hit_on, &mut rng_on).unwrap();
+            let l = lambertian.scatter(&ray, &hit_l, &mut rng_l).unwrap();
+            assert_eq!(on.ray.dir, l.ray.dir);
+            assert_eq!(
+                (on.albedo.r, on.albedo.g, on.albedo.b),
+                (l.albedo.r, l.albedo.g, l.albedo.b)
+            );
+        }
+    }
+
+    #[test]
+    fn depth_debug() {
+        let depth = DepthDebug::new(1., 5.);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let mut gray = |time: f64| {
+            let hit = Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, time, true, &depth, 0.5, 0.5);
+            let ray = Ray::from([0., 2., 0.], [0., -2., 0.], 0.);
+            assert!(depth.scatter(&ray, &hit, &mut rng).is_none());
+            depth.emitted(&ray, &hit).r
+        };
+        assert_eq!(gray(0.), 1.);
+        assert_eq!(gray(1.), 0.75);
+        assert_eq!(gray(10.), 0.);
+    }
+
+    #[test]
+    fn registry_shares_materials() {
+        let mut registry = MaterialRegistry::with_defaults();
+        let glass = registry.get("glass").unwrap();
+        assert!(Arc::ptr_eq(&glass, &registry.get("glass").unwrap()));
+        assert!(registry.get("unobtainium").is_none());
+
+        // Registering a name twice fails and keeps the original.
+        let red: SharedMaterial = Arc::new(Lambertian::new(Color::new(1., 0., 0.)));
+        registry.register("glass", red.clone()).unwrap_err();
+        assert!(Arc::ptr_eq(&glass, &registry.get("glass").unwrap()));
+        registry.register("red", red.clone()).unwrap();
+        assert!(Arc::ptr_eq(&red, &registry.get("red").unwrap()));
+
+        // Shapes can hold the shared material.
+        let sphere = Sphere::from([0., 0., 0.], 1., registry.get("red").unwrap());
+        let ray = Ray::from([0., 0., -5.], [0., 0., 1.], 0.);
+        assert!(sphere.hit(&ray, &(0.0..f64::INFINITY)).is_some());
+    }
+
+    #[test]
+    fn subsurface_stays_in_bounds() {
+        let bounds = AABB::new(Vec3::new(-1., -1., -1.), Vec3::new(1., 0., 1.));
+        let wax = Subsurface::new(Color::new(0.9, 0.8, 0.6), 10., bounds);
+        let hit = hit(&wax);
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let mut rng = CrateRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let scattered = wax.scatter(&ray, &hit, &mut rng).unwrap().ray;
+            let origin = scattered.origin;
+            assert!(origin.y >= -1. && origin.y <= 0., "{:?}", origin);
+            assert_eq!((origin.x, origin.z), (0., 0.));
+            // and leaves out through the surface that was hit
+            assert!(scattered.dir.dot(hit.normal) > 0., "{:?}", scattered.dir);
+        }
+
+        // Rays leaving the object aren't scattered again
+        let back = Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, 1., false, &wax, 0.5, 0.5);
+        let scatter = wax.scatter(&ray, &back, &mut rng).unwrap();
+        assert_eq!(scatter.ray.dir, ray.dir);
+        assert_eq!(scatter.albedo.g, 1.);
+    }
+
+    #[test]
+    fn velvet_sheen_grows_at_grazing_angles() {
+        let velvet = Velvet::new(Color::new(0.5, 0.1, 0.1), Color::new(0.9, 0.9, 0.9), 2.);
+        let hit = hit(&velvet);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let albedo = |dir: [f64; 3], rng: &mut CrateRng| {
+            let ray = Ray::from([0., 1., 0.], dir, 0.);
+            velvet.scatter(&ray, &hit, rng).unwrap().albedo
+        };
+
+        let head_on = albedo([0., -1., 0.], &mut rng);
+        assert_eq!((head_on.r, head_on.g, head_on.b), (0.5, 0.1, 0.1));
+        let oblique = albedo([1., -1., 0.], &mut rng);
+        let grazing = albedo([1., -0.01, 0.], &mut rng);
+        assert!(head_on.g < oblique.g && oblique.g < grazing.g);
+
+        // A bright sheen can't reflect more than all of the light.
+        let velvet = Velvet::new(Color::new(0.5, 0.1, 0.1), Color::new(3., 3., 3.), 1.);
+        let hit = Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, 1., true, &velvet, 0.5, 0.5);
+        let ray = Ray::from([0., 1., 0.], [1., -0.01, 0.], 0.);
+        let grazing = velvet.scatter(&ray, &hit, &mut rng).unwrap().albedo;
+        assert!(grazing.r <= 1. && grazing.g <= 1. && grazing.b <= 1.);
+        assert!(grazing.g > 0.9);
+    }
+
+    #[test]
+    fn plastic_reflects_more_at_grazing_angles() {
+        let plastic = Plastic::new(Color::new(0.8, 0.1, 0.1), 0.);
+        let hit = hit(&plastic);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let mut reflectance = |dir: [f64; 3]| {
+            let ray = Ray::from([0., 1., 0.], dir, 0.);
+            let n = 10_000;
+            let reflected = (0..n)
+                .filter(|_| plastic.scatter(&ray, &hit, &mut rng).unwrap().pdf.is_none())
+                .count();
+            reflected as f64 / n as f64
+        };
+
+        // About 4% of light is reflected head on
+        let head_on = reflectance([0., -1., 0.]);
+        assert!((head_on - 0.04).abs() < 0.01, "{}", head_on);
+        let grazing = reflectance([1., -0.01, 0.]);
+        assert!(grazing > 0.9, "{}", grazing);
+
+        // The clear coat reflects like a mirror
+        let ray = Ray::from([0., 1., 0.], [1., -0.01, 0.], 0.);
+        let scatter = std::iter::repeat_with(|| plastic.scatter(&ray, &hit, &mut rng).unwrap())
+            .find(|scatter| scatter.pdf.is_none())
+            .unwrap();
+        assert_eq!(scatter.ray.dir, Vec3::normalized(Vec3::new(1., 0.01, 0.)));
+        assert_eq!((scatter.albedo.r, scatter.albedo.g), (1., 1.));
+
+        // A denser coat reflects more head on
+        let plastic = Plastic::with_ref_index(Color::new(0.8, 0.1, 0.1), 0., 2.);
+        let hit = Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, 1., true, &plastic, 0.5, 0.5);
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let n = 10_000;
+        let reflected = (0..n)
+            .filter(|_| plastic.scatter(&ray, &hit, &mut rng).unwrap().pdf.is_none())
+            .count();
+        let head_on = reflected as f64 / n as f64;
+        assert!((head_on - 1. / 9.).abs() < 0.015, "{}", head_on);
+    }
+
+    #[test]
+    fn masked_picks_by_luminance() {
+        let mask = Gradient::new([0., 0., 0.], [1., 1., 1.], Axis::X, 0.0..1.);
+        let masked = Masked::new(DiffuseLight::white(1.), DiffuseLight::white(2.), mask);
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let emitted = |x: f64| {
+            let point = Vec3::new(x, 0., 0.);
+            let hit = Hit::new(point, Vec3::UNIT_Y, 1., true, &masked, 0.5, 0.5);
+            masked.emitted(&ray, &hit).r
+        };
+        assert_eq!(emitted(0.2), 2.);
+        assert_eq!(emitted(0.45), 2.);
+        assert_eq!(emitted(0.55), 1.);
+        assert_eq!(emitted(0.8), 1.);
+    }
+
+    #[test]
+    fn metal_albedo_is_a_texture() {
+        let stripes = Gradient::new([1., 0., 0.], [0., 0., 1.], Axis::X, 0.0..1.);
+        let metal = Metal::new(stripes, 0.);
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let mut albedo = |x: f64| {
+            let point = Vec3::new(x, 0., 0.);
+            let hit = Hit::new(point, Vec3::UNIT_Y, 1., true, &metal, 0.5, 0.5);
+            metal.scatter(&ray, &hit, &mut rng).unwrap().albedo
+        };
+        let (left, right) = (albedo(0.), albedo(1.));
+        assert_eq!((left.r, left.b), (1., 0.));
+        assert_eq!((right.r, right.b), (0., 1.));
+    }
 
-    pub fn new<T: Into<Option<u64>>>(seed: T, freq: f64) -> Self {
-        let mut rng = match seed.into() {
-            Some(seed) => CrateRng::seed_from_u64(seed),
-            None => CrateRng::from_entropy(),
+    #[test]
+    fn image_texture_quadrants() {
+        // Top row is black and red, bottom row is green and white
+        let bytes = vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 255, 255, 255];
+        let texture = ImageTexture::from(RgbImage::from_raw(2, 2, bytes).unwrap());
+        let rgb = |u: f64, v: f64| {
+            let c = texture.value(&TexCoord::new(u, v, Vec3::ORIGIN));
+            [c.r, c.g, c.b]
         };
+        assert_eq!(rgb(0.25, 0.75), [0., 0., 0.]);
+        assert_eq!(rgb(0.75, 0.75), [1., 0., 0.]);
+        assert_eq!(rgb(0.25, 0.25), [0., 1., 0.]);
+        assert_eq!(rgb(0.75, 0.25), [1., 1., 1.]);
+    }
 
-        let mut randoms = [0.0; Self::SIZE];
-        let mut perms = [0; Self::SIZE * 2];
+    #[test]
+    fn image_texture_wraps() {
+        // Top row is black and red, bottom row is green and white
+        let bytes = vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 255, 255, 255];
+        let mut texture = ImageTexture::from(RgbImage::from_raw(2, 2, bytes).unwrap());
+        let rgb = |texture: &ImageTexture, u: f64, v: f64| {
+            let c = texture.value(&TexCoord::new(u, v, Vec3::ORIGIN));
+            [c.r, c.g, c.b]
+        };
+        // Repeats by default
+        assert_eq!(rgb(&texture, 1.75, -0.25), [1., 0., 0.]);
+        assert_eq!(rgb(&texture, -0.25, 1.75), [1., 0., 0.]);
+        // Mirrors back and forth
+        texture.wrap = (Wrap::Mirror, Wrap::Mirror);
+        assert_eq!(rgb(&texture, 1.25, 0.75), [1., 0., 0.]);
+        assert_eq!(rgb(&texture, -0.25, -0.25), [0., 1., 0.]);
+        assert_eq!(rgb(&texture, 2.25, 0.75), [0., 0., 0.]);
+
+        // Clamps to the edges
+        texture.wrap = (Wrap::Clamp, Wrap::Clamp);
+        let rgb = |u: f64, v: f64| rgb(&texture, u, v);
+        assert_eq!(rgb(1., 1.), [1., 0., 0.]);
+        assert_eq!(rgb(-3., 2.), [0., 0., 0.]);
+        assert_eq!(rgb(f64::NAN, f64::NAN), [0., 1., 0.]);
+    }
 
-        // Initialize part of the permutation table and then shuffle it
-        for i in 0..Self::SIZE {
-            randoms[i] = rng.gen();
-            perms[i] = i as u16;
-        }
+    #[test]
+    fn image_texture_loads_jpeg() {
+        let name = format!(
+            "raytracing_{}_image_texture_loads_jpeg.jpg",
+            std::process::id()
+        );
+        let path = std::env::temp_dir().join(name);
+        RgbImage::from_pixel(8, 8, image::Rgb([255, 0, 0]))
+            .save(&path)
+            .unwrap();
+        let texture = ImageTexture::load(&path).unwrap();
+        let c = texture.value(&TexCoord::new(0.5, 0.5, Vec3::ORIGIN));
+        assert!(c.r > 0.9 && c.g < 0.1 && c.b < 0.1, "{:?}", c);
+    }
 
-        let index = Uniform::new(0, Self::SIZE);
-        for i in 0..Self::SIZE {
-            let j = index.sample(&mut rng);
-            perms.swap(i, j);
-            // Initialize the rest of the table.
-            perms[i + Self::SIZE] = perms[i];
+    #[test]
+    fn image_texture_mip_levels_average_pixels() {
+        // A 3x2 image: the odd column is averaged with itself
+        let bytes = vec![255, 0, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 255];
+        let texture = ImageTexture::from(RgbImage::from_raw(3, 2, bytes).unwrap());
+        assert_eq!(texture.levels(), 3);
+        let rgb = |u: f64, v: f64, lod: f64| {
+            let c = texture.value_lod(u, v, lod);
+            [c.r, c.g, c.b]
+        };
+        assert_eq!(rgb(0.1, 0.9, 0.), [1., 0., 0.]);
+        // The left half of level 1 averages the first two columns
+        assert_eq!(rgb(0.1, 0.9, 1.), [0.25, 0., 0.]);
+        // and the right half averages the last column with itself
+        assert_eq!(rgb(0.9, 0.9, 1.), [0., 0., 1.]);
+        // The last level is a single pixel, and coarser levels are clamped to it
+        assert_eq!(rgb(0.1, 0.9, 2.), [0.125, 0., 0.5]);
+        assert_eq!(rgb(0.9, 0.1, 10.), [0.125, 0., 0.5]);
+        // Fractional levels blend the levels around them
+        assert_eq!(rgb(0.9, 0.9, 1.5), [0.0625, 0., 0.75]);
+    }
+
+    #[test]
+    fn blend_follows_the_mask() {
+        let a = Arc::new(UvGradient {});
+        let b = Arc::new(Color::new(0.2, 0.4, 0.8));
+        let rgb = |mask: Color| {
+            let c = Blend::new(a.clone(), b.clone(), mask).value(&TexCoord::new(
+                0.5,
+                0.25,
+                Vec3::ORIGIN,
+            ));
+            [c.r, c.g, c.b]
+        };
+        assert_eq!(rgb(Color::new(0., 0., 0.)), [0.5, 0.25, 0.]);
+        assert_eq!(rgb(Color::new(1., 1., 1.)), [0.2, 0.4, 0.8]);
+        let [r, g, b] = rgb(Color::new(0.5, 0.5, 0.5));
+        for (c, expected) in [(r, 0.35), (g, 0.325), (b, 0.4)] {
+            assert!((c - expected).abs() < 1e-12, "{} != {}", c, expected);
         }
+    }
 
-        Self {
-            randoms,
-            perms,
-            freq,
-            callback: None,
+    #[test]
+    fn triplanar_blends_projections() {
+        let triplanar = Triplanar::new(UvGradient {}, 1.);
+        let point = Vec3::new(0.25, 0.5, 1.75);
+        let rg = |normal: Vec3| {
+            let c = triplanar.value(&TexCoord {
+                normal,
+                ..TexCoord::new(0., 0., point)
+            });
+            (c.r, c.g)
+        };
+        // Looking down, the texture is projected onto (x, z), which wraps around
+        assert_eq!(rg(-Vec3::UNIT_Y), (0.25, 0.75));
+        assert_eq!(rg(Vec3::UNIT_Z), (0.25, 0.5));
+        // A diagonal surface blends (y, z) and (x, z) equally
+        let diagonal = Vec3::normalized(Vec3::new(1., 1., 0.));
+        let (r, g) = rg(diagonal);
+        assert!((r - 0.375).abs() < 1e-12 && (g - 0.75).abs() < 1e-12);
+        // Sharper blends favor the closest axis
+        let tilted = Vec3::normalized(Vec3::new(1., 2., 0.));
+        let sharp = Triplanar::new(UvGradient {}, 8.);
+        let r = sharp
+            .value(&TexCoord {
+                normal: tilted,
+                ..TexCoord::new(0., 0., point)
+            })
+            .r;
+        assert!((r - 0.25).abs() < 0.01, "{}", r);
+        // Without a normal, it's projected from above
+        assert_eq!(triplanar.value(&TexCoord::new(0., 0., point)).g, 0.75);
+
+        // Invalid sharpness blends evenly instead of never finding valid weights
+        for &sharpness in &[-1., f64::NAN] {
+            let mut even = Triplanar::new(UvGradient {}, sharpness);
+            assert_eq!(even.sharpness, 0.);
+            even.sharpness = sharpness;
+            let c = even.value(&TexCoord::new(0., 0., point));
+            assert!((c.r - 1. / 3.).abs() < 1e-12, "{:?}", c);
         }
     }
 
-    pub fn hash(&self, x: isize, y: isize, z: isize) -> usize {
-        let perm_xy = self.perms[x as usize] + y as u16;
-        let plus_z = self.perms[perm_xy as usize] + z as u16;
-        self.perms[plus_z as usize] as usize
+    #[test]
+    fn image_texture_lod_follows_the_path_length() {
+        let bytes = vec![255, 0, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 255];
+        let mut texture = ImageTexture::from(RgbImage::from_raw(3, 2, bytes).unwrap());
+        texture.texel_density = Some(0.5);
+        let lambertian = Lambertian::new(&texture);
+        let mut rng = CrateRng::seed_from_u64(0);
+        // A ray that already bounced 2 units away from the camera, and hits 2 units further
+        let mut ray = Ray::from([0., 1., 0.], [0., -0.5, 0.], 0.);
+        ray.distance = 2.;
+        let hit = Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, 4., true, &lambertian, 0.1, 0.9);
+        let albedo = lambertian.scatter(&ray, &hit, &mut rng).unwrap().albedo;
+        let rgb = |c: Color| [c.r, c.g, c.b];
+        assert_eq!(rgb(albedo), rgb(texture.value_lod(0.1, 0.9, 1.)));
+        // Right in front of the camera it's at full resolution
+        let coord = TexCoord::new(0.1, 0.9, Vec3::ORIGIN);
+        assert_eq!(
+            rgb(texture.value(&coord)),
+            rgb(texture.value_lod(0.1, 0.9, 0.))
+        );
     }
 
-    pub fn eval(&self, p: Vec3) -> f64 {
-        self.callback
-            .as_ref()
-            .map(|callback| callback(self, p))
-            .unwrap_or_else(|| self.noise(p))
+    #[test]
+    fn image_texture_filters() {
+        // Top row is black and red, bottom row is green and white
+        let bytes = vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 255, 255, 255];
+        let mut texture = ImageTexture::from(RgbImage::from_raw(2, 2, bytes).unwrap());
+        let rgb = |texture: &ImageTexture, u: f64, v: f64| {
+            let c = texture.value(&TexCoord::new(u, v, Vec3::ORIGIN));
+            [c.r, c.g, c.b]
+        };
+        // Nearest picks the pixel the coordinates fall in, even at its corner
+        assert_eq!(rgb(&texture, 0.99, 0.99), [1., 0., 0.]);
+        assert_eq!(rgb(&texture, 0.01, 0.51), [0., 0., 0.]);
+
+        texture.filter = Filter::Bilinear;
+        // Pixel centers keep their color
+        assert_eq!(rgb(&texture, 0.75, 0.75), [1., 0., 0.]);
+        // Halfway between two pixels is their average
+        assert_eq!(rgb(&texture, 0.5, 0.75), [0.5, 0., 0.]);
+        assert_eq!(rgb(&texture, 0.25, 0.5), [0., 0.5, 0.]);
+        // and the middle is the average of all four
+        assert_eq!(rgb(&texture, 0.5, 0.5), [0.5, 0.5, 0.25]);
+        // Repeating blends the opposite edges together
+        assert_eq!(rgb(&texture, 0., 0.25), [0.5, 1., 0.5]);
+        assert_eq!(rgb(&texture, 1., 0.25), [0.5, 1., 0.5]);
+        assert_eq!(rgb(&texture, 0.25, 1.), [0., 0.5, 0.]);
+        // while clamping doesn't
+        texture.wrap = (Wrap::Repeat, Wrap::Clamp);
+        assert_eq!(rgb(&texture, 0.25, 1.), [0., 0., 0.]);
     }
 
-    fn noise(&self, mut p: Vec3) -> f64 {
-        p *= self.freq;
+    #[test]
+    fn diffuse_light_emits_its_texture() {
+        // A light that's red on the left and blue on the right, e.g. a window
+        let bytes = vec![255, 0, 0, 0, 0, 255];
+        let texture = ImageTexture::from(RgbImage::from_raw(2, 1, bytes).unwrap());
+        let light = DiffuseLight::new(texture, 2.);
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let at = |u| {
+            let hit = Hit::new(Vec3::ORIGIN, Vec3::UNIT_Y, 1., true, &light, u, 0.5);
+            let Color { r, g, b } = light.emitted(&ray, &hit);
+            (r, g, b)
+        };
+        assert_eq!(at(0.25), (2., 0., 0.));
+        assert_eq!(at(0.75), (0., 0., 2.));
+    }
 
-        let floor_p = p.map(|f| f.floor());
-        let t = p - floor_p;
-        let smooth = t.map(|f| f.smooth());
+    #[test]
+    fn uv_checkered_stretched() {
+        let checker = UvCheckered::stretched(4., 2., UvGradient {}, Color::new(0., 0., 1.));
+        let color = |u: f64, v: f64| {
+            let c = checker.value(&TexCoord::new(u, v, Vec3::ORIGIN));
+            [c.r, c.g, c.b]
+        };
+        assert_eq!(color(0.1, 0.1), [0., 0., 1.]);
+        assert_eq!(color(0.3, 0.25), [0.3, 0.25, 0.]);
+        assert_eq!(color(0.3, 0.75), [0., 0., 1.]);
+        assert_eq!(color(0.6, 0.75), [0.6, 0.75, 0.]);
+    }
 
-        // The 6 values that determine the cube enclosing the given point
-        // Do bitwise AND to get the euclidean remainder/modulo by 256.
-        let rx0 = floor_p.x as isize & Self::MASK;
-        let ry0 = floor_p.y as isize & Self::MASK;
-        let rz0 = floor_p.z as isize & Self::MASK;
-        let rx1 = (rx0 + 1) & Self::MASK;
-        let ry1 = (ry0 + 1) & Self::MASK;
-        let rz1 = (rz0 + 1) & Self::MASK;
+    #[test]
+    fn gradient_clamps_and_smooths() {
+        let mut gradient = Gradient::new([0., 0., 0.], [1., 1., 1.], Axis::Y, 1.0..3.);
+        let at = |gradient: &Gradient, y: f64| {
+            gradient
+                .value(&TexCoord::new(0., 0., Vec3::new(0., y, 0.)))
+                .r
+        };
+        assert_eq!(at(&gradient, 0.), 0.);
+        assert_eq!(at(&gradient, 1.5), 0.25);
+        assert_eq!(at(&gradient, 2.), 0.5);
+        assert_eq!(at(&gradient, 5.), 1.);
+
+        gradient.smooth = true;
+        assert!(at(&gradient, 1.5) < 0.25);
+        assert_eq!(at(&gradient, 2.), 0.5);
+        assert!(at(&gradient, 2.5) > 0.75);
+        assert_eq!(at(&gradient, 5.), 1.);
+
+        // An empty range is a step instead of dividing by zero
+        let step = Gradient::new([0., 0., 0.], [1., 1., 1.], Axis::Y, 2.0..2.);
+        assert_eq!(at(&step, 1.9), 0.);
+        assert_eq!(at(&step, 2.), 1.);
+        assert_eq!(at(&step, 2.1), 1.);
+    }
 
-        // The 8 random values at the corners of said cube.
-        let c000 = self.randoms[self.hash(rx0, ry0, rz0)];
-        let c100 = self.randoms[self.hash(rx1, ry0, rz0)];
-        let c010 = self.randoms[self.hash(rx0, ry1, rz0)];
-        let c110 = self.randoms[self.hash(rx1, ry1, rz0)];
+    #[test]
+    fn simplex_noise_is_normalized() {
+        let noise = SimplexNoise::new(3, 1.);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let n = 100_000;
+        let (mut sum, mut min, mut max) = (0., f64::INFINITY, f64::NEG_INFINITY);
+        for _ in 0..n {
+            let p = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 50. - Vec3::new(25., 25., 25.);
+            // Before it's clamped, so a wrong scale shows up
+            let value = noise.signed(p);
+            sum += value;
+            min = f64::min(min, value);
+            max = f64::max(max, value);
+        }
+        // Spans most of `[-1, 1]` without going past it
+        assert!((-1. ..-0.9).contains(&min), "{}", min);
+        assert!((0.9..=1.).contains(&max), "{}", max);
+        let mean = sum / n as f64;
+        assert!(mean.abs() < 0.02, "{}", mean);
+
+        // Works with the adapters
+        let fbm = SimplexNoise::new(3, 1.).fBm(2., 0.5, 5);
+        let value = fbm.eval(Vec3::new(0.3, 1.7, -4.2));
+        assert!((0. ..=1.).contains(&value));
+    }
 
-        let c001 = self.randoms[self.hash(rx0, ry0, rz1)];
-        let c101 = self.randoms[self.hash(rx1, ry0, rz1)];
-        let c011 = self.randoms[self.hash(rx0, ry1, rz1)];
-        let c111 = self.randoms[self.hash(rx1, ry1, rz1)];
+    #[test]
+    fn offset_dir_is_never_degenerate() {
+        let normal = Vec3::UNIT_Y;
+        let dir = Vec3::normalized(Vec3::new(1., 1., 0.));
+        // Fuzz that almost cancels out the direction falls back to it
+        assert_eq!(offset_dir(dir, -0.999_999_999 * dir, normal, true), dir);
+        // Fuzz that would push the direction below the surface gets flipped
+        let fuzz = Vec3::new(0., -0.9, 0.);
+        assert_eq!(offset_dir(dir, fuzz, normal, true), dir - fuzz);
+        assert_eq!(offset_dir(-dir, fuzz, normal, false), -dir + fuzz);
+    }
 
-        // lerp along X axis
-        let x00 = smooth.x.lerp(c000, c100);
-        let x10 = smooth.x.lerp(c010, c110);
-        let x01 = smooth.x.lerp(c001, c101);
-        let x11 = smooth.x.lerp(c011, c111);
+    #[test]
+    fn checker_space() {
+        // Moving the sphere by one unit flips the sign of the sine along x
+        let at_origin = Sphere::from([0., 0., 0.], 1., DbgBlack {});
+        let moved = Translate::new(Sphere::from([0., 0., 0.], 1., DbgBlack {}), Vec3::UNIT_X);
+        let range = 0.001..f64::INFINITY;
+        let hit_origin = at_origin
+            .hit(&Ray::from([0.3, 0.4, 5.], [0., 0., -1.], 0.), &range)
+            .unwrap();
+        let hit_moved = moved
+            .hit(&Ray::from([1.3, 0.4, 5.], [0., 0., -1.], 0.), &range)
+            .unwrap();
+        let mut checker = Checkered::color(consts::PI, [0., 0., 0.], [1., 1., 1.]);
+        let color = |checker: &Checkered<Color, Color>, hit: &Hit| {
+            checker.value(&TexCoord::new(hit.u, hit.v, hit.point)).r
+        };
 
-        // lerp along Y axis
-        let y0 = smooth.y.lerp(x00, x10);
-        let y1 = smooth.y.lerp(x01, x11);
+        // The pattern shifts across the moved sphere in world space
+        assert_eq!(checker.space, CheckerSpace::World);
+        assert_ne!(color(&checker, &hit_origin), color(&checker, &hit_moved));
+        // but stays on it in uv space
+        checker.space = CheckerSpace::Uv;
+        assert_eq!(color(&checker, &hit_origin), color(&checker, &hit_moved));
+        checker.freq = 2.;
+        assert_eq!(
+            checker.value(&TexCoord::new(0.25, 0.25, Vec3::ORIGIN)).r,
+            1.
+        );
+        assert_eq!(
+            checker.value(&TexCoord::new(0.75, 0.25, Vec3::ORIGIN)).r,
+            0.
+        );
+        // and lays out its tiles like `UvCheckered`
+        let uv_checker = UvCheckered::color(2., [0., 0., 0.], [1., 1., 1.]);
+        for &(u, v) in &[(0.1, 0.1), (0.6, 0.3), (0.3, 0.9), (0.9, 0.8)] {
+            let coord = TexCoord::new(u, v, Vec3::ORIGIN);
+            assert_eq!(checker.value(&coord).r, uv_checker.value(&coord).r);
+        }
+    }
 
-        // finally lerp along Z axis
-        smooth.z.lerp(y0, y1)
+    #[test]
+    fn transformed_remaps_before_lookup() {
+        let checker = Checkered::color(3., [0., 0., 0.], [1., 1., 1.]);
+        let mut transformed = Transformed::new(checker.clone());
+        transformed.scale = Vec3::new(2., 0.5, 1.);
+        transformed.offset = Vec3::new(0.1, -0.2, 0.3);
+        let mut rng = CrateRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let p = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 4.;
+            let remapped = p * Vec3::new(2., 0.5, 1.) + Vec3::new(0.1, -0.2, 0.3);
+            assert_eq!(
+                transformed.value(&TexCoord::new(0., 0., p)).r,
+                checker.value(&TexCoord::new(0., 0., remapped)).r
+            );
+        }
+
+        let mut tiled = Transformed::new(UvGradient {});
+        tiled.uv_scale = (2., 4.);
+        tiled.uv_offset = (0.5, 0.);
+        let Color { r, g, .. } = tiled.value(&TexCoord::new(0.25, 0.125, Vec3::ORIGIN));
+        assert_eq!((r, g), (1., 0.5));
+        tiled.wrap = Some((Wrap::Repeat, Wrap::Mirror));
+        let Color { r, g, .. } = tiled.value(&TexCoord::new(0.5, 0.375, Vec3::ORIGIN));
+        assert_eq!((r, g), (0.5, 0.5));
+        // `new` doesn't change anything
+        let same = Transformed::new(UvGradient {}).value(&TexCoord::new(0.25, 0.125, Vec3::ORIGIN));
+        assert_eq!((same.r, same.g), (0.25, 0.125));
     }
-}
-impl Debug for ValueNoise {
-    /// This struct's fields are too large to be printed.
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ValueNoise { .. }").finish()
+
+    #[test]
+    fn color_ramp_interpolates_between_stops() {
+        let (black, red, white) = (
+            Color::new(0., 0., 0.),
+            Color::new(1., 0., 0.),
+            Color::new(1., 1., 1.),
+        );
+        let ramp = ColorRamp::new(
+            Color::default(),
+            vec![(0.25, black), (0.75, red), (0.75, white)],
+        )
+        .unwrap();
+        let rgb = |t| {
+            let Color { r, g, b } = ramp.color(t);
+            (r, g, b)
+        };
+        assert_eq!(rgb(0.), (0., 0., 0.));
+        assert_eq!(rgb(0.5), (0.5, 0., 0.));
+        // Stops at the same position make a hard edge
+        assert_eq!(rgb(0.75), (1., 1., 1.));
+        assert_eq!(rgb(1.), (1., 1., 1.));
+        assert_eq!(ramp.value(&TexCoord::new(0., 0., Vec3::ORIGIN)).r, 1.);
+
+        assert!(ColorRamp::new(0., vec![]).is_err());
+        assert!(ColorRamp::new(0., vec![(0.5, red), (1.5, white)]).is_err());
+        assert!(ColorRamp::new(0., vec![(0.5, red), (0.2, white)]).is_err());
     }
-}
-impl Texture for ValueNoise {
-    fn value(&self, _u: f64, _v: f64, point: Vec3) -> Color {
-        Color::default() * self.eval(point)
+
+    #[test]
+    fn wood_rings_circle_the_axis() {
+        let (light, dark) = (Color::new(1., 1., 1.), Color::new(0., 0., 0.));
+        let wood = Wood::new(3, light, dark)
+            .with_axis(Axis::Z)
+            .with_rings(2.)
+            .with_distortion(0.);
+        let at = |x, y, z| wood.value(&TexCoord::new(0., 0., Vec3::new(x, y, z))).r;
+        // Without distortion, the color only depends on the distance from the axis
+        assert_eq!(at(0.25, 0., 0.), 0.5);
+        assert_eq!(at(0.25, 0., 0.), at(0., -0.25, 7.));
+        assert_eq!(at(0.75, 0., 0.), 0.5);
+        assert_eq!(at(0., 0., 3.), 1.);
     }
-}
-impl NoiseAdapter for ValueNoise {
-    fn noise(&self, p: Vec3) -> f64 {
-        self.noise(p)
+
+    #[test]
+    fn noises_from_one_rng_differ() {
+        let points: Vec<_> = (0..20)
+            .map(|i| Vec3::new(i as f64 * 0.37, 0.5, 1.3))
+            .collect();
+        let values =
+            |noise: &PerlinNoise| points.iter().map(|&p| noise.eval(p)).collect::<Vec<_>>();
+
+        let mut rng = CrateRng::seed_from_u64(42);
+        let first = PerlinNoise::from_rng(&mut rng, 1.);
+        let second = PerlinNoise::from_rng(&mut rng, 1.);
+        assert_ne!(values(&first), values(&second));
+        // but the same stream builds the same noises
+        let mut again = CrateRng::seed_from_u64(42);
+        assert_eq!(
+            values(&first),
+            values(&PerlinNoise::from_rng(&mut again, 1.))
+        );
+        assert_eq!(
+            values(&second),
+            values(&PerlinNoise::from_rng(&mut again, 1.))
+        );
     }
 
-    fn freq(&self) -> f64 {
-        self.freq
+    #[test]
+    fn noise_freq_is_per_axis() {
+        // Without any frequency along y and z, the noise only changes along x
+        let noise = PerlinNoise::new(3, Vec3::new(2., 0., 0.));
+        let p = Vec3::new(0.3, 1.7, -4.2);
+        assert_eq!(noise.eval(p), noise.eval(p + Vec3::new(0., 5., 7.)));
+        assert_ne!(noise.eval(p), noise.eval(p + Vec3::new(0.5, 0., 0.)));
+
+        // A scalar frequency scales every axis
+        let (a, b) = (ValueNoise::new(3, 2.), ValueNoise::new(3, [2., 2., 2.]));
+        assert_eq!(a.eval(p), b.eval(p));
     }
 
-    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>> {
-        &mut self.callback
+    #[test]
+    fn perlin_noise4_changes_over_time() {
+        let noise = PerlinNoise4::new(3, 1.).fBm(2., 0.5, 5);
+        let p = Vec3::new(0.3, 1.7, -4.2);
+        let at = |time| {
+            noise
+                .value(&TexCoord {
+                    time,
+                    ..TexCoord::new(0., 0., p)
+                })
+                .r
+        };
+        assert_eq!(at(0.), at(0.));
+        assert_eq!(at(0.), noise.value(&TexCoord::new(0., 0., p)).r);
+        assert_ne!(at(0.), at(0.5));
+
+        // Textures that don't change over time ignore it
+        let perlin = PerlinNoise::new(3, 1.);
+        assert_eq!(
+            perlin
+                .value(&TexCoord {
+                    time: 0.5,
+                    ..TexCoord::new(0., 0., p)
+                })
+                .r,
+            perlin.value(&TexCoord::new(0., 0., p)).r
+        );
     }
-}
 
-pub struct PerlinNoise {
-    perms: [u16; Self::SIZE * 2],
-    freq: f64,
-    callback: Option<Box<Callback<Self>>>,
-}
-impl PerlinNoise {
-    const SIZE: usize = 256;
-    /// Used for calculating the modulo/euclidean remainder by 256.
-    const MASK: isize = 255;
+    #[test]
+    fn wrapped_perlin_noise4_changes_over_time() {
+        let noise = || PerlinNoise4::new(3, 1.).fBm(2., 0.5, 5);
+        let p = Vec3::new(0.3, 1.7, -4.2);
+        let black = Color::new(0., 0., 0.);
+        let stops = vec![(0., black), (1., Color::new(1., 1., 1.))];
+        let wrapped: Vec<Box<dyn Texture>> = vec![
+            Box::new(Checkered::new(1., noise(), noise())),
+            Box::new(UvCheckered::new(1., noise(), noise())),
+            Box::new(Transformed::new(noise())),
+            Box::new(Blend::new(noise(), black, black)),
+            Box::new(ColorRamp::new(noise(), stops).unwrap()),
+            Box::new(Triplanar::new(noise(), 1.)),
+        ];
+        for texture in &wrapped {
+            let at = |time| {
+                let coord = TexCoord {
+                    time,
+                    ..TexCoord::new(0., 0., p)
+                };
+                texture.value(&coord).r
+            };
+            assert_ne!(at(0.), at(0.5), "{:?}", texture);
+        }
 
-    pub fn new<T: Into<Option<u64>>>(seed: T, freq: f64) -> Self {
-        let mut rng = match seed.into() {
-            Some(seed) => CrateRng::seed_from_u64(seed),
-            None => CrateRng::from_entropy(),
+        // Materials pass the ray's time to their textures
+        let at = |time| {
+            let coord = TexCoord {
+                time,
+                ..TexCoord::new(0., 0., p)
+            };
+            noise().value(&coord).luminance()
         };
+        let mut masked = Masked::new(DiffuseLight::white(1.), DiffuseLight::white(2.), noise());
+        masked.threshold = (at(0.) + at(0.5)) / 2.;
+        let emitted = |time| {
+            let ray = Ray::from([0., 1., 0.], [0., -1., 0.], time);
+            let hit = Hit::new(p, Vec3::UNIT_Y, 1., true, &masked, 0., 0.);
+            masked.emitted(&ray, &hit).r
+        };
+        assert_ne!(emitted(0.), emitted(0.5));
+    }
 
-        let mut perms = [0; Self::SIZE * 2];
-        // Initialize part of the permutation table and then shuffle it
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..Self::SIZE {
-            perms[i] = i as u16;
+    #[test]
+    fn isotropic_is_uniform() {
+        let material = Isotropic::new(Color::new(0.5, 0.5, 0.5));
+        let hit = hit(&material);
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let mut rng = CrateRng::seed_from_u64(0);
+
+        let n = 100_000;
+        let mut mean = Vec3::ORIGIN;
+        // Second moments: xx, yy, zz, xy, xz, yz
+        let mut moments = [0.; 6];
+        for _ in 0..n {
+            let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+            let Vec3 { x, y, z } = Vec3::normalized(scatter.ray.dir);
+            mean += Vec3::new(x, y, z);
+            let sample = [x * x, y * y, z * z, x * y, x * z, y * z];
+            for (m, v) in moments.iter_mut().zip(&sample) {
+                *m += v;
+            }
         }
+        mean /= n as f64;
+        let moments: Vec<_> = moments.iter().map(|m| m / n as f64).collect();
 
-        let index = Uniform::new(0, Self::SIZE);
-        for i in 0..Self::SIZE {
-            let j = index.sample(&mut rng);
-            perms.swap(i, j);
-            // Initialize the rest of the table.
-            perms[i + Self::SIZE] = perms[i];
+        // The mean is 0 and the covariance is I/3 for the uniform distribution on the sphere.
+        assert!(mean.norm() < 0.01, "{:?}", mean);
+        for m in &moments[..3] {
+            assert!((m - 1. / 3.).abs() < 0.01, "{:?}", moments);
+        }
+        for m in &moments[3..] {
+            assert!(m.abs() < 0.01, "{:?}", moments);
         }
+    }
 
-        Self {
-            perms,
-            freq,
-            callback: None,
+    #[test]
+    fn normal_debug_uses_outward_normal() {
+        let material = NormalDebug {};
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let mut hit = hit(&material);
+        let Color { r, g, b } = material.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (0.5, 1., 0.5));
+
+        // A back face hit has its normal flipped towards the ray.
+        hit.normal = -Vec3::UNIT_Y;
+        hit.front_face = false;
+        let Color { r, g, b } = material.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (0.5, 1., 0.5));
+    }
+
+    #[test]
+    fn two_sided_dispatches_by_face() {
+        let material = TwoSided::new(
+            DiffuseLight::white(4.),
+            Lambertian::new(Color::new(0., 0., 0.)),
+        );
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let mut hit = hit(&material);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let Color { r, g, b } = material.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (4., 4., 4.));
+        assert!(material.scatter(&ray, &hit, &mut rng).is_none());
+
+        hit.front_face = false;
+        let Color { r, g, b } = material.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (0., 0., 0.));
+        assert!(material.scatter(&ray, &hit, &mut rng).is_some());
+    }
+
+    #[test]
+    fn dielectric_tints_only_transmitted_rays() {
+        let tint = Color::new(0.9, 0.2, 0.2);
+        let material = Dielectric::tinted(1.5, tint);
+        let hit = hit(&material);
+        let mut rng = CrateRng::seed_from_u64(5);
+        let ray = Ray::from([1., 1., 0.], [-1., -1., 0.], 0.);
+        let (mut reflected, mut refracted) = (0, 0);
+
+        for _ in 0..1000 {
+            let Scatter { albedo, ray, .. } = material.scatter(&ray, &hit, &mut rng).unwrap();
+            let Color { r, g, b } = albedo;
+            if ray.dir.dot(hit.normal) > 0. {
+                reflected += 1;
+                assert_eq!((r, g, b), (1., 1., 1.));
+            } else {
+                refracted += 1;
+                assert_eq!((r, g, b), (tint.r, tint.g, tint.b));
+            }
         }
+        assert!(reflected > 0 && refracted > 0);
     }
 
-    /// Compute the dot product between a vector and one of the predefined gradients
-    pub fn dot_gradient(perm: usize, v: Vec3) -> f64 {
-        let Vec3 { x, y, z } = v;
-        // Modulo 16
-        let gradient = perm & 15;
-        match gradient {
-            // Product    // GRADIENT
-            0 => x + y,   // (1,1,0)
-            1 => -x + y,  // (-1,1,0)
-            2 => x - y,   // (1,-1,0)
-            3 => -x - y,  // (-1,-1,0)
-            4 => x + z,   // (1,0,1)
-            5 => -x + z,  // (-1,0,1)
-            6 => x - z,   // (1,0,-1)
-            7 => -x - z,  // (-1,0,-1)
-            8 => y + z,   // (0,1,1)
-            9 => -y - z,  // (0,-1,1)
-            10 => y - z,  // (0,1,-1)
-            11 => -y - z, // (0,-1,-1)
-            12 => x + y,  // (1,1,0)
-            13 => -x + y, // (-1,1,0)
-            14 => -y + z, // (0,-1,1)
-            15 => -y - z, // (0,-1,-1)
-            _ => unreachable!(),
+    #[test]
+    fn dispersion_matches_abbe_number() {
+        let material = Dielectric::dispersive(1.5, 20.);
+        assert!((material.ref_index_at(587.6) - 1.5).abs() < 1e-12);
+        let spread = material.ref_index_at(486.1) - material.ref_index_at(656.3);
+        assert!((spread - 0.5 / 20.).abs() < 1e-12);
+        assert_eq!(Dielectric::new(1.5).ref_index_at(400.), 1.5);
+    }
+
+    #[test]
+    fn smooth_dielectric_matches_the_unfrosted_output() {
+        let material = Dielectric::frosted(1.5, 0.);
+        let mut rng = CrateRng::seed_from_u64(5);
+        let mut expected_rng = rng.clone();
+        for i in 0..1000 {
+            let mut hit = hit(&material);
+            hit.front_face = i % 2 == 0;
+            let dir = Vec3::rand_unit_sphere(&mut rng) - Vec3::UNIT_Y;
+            let ray = Ray::new(Vec3::UNIT_Y, dir, 0.);
+            let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+
+            // Dielectric's scattering before it had roughness
+            let _ = Vec3::rand_unit_sphere(&mut expected_rng);
+            let eta = if hit.front_face { 1. / 1.5 } else { 1.5 };
+            let unit_dir = Vec3::normalized(dir);
+            let cos_theta = (-unit_dir).dot(hit.normal).min(1.0);
+            let sin_theta = (1. - cos_theta.powi(2)).sqrt();
+            let expected =
+                if eta * sin_theta > 1.0 || expected_rng.gen::<f64>() < schlick(cos_theta, eta) {
+                    unit_dir.reflect_unit(hit.normal)
+                } else {
+                    unit_dir.refract_unit(hit.normal, eta)
+                };
+            assert_eq!(scatter.ray.dir, expected);
         }
+        // and used the same random numbers
+        assert_eq!(rng.gen::<u64>(), expected_rng.gen::<u64>());
     }
 
-    pub fn hash(&self, x: isize, y: isize, z: isize) -> usize {
-        let perm_xy = self.perms[x as usize] + y as u16;
-        let plus_z = self.perms[perm_xy as usize] + z as u16;
-        self.perms[plus_z as usize] as usize
+    #[test]
+    fn absorbing_dielectric_follows_beer_lambert() {
+        let material = Dielectric::absorbing(1.5, Color::new(0.5, 2., 0.));
+        let mut rng = CrateRng::seed_from_u64(1);
+        // Leaving the material after travelling `distance` inside it, with a direction that
+        // isn't normalized
+        let transmittance = |distance: f64, rng: &mut CrateRng| {
+            let mut hit = hit(&material);
+            hit.front_face = false;
+            hit.time = distance / 2.;
+            let ray = Ray::from([0., 1., 0.], [0., -2., 0.], 0.);
+            let Color { r, g, b } = material.scatter(&ray, &hit, rng).unwrap().albedo;
+            [r, g, b]
+        };
+
+        assert_eq!(
+            transmittance(1., &mut rng),
+            [(-0.5_f64).exp(), (-2_f64).exp(), 1.]
+        );
+        // Twice the distance lets through the square of the fraction
+        let (one, two) = (transmittance(1.5, &mut rng), transmittance(3., &mut rng));
+        for (one, two) in one.iter().zip(&two) {
+            assert!((one * one - two).abs() < 1e-12, "{:?} {:?}", one, two);
+        }
+        // Entering the material doesn't absorb anything yet
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let Color { r, g, b } = material
+            .scatter(&ray, &hit(&material), &mut rng)
+            .unwrap()
+            .albedo;
+        assert_eq!((r, g, b), (1., 1., 1.));
     }
 
-    pub fn eval(&self, p: Vec3) -> f64 {
-        self.callback
-            .as_ref()
-            .map(|callback| callback(self, p))
-            .unwrap_or_else(|| self.noise(p))
+    #[test]
+    fn dispersion_keeps_wavelength() {
+        let material = Dielectric::dispersive(1.5, 20.);
+        let hit = hit(&material);
+        let mut rng = CrateRng::seed_from_u64(2);
+        let ray = Ray::from([1., 1., 0.], [-1., -1., 0.], 0.);
+
+        let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+        let wavelength = scatter.ray.wavelength.unwrap();
+        assert!(Color::WAVELENGTHS.contains(&wavelength));
+        let Color { r, g, b } = scatter.albedo;
+        let expected = Color::from_wavelength(wavelength);
+        assert_eq!((r, g, b), (expected.r, expected.g, expected.b));
+
+        // A ray that already has a wavelength isn't tinted again.
+        let scatter = material.scatter(&scatter.ray, &hit, &mut rng).unwrap();
+        assert_eq!(scatter.ray.wavelength, Some(wavelength));
+        let Color { r, g, b } = scatter.albedo;
+        assert_eq!((r, g, b), (1., 1., 1.));
     }
 
-    fn noise(&self, mut p: Vec3) -> f64 {
-        p *= self.freq;
+    #[test]
+    fn dispersion_bends_blue_more_than_red() {
+        let material = Dielectric::dispersive(1.5, 20.);
+        let hit = hit(&material);
+        let mut rng = CrateRng::seed_from_u64(4);
+        let dir = Vec3::new(-1., -1., 0.);
+        let refracted = |wavelength: f64, rng: &mut CrateRng| loop {
+            let ray = Ray::spectral(Vec3::new(1., 1., 0.), dir, 0., wavelength);
+            let scatter = material.scatter(&ray, &hit, rng).unwrap();
+            assert_eq!(scatter.ray.wavelength, Some(wavelength));
+            if scatter.ray.dir.dot(hit.normal) < 0. {
+                let Color { r, g, b } = scatter.albedo;
+                assert_eq!((r, g, b), (1., 1., 1.));
+                break Vec3::normalized(scatter.ray.dir);
+            }
+        };
 
-        let floor_p = p.map(|f| f.floor());
-        let t = p - floor_p;
-        let smooth = t.map(|f| f.smooth());
+        // Blue light has a higher index of refraction, so it ends up closer to the normal.
+        let (blue, red) = (refracted(400., &mut rng), refracted(700., &mut rng));
+        assert!(-blue.dot(hit.normal) > -red.dot(hit.normal));
+    }
 
-        // The 6 values that determine the cube enclosing the given point
-        // Do bitwise AND to get the euclidean remainder/modulo by 256.
-        let rx0 = floor_p.x as isize & Self::MASK;
-        let ry0 = floor_p.y as isize & Self::MASK;
-        let rz0 = floor_p.z as isize & Self::MASK;
-        let rx1 = (rx0 + 1) & Self::MASK;
-        let ry1 = (ry0 + 1) & Self::MASK;
-        let rz1 = (rz0 + 1) & Self::MASK;
+    #[test]
+    fn dispersive_dielectric_follows_one_channel() {
+        let material = DispersiveDielectric::new(1.5, 1.5, 1.5);
+        let hit = hit(&material);
+        let mut rng = CrateRng::seed_from_u64(3);
+        let ray = Ray::from([1., 1., 0.], [-1., -1., 0.], 0.);
+
+        // Each scatter carries three times one channel, which averages out to white
+        let n = 30_000;
+        let mut sum = Color::new(0., 0., 0.);
+        for _ in 0..n {
+            let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+            let Color { r, g, b } = scatter.albedo;
+            let mut channels = [r, g, b];
+            channels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(channels, [0., 0., 3.]);
+            sum += scatter.albedo;
+        }
+        let mean = sum / n as f64;
+        for &channel in &[mean.r, mean.g, mean.b] {
+            assert!((channel - 1.).abs() < 0.03, "{:?}", mean);
+        }
 
-        let (x0, y0, z0) = (t.x, t.y, t.z);
-        let (x1, y1, z1) = (x0 - 1., y0 - 1., z0 - 1.);
-        // The 8 vectors going from the grid points to the point P
-        let p000 = Vec3::new(x0, y0, z0);
-        let p100 = Vec3::new(x1, y0, z0);
-        let p010 = Vec3::new(x0, y1, z0);
-        let p110 = Vec3::new(x1, y1, z0);
+        // A ray that already follows a channel keeps it and isn't tinted again
+        let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+        let scatter = material.scatter(&scatter.ray, &hit, &mut rng).unwrap();
+        assert!(scatter.ray.wavelength.is_some());
+        let Color { r, g, b } = scatter.albedo;
+        assert_eq!((r, g, b), (1., 1., 1.));
+    }
 
-        let p001 = Vec3::new(x0, y0, z1);
-        let p101 = Vec3::new(x1, y0, z1);
-        let p011 = Vec3::new(x0, y1, z1);
-        let p111 = Vec3::new(x1, y1, z1);
+    #[test]
+    fn dispersive_dielectric_bends_each_channel_by_its_index() {
+        let material = DispersiveDielectric::new(1.4, 1.5, 1.6);
+        let hit = hit(&material);
+        let mut rng = CrateRng::seed_from_u64(4);
+        let ray = Ray::from([1., 1., 0.], [-1., -1., 0.], 0.);
+        let (mut red, mut blue) = (None, None);
+        while red.is_none() || blue.is_none() {
+            let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+            if scatter.ray.dir.dot(hit.normal) > 0. {
+                continue;
+            }
+            let dir = Vec3::normalized(scatter.ray.dir);
+            let Color { r, b, .. } = scatter.albedo;
+            if r > 0. {
+                red = Some(dir);
+            } else if b > 0. {
+                blue = Some(dir);
+            }
+        }
+        let expected =
+            |ref_index| Vec3::normalized(ray.dir).refract_unit(hit.normal, 1. / ref_index);
+        assert_eq!(red, Some(expected(1.4)));
+        assert_eq!(blue, Some(expected(1.6)));
+    }
 
-        // Dot product each vector with its respective gradient
-        let v000 = Self::dot_gradient(self.hash(rx0, ry0, rz0), p000);
-        let v100 = Self::dot_gradient(self.hash(rx1, ry0, rz0), p100);
-        let v010 = Self::dot_gradient(self.hash(rx0, ry1, rz0), p010);
-        let v110 = Self::dot_gradient(self.hash(rx1, ry1, rz0), p110);
+    #[test]
+    fn normal_mapped_perturbs_normal() {
+        let ray = Ray::from([1., 0., -0.5], [-1., 0., 0.5], 0.);
+        let mut hit = hit(&NormalDebug {});
+        hit.normal = Vec3::UNIT_X;
+
+        // A flat normal map leaves the normal unchanged.
+        let flat = NormalMapped::new(NormalDebug {}, Color::new(0.5, 0.5, 1.));
+        let Color { r, g, b } = flat.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (1., 0.5, 0.5));
+
+        // The tangent points towards increasing u, which is clockwise around the y axis.
+        let tangent = NormalMapped::new(NormalDebug {}, Color::new(1., 0.5, 0.5));
+        let Color { r, g, b } = tangent.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (0.5, 0.5, 0.));
+    }
 
-        let v001 = Self::dot_gradient(self.hash(rx0, ry0, rz1), p001);
-        let v101 = Self::dot_gradient(self.hash(rx1, ry0, rz1), p101);
-        let v011 = Self::dot_gradient(self.hash(rx0, ry1, rz1), p011);
-        let v111 = Self::dot_gradient(self.hash(rx1, ry1, rz1), p111);
+    #[test]
+    fn brick_normals_bevel_edges() {
+        let bricks = BrickNormals::new(4., 2., 0.2);
+        let normal = |u: f64, v: f64| {
+            let Color { r, g, b } = bricks.value(&TexCoord::new(u, v, Vec3::ORIGIN));
+            (r, g, b)
+        };
+        // Flat in the middle of a brick
+        assert_eq!(normal(0.25, 0.125), (0.5, 0.5, 1.));
+        // Tilts towards the mortar at the left and top edges
+        let (r, g, _) = normal(0.01, 0.125);
+        assert!(r < 0.5 && g == 0.5);
+        let (r, g, _) = normal(0.25, 0.24);
+        assert!(r == 0.5 && g > 0.5);
+        // The next row is offset by half a brick
+        let (r, g, _) = normal(0.26, 0.375);
+        assert!(r < 0.5 && g == 0.5);
+    }
 
-        // lerp along X axis using the dot product
-        let x00 = smooth.x.lerp(v000, v100);
-        let x10 = smooth.x.lerp(v010, v110);
-        let x01 = smooth.x.lerp(v001, v101);
-        let x11 = smooth.x.lerp(v011, v111);
+    #[test]
+    fn bump_mapped_tilts_down_the_slope() {
+        let ray = Ray::from([1., 0., 0.], [-1., 0., 0.], 0.);
+        let mut hit = hit(&NormalDebug {});
+        hit.normal = Vec3::UNIT_X;
+
+        // A constant height leaves the normal unchanged.
+        let flat = BumpMapped::new(NormalDebug {}, Color::new(0.5, 0.5, 0.5), 1.);
+        let Color { r, g, b } = flat.emitted(&ray, &hit);
+        assert_eq!((r, g, b), (1., 0.5, 0.5));
+
+        // Height increases with v, which is towards +y here, so the normal tilts towards -y.
+        let ramp = Gradient::new([0., 0., 0.], [1., 1., 1.], Axis::Y, 0.0..1.);
+        let bumped = BumpMapped::new(NormalDebug {}, ramp, 0.5);
+        hit.point = Vec3::new(1., 0.5, 0.);
+        let Color { r, g, b } = bumped.emitted(&ray, &hit);
+        assert!(g < 0.5 && (b - 0.5).abs() < 1e-9 && r < 1.);
+    }
 
-        // lerp along Y axis
-        let y0 = smooth.y.lerp(x00, x10);
-        let y1 = smooth.y.lerp(x01, x11);
+    #[test]
+    fn conductor_fresnel() {
+        let conductor = Conductor::new(Color::new(0.2, 1., 1.5), Color::new(3.9, 2.4, 0.), 0.);
+        // At normal incidence, the reflectance is ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2).
+        let head_on = conductor.fresnel(1.);
+        let expected =
+            |eta: f64, k: f64| ((eta - 1.).powi(2) + k * k) / ((eta + 1.).powi(2) + k * k);
+        assert!((head_on.r - expected(0.2, 3.9)).abs() < 1e-12);
+        assert!((head_on.g - expected(1., 2.4)).abs() < 1e-12);
+        assert!((head_on.b - expected(1.5, 0.)).abs() < 1e-12);
+
+        // Every channel reflects all light at grazing angles.
+        let Color { r, g, b } = conductor.fresnel(0.);
+        assert!((r - 1.).abs() < 1e-12 && (g - 1.).abs() < 1e-12 && (b - 1.).abs() < 1e-12);
+    }
 
-        // finally lerp along Z axis
-        let noise = smooth.z.lerp(y0, y1);
+    #[test]
+    fn boxed_and_shared_materials_forward() {
+        let materials: Vec<Box<dyn Material>> = vec![
+            Box::new(DiffuseLight::white(2.)),
+            Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ];
+        let shared: Arc<dyn Material + Send> = Arc::new(DiffuseLight::white(3.));
+        let ray = Ray::from([0., 1., 0.], [0., -1., 0.], 0.);
+        let mut rng = CrateRng::seed_from_u64(0);
+
+        let emitted = |material: &dyn Material| material.emitted(&ray, &hit(material)).r;
+        assert_eq!(emitted(&materials[0]), 2.);
+        assert_eq!(emitted(&materials[1]), 0.);
+        assert_eq!(emitted(&shared), 3.);
+        assert!(materials[1]
+            .scatter(&ray, &hit(&materials[1]), &mut rng)
+            .is_some());
+    }
 
-        // normalize noise to range [0, 1]
-        (noise + 1.) * 0.5
+    #[test]
+    fn lambertian_is_cosine_weighted() {
+        // With a cosine-weighted distribution, cos^2(theta) and the azimuth are both uniform.
+        const THETA_BINS: usize = 10;
+        const PHI_BINS: usize = 8;
+        const SAMPLES: usize = 80_000;
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let hit = hit(&material);
+        let ray = Ray::from([1., 1., 0.], [-1., -1., 0.], 0.);
+        let mut rng = CrateRng::seed_from_u64(11);
+
+        let mut bins = [[0usize; PHI_BINS]; THETA_BINS];
+        for _ in 0..SAMPLES {
+            let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+            let dir = Vec3::normalized(scatter.ray.dir);
+            let pdf = scatter.pdf.unwrap();
+            assert!((pdf - material.scattering_pdf(&ray, &hit, &scatter.ray)).abs() < 1e-12);
+            assert!((pdf - dir.y * consts::FRAC_1_PI).abs() < 1e-12);
+
+            let phi = dir.z.atan2(dir.x) + consts::PI;
+            let i = ((dir.y.powi(2) * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+            let j = ((phi / (2. * consts::PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+            bins[i][j] += 1;
+        }
+
+        let expected = (SAMPLES / (THETA_BINS * PHI_BINS)) as f64;
+        let chi_squared: f64 = bins
+            .iter()
+            .flatten()
+            .map(|&count| (count as f64 - expected).powi(2) / expected)
+            .sum();
+        // 79 degrees of freedom. The critical value for p = 0.001 is about 124.8
+        assert!(chi_squared < 124.8, "chi squared: {}", chi_squared);
     }
-}
-impl Debug for PerlinNoise {
-    /// This struct's fields are too large to be printed.
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("PerlinNoise { .. }").finish()
+
+    #[test]
+    fn phong_specular_lobe_follows_mirror() {
+        let black = Color::new(0., 0., 0.);
+        let material = Phong::new(black, Color::new(0.5, 0.5, 0.5), 1000.);
+        let hit = hit(&material);
+        let ray = Ray::from([-1., 1., 0.], [1., -1., 0.], 0.);
+        let mirror = Vec3::normalized(Vec3::new(1., 1., 0.));
+        let mut rng = CrateRng::seed_from_u64(5);
+
+        for _ in 0..1000 {
+            let scatter = material.scatter(&ray, &hit, &mut rng).unwrap();
+            let pdf = material.scattering_pdf(&ray, &hit, &scatter.ray);
+            assert!((scatter.pdf.unwrap() - pdf).abs() < 1e-9 * pdf);
+            assert!(Vec3::normalized(scatter.ray.dir).dot(mirror) > 0.99);
+        }
     }
-}
-impl Texture for PerlinNoise {
-    fn value(&self, _u: f64, _v: f64, point: Vec3) -> Color {
-        Color::default() * self.eval(point)
+
+    /// Average luminance of the albedo over many scatters. Absorbed rays count as black.
+    fn mean_luminance(material: &dyn Material, ray: &Ray) -> f64 {
+        const SAMPLES: usize = 100_000;
+        let hit = hit(material);
+        let mut rng = CrateRng::seed_from_u64(17);
+        let total: f64 = (0..SAMPLES)
+            .filter_map(|_| material.scatter(ray, &hit, &mut rng))
+            .map(|scatter| scatter.albedo.luminance())
+            .sum();
+        total / SAMPLES as f64
     }
-}
-impl NoiseAdapter for PerlinNoise {
-    fn noise(&self, p: Vec3) -> f64 {
-        self.noise(p)
+
+    #[test]
+    fn pbr_rough_dielectric_is_like_lambertian() {
+        let base = Color::new(0.5, 0.5, 0.5);
+        let ray = Ray::from([1., 1., 0.], [-1., -1., 0.], 0.);
+        let mean_pbr = mean_luminance(&Pbr::new(base, 0., 1.), &ray);
+        let mean_lambertian = mean_luminance(&Lambertian::new(base), &ray);
+        assert!((mean_pbr - mean_lambertian).abs() < 0.1 * mean_lambertian);
     }
 
-    fn freq(&self) -> f64 {
-        self.freq
+    #[test]
+    fn pbr_smooth_metal_is_like_metal() {
+        let base = Color::new(0.8, 0.6, 0.2);
+        let ray = Ray::from([0.2, 1., 0.], [-0.2, -1., 0.], 0.);
+        let (pbr, metal) = (Pbr::new(base, 1., 0.), Metal::new(base, 0.));
+        let (mean_pbr, mean_metal) = (mean_luminance(&pbr, &ray), mean_luminance(&metal, &ray));
+        assert!((mean_pbr - mean_metal).abs() < 0.02 * mean_metal);
+
+        let hit = hit(&pbr);
+        let mut rng = CrateRng::seed_from_u64(0);
+        let mirror = Vec3::normalized(ray.dir.reflect(hit.normal));
+        for _ in 0..100 {
+            let scatter = pbr.scatter(&ray, &hit, &mut rng).unwrap();
+            assert!((Vec3::normalized(scatter.ray.dir) - mirror).norm() < 1e-9);
+        }
     }
 
-    fn callback(&mut self) -> &mut Option<Box<Callback<Self>>> {
-        &mut self.callback
+    #[test]
+    fn pbr_rough_metal_keeps_reflections_above_the_surface() {
+        let pbr = Pbr::new(Color::new(0.9, 0.9, 0.9), 1., 1.);
+        let hit = hit(&pbr);
+        let mut rng = CrateRng::seed_from_u64(1);
+        // At a grazing angle, many microfacets reflect below the surface
+        let ray = Ray::from([1., 0.1, 0.], [-1., -0.1, 0.], 0.);
+        for _ in 0..1000 {
+            let scatter = pbr.scatter(&ray, &hit, &mut rng).unwrap();
+            assert!(
+                scatter.ray.dir.dot(hit.normal) > 0.,
+                "{:?}",
+                scatter.ray.dir
+            );
+        }
     }
 }
@@ -0,0 +1,872 @@
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use image::GrayImage;
+
+use crate::material::VertexColorTexture;
+use crate::shape::Triangle;
+use crate::{Color, CrateRng, Hit, HitList, Hittable, Material, Ray, Vec3, AABB, BVH};
+
+/// An indexed triangle mesh.
+#[derive(Clone, Debug, Default)]
+pub struct TriangleMesh {
+    pub vertices: Vec<Vec3>,
+    /// Per-vertex normals, if the mesh provided them.
+    pub normals: Option<Vec<Vec3>>,
+    /// Per-vertex colors, if the mesh provided them.
+    pub colors: Option<Vec<Color>>,
+    /// Indices into `vertices` for each triangle
+    pub faces: Vec<[usize; 3]>,
+    /// Whether the created triangles ignore hits on their back face. Off by default.
+    pub cull_backfaces: bool,
+}
+impl TriangleMesh {
+    /// Load a mesh from an ASCII or binary little endian PLY file.
+    pub fn load_ply<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("Couldn't read PLY file {:?}", path))?;
+        Self::parse_ply(&bytes).with_context(|| format!("Invalid PLY file {:?}", path))
+    }
+
+    /// Parse a mesh from the contents of an ASCII or binary little endian PLY file.
+    pub fn parse_ply(bytes: &[u8]) -> Result<Self> {
+        let (header, body) = ply::Header::parse(bytes)?;
+        header.read_mesh(body)
+    }
+
+    /// Load a mesh from a Wavefront OBJ file. Vertex normals (`vn`) are used for smooth shading
+    /// if every face has them. Texture coordinates, groups, and materials are ignored.
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read OBJ file {:?}", path))?;
+        Self::parse_obj(&text).with_context(|| format!("Invalid OBJ file {:?}", path))
+    }
+
+    /// Parse a mesh from the contents of a Wavefront OBJ file.
+    pub fn parse_obj(text: &str) -> Result<Self> {
+        obj::parse(text)
+    }
+
+    /// Create the triangles of the mesh, all sharing the same material. They're smooth shaded
+    /// if the mesh has normals.
+    pub fn triangles<M: Material + Send + 'static>(&self, material: M) -> HitList {
+        let material = Arc::new(material);
+        let mut list = HitList::new();
+        for face in &self.faces {
+            let [v0, v1, v2] = self.face_vertices(face);
+            let triangle = Triangle::new(v0, v1, v2, material.clone());
+            list.push(self.finish(triangle, face));
+        }
+        list
+    }
+
+    /// Create the triangles of the mesh, all sharing the same material, in a BVH.
+    pub fn bvh<M: Material + Send + 'static>(&self, material: M, rng: &mut CrateRng) -> BVH {
+        self.triangles(material).into_bvh(&(0.0..1.), rng)
+    }
+
+    /// Create the triangles of the mesh, each with a material made from its vertex colors.
+    pub fn colored_triangles<M, F>(&self, mut material: F) -> Result<HitList>
+    where
+        M: Material + 'static,
+        F: FnMut(VertexColorTexture) -> M,
+    {
+        let colors = self
+            .colors
+            .as_ref()
+            .ok_or_else(|| anyhow!("Mesh doesn't have vertex colors."))?;
+
+        let mut list = HitList::new();
+        for face in &self.faces {
+            let [v0, v1, v2] = self.face_vertices(face);
+            let [c0, c1, c2] = [colors[face[0]], colors[face[1]], colors[face[2]]];
+            let texture = VertexColorTexture::new(c0, c1, c2);
+            let triangle = Triangle::new(v0, v1, v2, material(texture));
+            list.push(self.finish(triangle, face));
+        }
+        Ok(list)
+    }
+
+    /// Sets each vertex's normal to the average of the normals of the faces around it,
+    /// weighted by their area, for smooth shading.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vec3::ORIGIN; self.vertices.len()];
+        for face in &self.faces {
+            let [v0, v1, v2] = self.face_vertices(face);
+            // The cross product's length is twice the face's area
+            let normal = (v1 - v0).cross(v2 - v0);
+            for &i in face {
+                normals[i] += normal;
+            }
+        }
+        for normal in &mut normals {
+            // Vertices that aren't part of a face don't need a normal
+            *normal = Vec3::checked_normalized(*normal).unwrap_or(Vec3::UNIT_Y);
+        }
+        self.normals = Some(normals);
+    }
+
+    /// Applies the mesh's settings to one of its triangles.
+    fn finish<M>(&self, triangle: Triangle<M>, face: &[usize; 3]) -> Triangle<M> {
+        let triangle = triangle.cull_backfaces(self.cull_backfaces);
+        match &self.normals {
+            Some(normals) => {
+                triangle.with_normals([normals[face[0]], normals[face[1]], normals[face[2]]])
+            }
+            None => triangle,
+        }
+    }
+
+    fn face_vertices(&self, face: &[usize; 3]) -> [Vec3; 3] {
+        [
+            self.vertices[face[0]],
+            self.vertices[face[1]],
+            self.vertices[face[2]],
+        ]
+    }
+}
+
+/// Load a PLY file (see `TriangleMesh::load_ply`) as a BVH of triangles sharing `material`.
+pub fn load_ply<P, M>(path: P, material: M, rng: &mut CrateRng) -> Result<BVH>
+where
+    P: AsRef<Path>,
+    M: Material + Send + 'static,
+{
+    Ok(TriangleMesh::load_ply(path)?.bvh(material, rng))
+}
+
+/// Load an OBJ file (see `TriangleMesh::load_obj`) as a BVH of triangles sharing `material`.
+pub fn load_obj<P, M>(path: P, material: M, rng: &mut CrateRng) -> Result<BVH>
+where
+    P: AsRef<Path>,
+    M: Material + Send + 'static,
+{
+    Ok(TriangleMesh::load_obj(path)?.bvh(material, rng))
+}
+
+/// Terrain made from a grayscale image. Each pixel is a vertex of a grid in the XZ plane,
+/// raised by its brightness times `height`. The grid is centered on the origin, with the top
+/// of the image towards `-z`, and is smooth shaded.
+#[derive(Debug)]
+pub struct Heightfield {
+    bvh: BVH,
+}
+impl Heightfield {
+    /// Load the heights from an image file, e.g. a PNG.
+    pub fn load<P, M>(
+        path: P,
+        spacing: (f64, f64),
+        height: f64,
+        material: M,
+        rng: &mut CrateRng,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        M: Material + Send + 'static,
+    {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .with_context(|| format!("Couldn't load heightmap {:?}", path))?
+            .to_luma8();
+        Self::new(&image, spacing, height, material, rng)
+            .with_context(|| format!("Invalid heightmap {:?}", path))
+    }
+
+    /// `spacing` is the distance between neighboring pixels along x and z.
+    pub fn new<M: Material + Send + 'static>(
+        image: &GrayImage,
+        spacing: (f64, f64),
+        height: f64,
+        material: M,
+        rng: &mut CrateRng,
+    ) -> Result<Self> {
+        let mesh = Self::mesh(image, spacing, height)?;
+        Ok(Self {
+            bvh: mesh.bvh(material, rng),
+        })
+    }
+
+    /// The smooth shaded grid of triangles making up the terrain.
+    pub fn mesh(image: &GrayImage, (dx, dz): (f64, f64), height: f64) -> Result<TriangleMesh> {
+        let (width, depth) = (image.width() as usize, image.height() as usize);
+        ensure!(
+            width >= 2 && depth >= 2,
+            "A heightmap must be at least 2x2 pixels, but it's {}x{}",
+            width,
+            depth
+        );
+
+        let mut mesh = TriangleMesh::default();
+        let (x0, z0) = (-dx * (width - 1) as f64 / 2., -dz * (depth - 1) as f64 / 2.);
+        for (i, j, pixel) in image.enumerate_pixels() {
+            let y = pixel.0[0] as f64 / 255. * height;
+            mesh.vertices
+                .push(Vec3::new(x0 + dx * i as f64, y, z0 + dz * j as f64));
+        }
+        // Two triangles for each square of the grid, wound counterclockwise from above
+        let index = |i: usize, j: usize| j * width + i;
+        for j in 0..depth - 1 {
+            for i in 0..width - 1 {
+                let (a, b) = (index(i, j), index(i + 1, j));
+                let (c, d) = (index(i, j + 1), index(i + 1, j + 1));
+                mesh.faces.push([a, c, b]);
+                mesh.faces.push([b, c, d]);
+            }
+        }
+        mesh.compute_normals();
+        Ok(mesh)
+    }
+}
+impl Hittable for Heightfield {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        self.bvh.hit(ray, hit_time)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        self.bvh.bounding_box(shutter_time)
+    }
+}
+
+mod obj {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    pub(super) fn parse(text: &str) -> Result<TriangleMesh> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        // Each face as indices into `positions` and `normals`
+        let mut polygons: Vec<Vec<(usize, Option<usize>)>> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_ascii_whitespace();
+            let parsed = match words.next() {
+                Some("v") => parse_vec3(words).map(|v| positions.push(v)),
+                Some("vn") => parse_vec3(words).map(|n| normals.push(n)),
+                Some("f") => words
+                    .map(|corner| parse_corner(corner, positions.len(), normals.len()))
+                    .collect::<Result<Vec<_>>>()
+                    .and_then(|polygon| {
+                        ensure!(polygon.len() >= 3, "A face needs at least 3 vertices");
+                        polygons.push(polygon);
+                        Ok(())
+                    }),
+                _ => Ok(()),
+            };
+            parsed.with_context(|| format!("Line {}: \"{}\"", number + 1, line.trim()))?;
+        }
+
+        let mut mesh = TriangleMesh::default();
+        let smooth = polygons
+            .iter()
+            .flatten()
+            .all(|(_, normal)| normal.is_some());
+        if smooth {
+            // OBJ gives each corner of a face its own normal, so a vertex is created for each
+            // distinct pair of position and normal.
+            let mut vertices = HashMap::new();
+            let mut vertex_normals = Vec::new();
+            for polygon in &mut polygons {
+                for corner in polygon.iter_mut() {
+                    let next = mesh.vertices.len();
+                    let index = *vertices.entry(*corner).or_insert(next);
+                    if index == next {
+                        mesh.vertices.push(positions[corner.0]);
+                        vertex_normals.push(Vec3::normalized(normals[corner.1.unwrap()]));
+                    }
+                    corner.0 = index;
+                }
+            }
+            mesh.normals = Some(vertex_normals);
+        } else {
+            mesh.vertices = positions;
+        }
+        for polygon in &polygons {
+            // Triangulate as a fan around the first corner
+            for pair in polygon[1..].windows(2) {
+                mesh.faces.push([polygon[0].0, pair[0].0, pair[1].0]);
+            }
+        }
+        Ok(mesh)
+    }
+
+    fn parse_vec3<'a, I: Iterator<Item = &'a str>>(words: I) -> Result<Vec3> {
+        let coords = words
+            .take(3)
+            .map(|word| {
+                word.parse()
+                    .with_context(|| format!("Invalid number \"{}\"", word))
+            })
+            .collect::<Result<Vec<f64>>>()?;
+        ensure!(coords.len() == 3, "Expected 3 coordinates");
+        Ok(Vec3::new(coords[0], coords[1], coords[2]))
+    }
+
+    /// Parses a face's corner, written as `v`, `v/vt`, `v//vn`, or `v/vt/vn`.
+    fn parse_corner(
+        corner: &str,
+        positions: usize,
+        normals: usize,
+    ) -> Result<(usize, Option<usize>)> {
+        let mut indices = corner.split('/');
+        let position = resolve(indices.next().unwrap_or(""), positions)?;
+        let normal = match indices.nth(1) {
+            Some(index) if !index.is_empty() => Some(resolve(index, normals)?),
+            _ => None,
+        };
+        Ok((position, normal))
+    }
+
+    /// Converts a 1-based index, or a negative one counting back from the end, to a 0-based one.
+    fn resolve(index: &str, len: usize) -> Result<usize> {
+        let index: i64 = index
+            .parse()
+            .with_context(|| format!("Invalid index \"{}\"", index))?;
+        let resolved = if index < 0 {
+            len as i64 + index
+        } else {
+            index - 1
+        };
+        ensure!(
+            (0..len as i64).contains(&resolved),
+            "Index {} is out of bounds",
+            index
+        );
+        Ok(resolved as usize)
+    }
+}
+
+mod ply {
+    use std::str::{self, SplitAsciiWhitespace};
+
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Format {
+        Ascii,
+        BinaryLittleEndian,
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    enum Scalar {
+        I8,
+        U8,
+        I16,
+        U16,
+        I32,
+        U32,
+        F32,
+        F64,
+    }
+    impl Scalar {
+        fn parse(s: &str) -> Result<Self> {
+            use Scalar::*;
+            Ok(match s {
+                "char" | "int8" => I8,
+                "uchar" | "uint8" => U8,
+                "short" | "int16" => I16,
+                "ushort" | "uint16" => U16,
+                "int" | "int32" => I32,
+                "uint" | "uint32" => U32,
+                "float" | "float32" => F32,
+                "double" | "float64" => F64,
+                _ => bail!("Unknown property type \"{}\"", s),
+            })
+        }
+
+        fn size(self) -> usize {
+            use Scalar::*;
+            match self {
+                I8 | U8 => 1,
+                I16 | U16 => 2,
+                I32 | U32 | F32 => 4,
+                F64 => 8,
+            }
+        }
+
+        /// The value that maps an integer color channel to `1.0`.
+        fn color_max(self) -> f64 {
+            use Scalar::*;
+            match self {
+                I8 => i8::MAX as f64,
+                U8 => u8::MAX as f64,
+                I16 => i16::MAX as f64,
+                U16 => u16::MAX as f64,
+                I32 => i32::MAX as f64,
+                U32 => u32::MAX as f64,
+                F32 | F64 => 1.,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    enum Kind {
+        Scalar(Scalar),
+        List { count: Scalar, item: Scalar },
+    }
+
+    #[derive(Debug)]
+    struct Property {
+        name: String,
+        kind: Kind,
+    }
+
+    #[derive(Debug)]
+    struct Element {
+        name: String,
+        count: usize,
+        properties: Vec<Property>,
+    }
+
+    #[derive(Debug)]
+    pub(super) struct Header {
+        format: Format,
+        elements: Vec<Element>,
+    }
+    impl Header {
+        /// Parses the header and returns it along with the rest of the file.
+        pub(super) fn parse(bytes: &[u8]) -> Result<(Self, &[u8])> {
+            const END: &[u8] = b"end_header";
+            let end = bytes
+                .windows(END.len())
+                .position(|w| w == END)
+                .ok_or_else(|| anyhow!("Missing \"end_header\""))?;
+            // The body starts after the newline that ends the header.
+            let body_start = bytes[end..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |i| end + i + 1);
+            let text = str::from_utf8(&bytes[..end]).context("Header isn't valid UTF-8")?;
+
+            let mut lines = text.lines().map(str::trim);
+            ensure!(lines.next() == Some("ply"), "Missing \"ply\" magic number");
+
+            let mut format = None;
+            let mut elements: Vec<Element> = Vec::new();
+            for line in lines {
+                let words: Vec<_> = line.split_ascii_whitespace().collect();
+                match words.as_slice() {
+                    [] | ["comment", ..] | ["obj_info", ..] => {}
+                    ["format", fmt, "1.0"] => {
+                        format = Some(match *fmt {
+                            "ascii" => Format::Ascii,
+                            "binary_little_endian" => Format::BinaryLittleEndian,
+                            _ => bail!("Unsupported format \"{}\"", fmt),
+                        });
+                    }
+                    ["element", name, count] => elements.push(Element {
+                        name: name.to_string(),
+                        count: count
+                            .parse()
+                            .with_context(|| format!("Invalid count for element \"{}\"", name))?,
+                        properties: Vec::new(),
+                    }),
+                    ["property", rest @ ..] => {
+                        let element = elements.last_mut().ok_or_else(|| {
+                            anyhow!("Property declared before any element: \"{}\"", line)
+                        })?;
+                        let property = match rest {
+                            ["list", count, item, name] => Property {
+                                name: name.to_string(),
+                                kind: Kind::List {
+                                    count: Scalar::parse(count)?,
+                                    item: Scalar::parse(item)?,
+                                },
+                            },
+                            [ty, name] => Property {
+                                name: name.to_string(),
+                                kind: Kind::Scalar(Scalar::parse(ty)?),
+                            },
+                            _ => bail!("Malformed property: \"{}\"", line),
+                        };
+                        element.properties.push(property);
+                    }
+                    _ => bail!("Malformed header line: \"{}\"", line),
+                }
+            }
+
+            let format = format.ok_or_else(|| anyhow!("Missing format"))?;
+            Ok((Self { format, elements }, &bytes[body_start..]))
+        }
+
+        pub(super) fn read_mesh(&self, body: &[u8]) -> Result<TriangleMesh> {
+            let mut body = match self.format {
+                Format::Ascii => {
+                    let text = str::from_utf8(body).context("Body isn't valid UTF-8")?;
+                    Body::Ascii(text.split_ascii_whitespace())
+                }
+                Format::BinaryLittleEndian => Body::Binary(body),
+            };
+
+            let mut mesh = TriangleMesh::default();
+            for element in &self.elements {
+                match element.name.as_str() {
+                    "vertex" => read_vertices(element, &mut body, &mut mesh)?,
+                    "face" => read_faces(element, &mut body, &mut mesh)?,
+                    _ => skip(element, &mut body)?,
+                }
+            }
+
+            let len = mesh.vertices.len();
+            for face in &mesh.faces {
+                if let Some(i) = face.iter().find(|&&i| i >= len) {
+                    bail!(
+                        "Element \"face\" has vertex index {} but there are {} vertices",
+                        i,
+                        len
+                    );
+                }
+            }
+            Ok(mesh)
+        }
+    }
+
+    enum Body<'a> {
+        Ascii(SplitAsciiWhitespace<'a>),
+        Binary(&'a [u8]),
+    }
+    impl Body<'_> {
+        fn read(&mut self, ty: Scalar) -> Result<f64> {
+            match self {
+                Body::Ascii(words) => {
+                    let word = words
+                        .next()
+                        .ok_or_else(|| anyhow!("Unexpected end of file"))?;
+                    word.parse::<f64>()
+                        .with_context(|| format!("\"{}\" isn't a number", word))
+                }
+                Body::Binary(bytes) => {
+                    ensure!(bytes.len() >= ty.size(), "Unexpected end of file");
+                    let (b, rest) = bytes.split_at(ty.size());
+                    *bytes = rest;
+                    // The slice is exactly the right length for each conversion.
+                    Ok(match ty {
+                        Scalar::I8 => b[0] as i8 as f64,
+                        Scalar::U8 => b[0] as f64,
+                        Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                        Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                        Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        Scalar::F64 => {
+                            f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+                        }
+                    })
+                }
+            }
+        }
+
+        /// Read the values of a property, which is a single value unless it's a list.
+        fn read_property(&mut self, property: &Property) -> Result<Vec<f64>> {
+            match property.kind {
+                Kind::Scalar(ty) => Ok(vec![self.read(ty)?]),
+                Kind::List { count, item } => {
+                    let count = self.read(count)?;
+                    ensure!(count >= 0., "List length is negative");
+                    (0..count as usize).map(|_| self.read(item)).collect()
+                }
+            }
+        }
+    }
+
+    fn context(element: &Element, property: &Property, index: usize) -> String {
+        format!(
+            "Couldn't read property \"{}\" of element \"{}\" #{}",
+            property.name, element.name, index
+        )
+    }
+
+    fn read_vertices(element: &Element, body: &mut Body, mesh: &mut TriangleMesh) -> Result<()> {
+        let has = |name| element.properties.iter().any(|p| p.name == name);
+        for name in &["x", "y", "z"] {
+            ensure!(
+                has(*name),
+                "Element \"vertex\" is missing property \"{}\"",
+                name
+            );
+        }
+        let has_normals = has("nx") && has("ny") && has("nz");
+        let has_colors = has("red") && has("green") && has("blue");
+
+        let mut normals = Vec::new();
+        let mut colors = Vec::new();
+        for i in 0..element.count {
+            let (mut position, mut normal, mut color) = ([0.; 3], [0.; 3], [0.; 3]);
+            for property in &element.properties {
+                let values = body
+                    .read_property(property)
+                    .with_context(|| context(element, property, i))?;
+                let (value, ty) = match (&property.kind, values.as_slice()) {
+                    (Kind::Scalar(ty), &[value]) => (value, *ty),
+                    // Lists aren't part of a vertex.
+                    _ => continue,
+                };
+
+                match property.name.as_str() {
+                    "x" => position[0] = value,
+                    "y" => position[1] = value,
+                    "z" => position[2] = value,
+                    "nx" => normal[0] = value,
+                    "ny" => normal[1] = value,
+                    "nz" => normal[2] = value,
+                    "red" => color[0] = value / ty.color_max(),
+                    "green" => color[1] = value / ty.color_max(),
+                    "blue" => color[2] = value / ty.color_max(),
+                    _ => {}
+                }
+            }
+
+            mesh.vertices.push(position.into());
+            if has_normals {
+                normals.push(normal.into());
+            }
+            if has_colors {
+                colors.push(color.into());
+            }
+        }
+
+        if has_normals {
+            mesh.normals = Some(normals);
+        }
+        if has_colors {
+            mesh.colors = Some(colors);
+        }
+        Ok(())
+    }
+
+    fn read_faces(element: &Element, body: &mut Body, mesh: &mut TriangleMesh) -> Result<()> {
+        for i in 0..element.count {
+            for property in &element.properties {
+                let values = body
+                    .read_property(property)
+                    .with_context(|| context(element, property, i))?;
+                if !matches!(property.name.as_str(), "vertex_indices" | "vertex_index") {
+                    continue;
+                }
+
+                ensure!(
+                    values.len() >= 3,
+                    "{}: A face needs at least 3 vertices",
+                    context(element, property, i)
+                );
+                let mut indices = Vec::with_capacity(values.len());
+                for &v in &values {
+                    ensure!(
+                        v >= 0. && v.fract() == 0.,
+                        "{}: Invalid vertex index {}",
+                        context(element, property, i),
+                        v
+                    );
+                    indices.push(v as usize);
+                }
+                // Triangulate polygons as a fan around the first vertex.
+                for pair in indices[1..].windows(2) {
+                    mesh.faces.push([indices[0], pair[0], pair[1]]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn skip(element: &Element, body: &mut Body) -> Result<()> {
+        for i in 0..element.count {
+            for property in &element.properties {
+                body.read_property(property)
+                    .with_context(|| context(element, property, i))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mesh_test {
+    use super::*;
+    use crate::material::DbgBlack;
+    use rand::SeedableRng;
+
+    const ASCII: &str = "ply
+format ascii 1.0
+comment A colored quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+
+    #[test]
+    fn ascii() {
+        let mesh = TriangleMesh::parse_ply(ASCII.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[2], Vec3::new(1., 1., 0.));
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(mesh.normals.is_none());
+
+        let colors = mesh.colors.unwrap();
+        assert_eq!(colors[1].g, 1.);
+        assert_eq!(colors[1].r, 0.);
+    }
+
+    #[test]
+    fn binary() {
+        let mut bytes = b"ply
+format binary_little_endian 1.0
+element vertex 3
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar uint vertex_indices
+end_header
+"
+        .to_vec();
+        for v in &[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]] {
+            for c in v {
+                bytes.extend_from_slice(&f64::to_le_bytes(*c));
+            }
+            for n in &[0f32, 0., 1.] {
+                bytes.extend_from_slice(&f32::to_le_bytes(*n));
+            }
+        }
+        bytes.push(3);
+        for i in 0..3u32 {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+
+        let mesh = TriangleMesh::parse_ply(&bytes).unwrap();
+        assert_eq!(mesh.vertices[1], Vec3::UNIT_X);
+        assert_eq!(mesh.normals.unwrap()[2], Vec3::UNIT_Z);
+        assert_eq!(mesh.faces, vec![[0, 1, 2]]);
+        assert!(mesh.colors.is_none());
+    }
+
+    #[test]
+    fn heightfield() {
+        // A 3x2 heightmap with a peak in the middle of the top row
+        let image = GrayImage::from_raw(3, 2, vec![0, 255, 0, 0, 0, 0]).unwrap();
+        let mesh = Heightfield::mesh(&image, (1., 2.), 4.).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.vertices[0], Vec3::new(-1., 0., -1.));
+        assert_eq!(mesh.vertices[1], Vec3::new(0., 4., -1.));
+        assert_eq!(mesh.faces.len(), 4);
+        // Every face points up
+        for face in &mesh.faces {
+            let [v0, v1, v2] = mesh.face_vertices(face);
+            assert!((v1 - v0).cross(v2 - v0).y > 0.);
+        }
+        let normals = mesh.normals.unwrap();
+        // The flat corner away from the peak faces straight up, and the peak's neighbors lean
+        // away from it
+        assert_eq!(normals[5], Vec3::UNIT_Y);
+        assert!(normals[0].x < 0. && normals[2].x > 0.);
+
+        let mut rng = CrateRng::seed_from_u64(0);
+        let terrain = Heightfield::new(&image, (1., 2.), 4., DbgBlack {}, &mut rng).unwrap();
+        let down = Ray::from([0., 10., -1.], [0., -1., 0.], 0.);
+        let hit = terrain.hit(&down, &(0.001..f64::INFINITY)).unwrap();
+        assert!((hit.point.y - 4.).abs() < 1e-9);
+
+        let tiny = GrayImage::from_raw(1, 2, vec![0, 0]).unwrap();
+        assert!(Heightfield::mesh(&tiny, (1., 1.), 1.).is_err());
+    }
+
+    #[test]
+    fn obj() {
+        let text = "# A quad with flat normals and a triangle without
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 2
+f 1 2 3 4
+f -4 -3 -1
+";
+        let mesh = TriangleMesh::parse_obj(text).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3], [0, 1, 3]]);
+        assert!(mesh.normals.is_none());
+
+        // Corners with the same position but different normals become separate vertices
+        let text = "v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0.5 0.5
+vn 0 0 1
+vn 0 0 -2
+f 1/1/1 2/1/1 3/1/1
+f 1//2 3//2 2//2
+";
+        let mesh = TriangleMesh::parse_obj(text).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [3, 4, 5]]);
+        let normals = mesh.normals.unwrap();
+        assert_eq!(normals[0], Vec3::UNIT_Z);
+        assert_eq!(normals[3], -Vec3::UNIT_Z);
+
+        let err = TriangleMesh::parse_obj("v 0 0 0\nf 1 2 3\n").unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("Line 2"), "{}", msg);
+        assert!(msg.contains("out of bounds"), "{}", msg);
+    }
+
+    #[test]
+    fn load_ply_into_bvh() {
+        // Unique to this test and process, so concurrent test runs don't share files
+        let temp = |name| {
+            let name = format!("raytracing_{}_{}", std::process::id(), name);
+            std::env::temp_dir().join(name)
+        };
+        let path = temp("load_ply_into_bvh.ply");
+        fs::write(&path, ASCII).unwrap();
+        let mut rng = CrateRng::seed_from_u64(0);
+        let quad = load_ply(&path, DbgBlack {}, &mut rng).unwrap();
+        let ray = Ray::from([0.75, 0.75, 1.], [0., 0., -1.], 0.);
+        let hit = quad.hit(&ray, &(0.001..f64::INFINITY)).unwrap();
+        assert_eq!(hit.point, Vec3::new(0.75, 0.75, 0.));
+
+        let missing = temp("load_ply_into_bvh_missing.ply");
+        let err = format!(
+            "{:#}",
+            load_ply(&missing, DbgBlack {}, &mut rng).unwrap_err()
+        );
+        assert!(err.contains("load_ply_into_bvh_missing.ply"), "{}", err);
+    }
+
+    #[test]
+    fn error_names_property() {
+        let bad = ASCII.replace("1 1 0 0 0 255", "1 oops 0 0 0 255");
+        let err = TriangleMesh::parse_ply(bad.as_bytes()).unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("\"y\""), "{}", msg);
+        assert!(msg.contains("\"vertex\" #2"), "{}", msg);
+    }
+
+    #[test]
+    fn error_names_face_with_invalid_index() {
+        for index in &["-1", "2.5"] {
+            let bad = ASCII.replace("4 0 1 2 3", &format!("4 0 1 {} 3", index));
+            let err = TriangleMesh::parse_ply(bad.as_bytes()).unwrap_err();
+            let msg = format!("{:#}", err);
+            assert!(msg.contains("\"face\" #0"), "{}", msg);
+            assert!(msg.contains(&format!("index {}", index)), "{}", msg);
+        }
+    }
+}
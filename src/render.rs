@@ -0,0 +1,436 @@
+use rand::Rng;
+
+use crate::{Background, Color, CrateRng, HitList, Hittable, Ray, SunLight, Vec3};
+
+/// Chance of sampling a direction towards the lights and the sun instead of from the material at
+/// a diffuse bounce.
+const LIGHT_CHANCE: f64 = 0.5;
+
+/// Iterative version of the diffuse ray calculation.
+/// Used because the recursive method blew the stack every time.
+///
+/// At diffuse bounces, i.e. when the `Scatter` has a `pdf`, the next direction is sampled from a
+/// mixture of the material's distribution and directions towards `lights` and the `sun`. The
+/// path is then weighted by `Material::eval` over the mixture's density. Specular bounces always
+/// follow the material. An empty `lights` and no `sun` disables light sampling.
+pub fn ray_color(
+    world: &HitList,
+    lights: &HitList,
+    background: &Background,
+    sun: Option<&SunLight>,
+    ray: &Ray,
+    max_depth: u32,
+    rng: &mut CrateRng,
+) -> Color {
+    // The light gathered along the path so far
+    let mut color = Color::new(0., 0., 0.);
+    // How much of the light coming from further along the path makes it back to the camera
+    let mut throughput = Color::default();
+    let mut ray = ray.clone();
+    let mut bounces = max_depth;
+    // Split the chance of sampling towards a light between the lights and the sun
+    let (lights_chance, sun_chance) = match (lights.is_empty(), sun.is_some()) {
+        (false, true) => (LIGHT_CHANCE / 2., LIGHT_CHANCE / 2.),
+        (false, false) => (LIGHT_CHANCE, 0.),
+        (true, true) => (0., LIGHT_CHANCE),
+        (true, false) => (0., 0.),
+    };
+
+    // NOTE: Tweak the beginning of the range to deal with shadow acne.
+    while let Some(hit) = world.hit(&ray, &(0.001..f64::INFINITY)) {
+        color += throughput * hit.material.emitted(&ray, &hit);
+        let scatter = match hit.material.scatter(&ray, &hit, rng) {
+            Some(scatter) => scatter,
+            // Ray got absorbed so no more light is reflected.
+            None => return color,
+        };
+
+        let mut scattered = scatter.ray;
+        if scatter.pdf.is_none() || lights_chance + sun_chance <= 0. {
+            throughput *= scatter.albedo;
+        } else {
+            let choice = rng.gen::<f64>();
+            if choice < lights_chance {
+                scattered.dir = lights.random_dir(hit.point, rng);
+            } else if let Some(sun) = sun.filter(|_| choice < lights_chance + sun_chance) {
+                scattered.dir = sun.random_dir(rng);
+            }
+            let material_pdf = hit.material.scattering_pdf(&ray, &hit, &scattered);
+            let mut pdf = (1. - lights_chance - sun_chance) * material_pdf;
+            if lights_chance > 0. {
+                pdf += lights_chance * lights.pdf_value(hit.point, scattered.dir);
+            }
+            if let Some(sun) = sun {
+                pdf += sun_chance * sun.pdf_value(scattered.dir);
+            }
+            if pdf <= 0. {
+                return color;
+            }
+            throughput *= hit.material.eval(&ray, &hit, scattered.dir) / pdf;
+        }
+
+        // Keep the wavelength picked by a dispersive material for the rest of the path.
+        scattered.wavelength = scattered.wavelength.or(ray.wavelength);
+        scattered.distance = ray.distance + hit.time * ray.dir.norm();
+        ray = scattered;
+
+        bounces -= 1;
+        if bounces == 0 {
+            return color;
+        }
+    }
+
+    // The ray escaped the world
+    let mut sky = background.color(&ray);
+    if let Some(sun) = sun {
+        sky += sun.emitted(ray.dir);
+    }
+    color + throughput * sky
+}
+
+/// Distance from the ray's origin to the first hit in `world`, or infinity for a miss.
+pub fn first_hit_distance(world: &HitList, ray: &Ray) -> f64 {
+    world
+        .hit(ray, &(0.001..f64::INFINITY))
+        .map_or(f64::INFINITY, |hit| hit.time * ray.dir.norm())
+}
+
+/// Outward surface normal at the first hit in `world`, or `None` for a miss.
+pub fn first_hit_normal(world: &HitList, ray: &Ray) -> Option<Vec3> {
+    let hit = world.hit(ray, &(0.001..f64::INFINITY))?;
+    // Undo the flip that makes the normal face the incident ray.
+    Some(if hit.front_face {
+        hit.normal
+    } else {
+        -hit.normal
+    })
+}
+
+/// Running mean and spread of the samples of a pixel. Uses Welford's algorithm, which doesn't
+/// lose the variance to cancellation when it's small next to the mean like `E[X^2] - E[X]^2`
+/// does.
+#[derive(Copy, Clone, Debug)]
+pub struct SampleStats {
+    count: u32,
+    mean: Color,
+    /// Sum of squared differences from the mean
+    m2: Color,
+}
+impl SampleStats {
+    pub fn new() -> Self {
+        let black = Color::new(0., 0., 0.);
+        Self {
+            count: 0,
+            mean: black,
+            m2: black,
+        }
+    }
+
+    pub fn push(&mut self, sample: Color) {
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (sample - self.mean);
+    }
+
+    /// Average of the samples so far, or black if there are none.
+    pub fn mean(&self) -> Color {
+        self.mean
+    }
+
+    /// Unbiased sample variance of each channel, or black with fewer than two samples.
+    pub fn variance(&self) -> Color {
+        if self.count < 2 {
+            return Color::new(0., 0., 0.);
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    /// Standard deviation of each channel of the samples.
+    pub fn std_dev(&self) -> Color {
+        let Color { r, g, b } = self.variance();
+        Color::new(r.sqrt(), g.sqrt(), b.sqrt())
+    }
+
+    /// Standard error of the mean of each channel, i.e. the standard deviation divided by the
+    /// square root of the sample count. This is how far the pixel's color is expected to be from
+    /// the converged one.
+    pub fn std_error(&self) -> Color {
+        self.std_dev() / (self.count.max(1) as f64).sqrt()
+    }
+}
+impl Default for SampleStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod render_test {
+    use super::*;
+    use crate::material::{
+        DbgBlack, DiffuseLight, HiddenEmitter, ImageTexture, Lambertian, Material, OrenNayar, Pbr,
+        Phong,
+    };
+    use crate::shape::{Sphere, XZRect};
+    use image::RgbImage;
+    use rand::SeedableRng;
+
+    #[test]
+    fn light_sampling_is_unbiased() {
+        const SAMPLES: usize = 40_000;
+        let mut world = HitList::new();
+        world.push(XZRect::new(
+            -5.0..5.,
+            -5.0..5.,
+            0.,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        ));
+        world.push(XZRect::new(
+            -0.5..0.5,
+            -0.5..0.5,
+            2.,
+            DiffuseLight::white(4.),
+        ));
+        let mut lights = HitList::new();
+        lights.push(XZRect::new(-0.5..0.5, -0.5..0.5, 2., DbgBlack {}));
+        let background = Background::Solid(Color::new(0., 0., 0.));
+        let ray = Ray::from([0.3, 1., 0.2], [0., -1., 0.], 0.);
+
+        let mean = |lights: &HitList| {
+            let mut rng = CrateRng::seed_from_u64(6);
+            let sum: f64 = (0..SAMPLES)
+                .map(|_| ray_color(&world, lights, &background, None, &ray, 10, &mut rng).r)
+                .sum();
+            sum / SAMPLES as f64
+        };
+        let (with, without) = (mean(&lights), mean(&HitList::new()));
+        assert!(
+            (with - without).abs() < 0.05 * without,
+            "{} != {}",
+            with,
+            without
+        );
+    }
+
+    #[test]
+    fn light_sampling_is_unbiased_for_glossy_materials() {
+        const SAMPLES: usize = 40_000;
+        let light = || XZRect::new(-1.0..1., -1.0..1., 2., DiffuseLight::white(4.));
+        let mut lights = HitList::new();
+        lights.push(XZRect::new(-1.0..1., -1.0..1., 2., DbgBlack {}));
+        let background = Background::Solid(Color::new(0., 0., 0.));
+        // Slanted, so that the materials' weights depend on the outgoing direction
+        let ray = Ray::from([0.3, 1., 0.2], [1., -1., 0.5], 0.);
+
+        let mean = |world: &HitList, lights: &HitList| {
+            let mut rng = CrateRng::seed_from_u64(6);
+            let sum: f64 = (0..SAMPLES)
+                .map(|_| ray_color(world, lights, &background, None, &ray, 10, &mut rng).r)
+                .sum();
+            sum / SAMPLES as f64
+        };
+        let floors: Vec<Box<dyn Material>> = vec![
+            Box::new(OrenNayar::new(Color::new(0.5, 0.5, 0.5), 1.)),
+            Box::new(Phong::new(
+                Color::new(0.3, 0.3, 0.3),
+                Color::new(0.5, 0.5, 0.5),
+                5.,
+            )),
+            Box::new(Pbr::new(Color::new(0.5, 0.5, 0.5), 0.2, 0.6)),
+        ];
+        for floor in floors {
+            let mut world = HitList::new();
+            world.push(XZRect::new(-5.0..5., -5.0..5., 0., floor));
+            world.push(light());
+            let (with, without) = (mean(&world, &lights), mean(&world, &HitList::new()));
+            assert!(
+                (with - without).abs() < 0.05 * without,
+                "{} != {}",
+                with,
+                without
+            );
+        }
+    }
+
+    #[test]
+    fn sun_lights_a_diffuse_plane() {
+        const SAMPLES: usize = 10_000;
+        let mut world = HitList::new();
+        world.push(XZRect::new(
+            -5.0..5.,
+            -5.0..5.,
+            0.,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        ));
+        let background = Background::Solid(Color::new(0., 0., 0.));
+        let sun = SunLight::new(Vec3::UNIT_Y, Color::new(1., 1., 1.), 2.);
+        let ray = Ray::from([0.3, 1., 0.2], [0., -1., 0.], 0.);
+
+        let mut rng = CrateRng::seed_from_u64(6);
+        let sum: f64 = (0..SAMPLES)
+            .map(|_| {
+                ray_color(
+                    &world,
+                    &HitList::new(),
+                    &background,
+                    Some(&sun),
+                    &ray,
+                    10,
+                    &mut rng,
+                )
+                .r
+            })
+            .sum();
+        let mean = sum / SAMPLES as f64;
+        // A surface facing the sun reflects its albedo times the sun's color.
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn hidden_emitter_lights_the_scene_without_being_seen() {
+        const SAMPLES: usize = 10_000;
+        let mut world = HitList::new();
+        world.push(XZRect::new(
+            -5.0..5.,
+            -5.0..5.,
+            0.,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        ));
+        world.push(XZRect::new(
+            -5.0..5.,
+            -5.0..5.,
+            2.,
+            HiddenEmitter::new(DiffuseLight::white(1.)),
+        ));
+        let background = Background::Solid(Color::new(0., 0., 0.));
+        let mut rng = CrateRng::seed_from_u64(6);
+        let mut mean = |ray: &Ray| {
+            let sum: f64 = (0..SAMPLES)
+                .map(|_| {
+                    ray_color(
+                        &world,
+                        &HitList::new(),
+                        &background,
+                        None,
+                        ray,
+                        10,
+                        &mut rng,
+                    )
+                    .r
+                })
+                .sum();
+            sum / SAMPLES as f64
+        };
+
+        // The camera looks through the light at the sky
+        let up = Ray {
+            primary: true,
+            ..Ray::from([0., 1., 0.], [0., 1., 0.], 0.)
+        };
+        assert_eq!(mean(&up), 0.);
+        // but the floor is lit by it
+        let down = Ray {
+            primary: true,
+            ..Ray::from([0., 1., 0.], [0., -1., 0.], 0.)
+        };
+        assert!(mean(&down) > 0.2);
+        // and so are reflections of it
+        let scattered = Ray::from([0., 1., 0.], [0., 1., 0.], 0.);
+        assert_eq!(mean(&scattered), 1.);
+    }
+
+    #[test]
+    fn mipmaps_keep_distant_textures_from_sparkling() {
+        const PIXELS: usize = 100;
+        // Angle covered by each pixel
+        const PIXEL_SIZE: f64 = 0.02;
+        // Each pixel of the image is a square of a fine checkerboard, 12.8 of them per unit.
+        let checker =
+            RgbImage::from_fn(256, 256, |x, y| image::Rgb([((x + y) % 2 * 255) as u8; 3]));
+        let floor = |texel_density| {
+            let mut world = HitList::new();
+            let mut texture = ImageTexture::from(checker.clone());
+            texture.texel_density = texel_density;
+            world.push(XZRect::new(
+                -10.0..10.,
+                -10.0..10.,
+                0.,
+                Lambertian::new(texture),
+            ));
+            world
+        };
+        // Lambertian surfaces under a white sky reflect their albedo
+        let background = Background::Solid(Color::new(1., 1., 1.));
+
+        // Standard deviation between renders with different seeds of the same row of pixels
+        // looking at the far end of the floor.
+        let noise = |world: &HitList| {
+            let mut renders = [6, 7].iter().map(|&seed| {
+                let mut rng = CrateRng::seed_from_u64(seed);
+                (0..PIXELS)
+                    .map(|i| {
+                        let x = (i as f64 + rng.gen::<f64>()) * PIXEL_SIZE - 1.;
+                        let dir = Vec3::new(x, -0.25 + rng.gen::<f64>() * PIXEL_SIZE, -1.);
+                        let ray = Ray::new(Vec3::new(0., 2., 0.), dir, 0.);
+                        ray_color(world, &HitList::new(), &background, None, &ray, 2, &mut rng).r
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let (a, b) = (renders.next().unwrap(), renders.next().unwrap());
+            let squared: f64 = a.iter().zip(&b).map(|(a, b)| (a - b).powi(2)).sum();
+            (squared / PIXELS as f64).sqrt()
+        };
+        let sparkly = noise(&floor(None));
+        let smooth = noise(&floor(Some(12.8 * PIXEL_SIZE)));
+        assert!(sparkly > 0.5, "{}", sparkly);
+        assert!(smooth < 0.1 * sparkly, "{} vs {}", smooth, sparkly);
+    }
+
+    #[test]
+    fn first_hit_distance_is_in_world_units() {
+        let mut world = HitList::new();
+        world.push(XZRect::new(-5.0..5., -5.0..5., 0., DbgBlack {}));
+        // The direction isn't normalized, so the hit's time is half the distance.
+        let ray = Ray::from([0., 3., 0.], [0., -2., 0.], 0.);
+        assert_eq!(first_hit_distance(&world, &ray), 3.);
+        let ray = Ray::from([0., 3., 0.], [0., 2., 0.], 0.);
+        assert_eq!(first_hit_distance(&world, &ray), f64::INFINITY);
+    }
+
+    #[test]
+    fn first_hit_normal_faces_outwards() {
+        let mut world = HitList::new();
+        world.push(Sphere::from([0., 0., 0.], 1., DbgBlack {}));
+        let outside = Ray::from([0., 3., 0.], [0., -1., 0.], 0.);
+        assert_eq!(first_hit_normal(&world, &outside), Some(Vec3::UNIT_Y));
+        let inside = Ray::from([0., 0., 0.], [0., -1., 0.], 0.);
+        assert_eq!(first_hit_normal(&world, &inside), Some(-Vec3::UNIT_Y));
+        let miss = Ray::from([0., 3., 0.], [0., 1., 0.], 0.);
+        assert_eq!(first_hit_normal(&world, &miss), None);
+    }
+
+    #[test]
+    fn sample_stats_keep_small_variances_of_large_samples() {
+        let mut stats = SampleStats::new();
+        stats.push(Color::new(1., 1., 1.));
+        assert_eq!(stats.mean().r, 1.);
+        assert_eq!(stats.variance().r, 0.);
+
+        // Summing squares would lose the spread of these entirely to rounding
+        let mut stats = SampleStats::new();
+        for x in &[1., 2., 3., 4.] {
+            stats.push(Color::new(1e9 + x, *x, 0.));
+        }
+        let (mean, variance, std_error) = (stats.mean(), stats.variance(), stats.std_error());
+        assert_eq!([mean.r, mean.g, mean.b], [1e9 + 2.5, 2.5, 0.]);
+        assert!((variance.r - 5. / 3.).abs() < 1e-6, "{:?}", variance);
+        assert_eq!([variance.g, variance.b], [5. / 3., 0.]);
+        let std_dev = stats.std_dev();
+        assert_eq!([std_dev.g, std_dev.b], [(5. / 3_f64).sqrt(), 0.]);
+        let expected = (5. / 12_f64).sqrt();
+        assert!((std_error.r - expected).abs() < 1e-6, "{:?}", std_error);
+        assert!((std_error.g - expected).abs() < 1e-12, "{:?}", std_error);
+        assert_eq!(std_error.b, 0.);
+    }
+}
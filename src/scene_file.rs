@@ -0,0 +1,523 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::SceneParts;
+use crate::material::{Checkered, Gradient, UvCheckered};
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Metal};
+use crate::material::{NormalDebug, OrenNayar};
+use crate::material::{PerlinNoise, ValueNoise};
+use crate::shape::{Ellipsoid, MovingSphere, Sphere, Triangle, XYRect, XZRect, YZRect};
+use crate::{
+    Axis, Background, Camera, CameraBuilder, Color, CrateRng, Hit, HitList, Material, Ray, Scatter,
+};
+use crate::{ResultExt, TexCoord, Texture, Vec3};
+
+/// Description of a scene that can be loaded from a RON or JSON file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub background: BackgroundDesc,
+    pub shapes: Vec<ShapeDesc>,
+}
+impl SceneDesc {
+    /// Loads a scene from a file. Files ending in `.json` are parsed as JSON and anything else
+    /// as RON.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read scene file {:?}", path))?;
+
+        let desc = if path.extension() == Some(OsStr::new("json")) {
+            serde_json::from_str(&text).map_err(anyhow::Error::new)
+        } else {
+            ron::de::from_str(&text).map_err(anyhow::Error::new)
+        };
+        desc.with_context(|| format!("Invalid scene file {:?}", path))
+    }
+
+    /// Spheres and rectangles with a `DiffuseLight` material are also added to the lights.
+    pub fn create(&self, aspect_ratio: f64) -> Result<SceneParts> {
+        let camera = self.camera.builder(aspect_ratio);
+        camera.verify().camera_context(&camera)?;
+        let (mut world, mut lights) = (HitList::new(), HitList::new());
+        for shape in &self.shapes {
+            shape.push_to(&mut world);
+            let light = matches!(shape.material(), MaterialDesc::DiffuseLight { .. });
+            let sampleable = matches!(
+                shape,
+                ShapeDesc::Sphere { .. }
+                    | ShapeDesc::XYRect { .. }
+                    | ShapeDesc::XZRect { .. }
+                    | ShapeDesc::YZRect { .. }
+            );
+            if light && sampleable {
+                shape.push_to(&mut lights);
+            }
+        }
+        Ok((camera, world, lights, self.background.build()))
+    }
+}
+
+/// Mirrors `CameraBuilder`. Optional fields use the builder's defaults.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDesc {
+    pub origin: Vec3,
+    pub look_at: Vec3,
+    pub view_up: Option<Vec3>,
+    pub vfov_degrees: Option<f64>,
+    pub aperture: Option<f64>,
+    pub focus_dist: Option<f64>,
+    pub shutter_time: Option<Range<f64>>,
+}
+impl CameraDesc {
+    pub fn build(&self, aspect_ratio: f64) -> Result<Camera> {
+        let builder = self.builder(aspect_ratio);
+        builder.build().camera_context(&builder)
+    }
+
+    pub fn builder(&self, aspect_ratio: f64) -> CameraBuilder {
+        let mut builder = Camera::builder();
+        builder
+            .origin(self.origin)
+            .aspect_ratio(aspect_ratio)
+            .look_at(self.look_at)
+            .focus_dist(self.focus_dist)
+            .shutter_time(self.shutter_time.clone());
+        if let Some(view_up) = self.view_up {
+            builder.view_up(view_up);
+        }
+        if let Some(vfov) = self.vfov_degrees {
+            builder.vfov_degrees(vfov);
+        }
+        if let Some(aperture) = self.aperture {
+            builder.aperture(aperture);
+        }
+        builder
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub enum BackgroundDesc {
+    #[default]
+    Sky,
+    Solid(Color),
+}
+impl BackgroundDesc {
+    pub fn build(&self) -> Background {
+        match self {
+            BackgroundDesc::Sky => Background::Sky,
+            BackgroundDesc::Solid(color) => Background::Solid(*color),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ShapeDesc {
+    Sphere {
+        center: Vec3,
+        radius: f64,
+        material: MaterialDesc,
+    },
+    MovingSphere {
+        center_0: Vec3,
+        center_1: Vec3,
+        radius: f64,
+        material: MaterialDesc,
+    },
+    Ellipsoid {
+        center: Vec3,
+        radii: Vec3,
+        material: MaterialDesc,
+    },
+    Triangle {
+        vertices: [Vec3; 3],
+        material: MaterialDesc,
+    },
+    XYRect {
+        x: Range<f64>,
+        y: Range<f64>,
+        k: f64,
+        material: MaterialDesc,
+    },
+    XZRect {
+        x: Range<f64>,
+        z: Range<f64>,
+        k: f64,
+        material: MaterialDesc,
+    },
+    YZRect {
+        y: Range<f64>,
+        z: Range<f64>,
+        k: f64,
+        material: MaterialDesc,
+    },
+}
+impl ShapeDesc {
+    pub fn material(&self) -> &MaterialDesc {
+        match self {
+            ShapeDesc::Sphere { material, .. }
+            | ShapeDesc::MovingSphere { material, .. }
+            | ShapeDesc::Ellipsoid { material, .. }
+            | ShapeDesc::Triangle { material, .. }
+            | ShapeDesc::XYRect { material, .. }
+            | ShapeDesc::XZRect { material, .. }
+            | ShapeDesc::YZRect { material, .. } => material,
+        }
+    }
+
+    pub fn push_to(&self, world: &mut HitList) {
+        match self {
+            ShapeDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => world.push(Sphere::new(*center, *radius, material.build())),
+            ShapeDesc::MovingSphere {
+                center_0,
+                center_1,
+                radius,
+                material,
+            } => world.push(MovingSphere::new(
+                *center_0,
+                *center_1,
+                *radius,
+                material.build(),
+            )),
+            ShapeDesc::Ellipsoid {
+                center,
+                radii,
+                material,
+            } => world.push(Ellipsoid::new(*center, *radii, material.build())),
+            ShapeDesc::Triangle { vertices, material } => {
+                let [v0, v1, v2] = *vertices;
+                world.push(Triangle::new(v0, v1, v2, material.build()))
+            }
+            ShapeDesc::XYRect { x, y, k, material } => {
+                world.push(XYRect::new(x.clone(), y.clone(), *k, material.build()))
+            }
+            ShapeDesc::XZRect { x, z, k, material } => {
+                world.push(XZRect::new(x.clone(), z.clone(), *k, material.build()))
+            }
+            ShapeDesc::YZRect { y, z, k, material } => {
+                world.push(YZRect::new(y.clone(), z.clone(), *k, material.build()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: TextureDesc,
+    },
+    Metal {
+        albedo: Color,
+        fuzz: f64,
+    },
+    Dielectric {
+        ref_index: f64,
+        #[serde(default)]
+        attenuation: Option<Color>,
+        #[serde(default)]
+        roughness: f64,
+        #[serde(default)]
+        tint: Option<TextureDesc>,
+        #[serde(default)]
+        abbe: Option<f64>,
+    },
+    DiffuseLight {
+        emit: TextureDesc,
+        intensity: f64,
+    },
+    Isotropic {
+        albedo: TextureDesc,
+    },
+    OrenNayar {
+        albedo: TextureDesc,
+        sigma: f64,
+    },
+    NormalDebug,
+}
+impl MaterialDesc {
+    fn build(&self) -> SceneMaterial {
+        match self {
+            MaterialDesc::Lambertian { albedo } => {
+                SceneMaterial::Lambertian(Lambertian::new(albedo.build()))
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                SceneMaterial::Metal(Metal::new(*albedo, *fuzz))
+            }
+            MaterialDesc::Dielectric {
+                ref_index,
+                attenuation,
+                roughness,
+                tint,
+                abbe,
+            } => {
+                let tint = tint.as_ref().map_or_else(
+                    || SceneTexture::Solid(Color::new(1., 1., 1.)),
+                    TextureDesc::build,
+                );
+                let mut material = Dielectric::tinted(*ref_index, tint);
+                material.roughness = roughness.clamp(0., 1.);
+                material.abbe = *abbe;
+                if let Some(attenuation) = attenuation {
+                    material.attenuation = *attenuation;
+                }
+                SceneMaterial::Dielectric(material)
+            }
+            MaterialDesc::DiffuseLight { emit, intensity } => {
+                SceneMaterial::DiffuseLight(DiffuseLight::new(emit.build(), *intensity))
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                SceneMaterial::Isotropic(Isotropic::new(albedo.build()))
+            }
+            MaterialDesc::OrenNayar { albedo, sigma } => {
+                SceneMaterial::OrenNayar(OrenNayar::new(albedo.build(), *sigma))
+            }
+            MaterialDesc::NormalDebug => SceneMaterial::NormalDebug(NormalDebug {}),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid(Color),
+    Checkered {
+        freq: f64,
+        odd: Box<TextureDesc>,
+        even: Box<TextureDesc>,
+    },
+    UvCheckered {
+        freq: f64,
+        odd: Box<TextureDesc>,
+        even: Box<TextureDesc>,
+    },
+    Gradient {
+        from: Color,
+        to: Color,
+        axis: Axis,
+        range: Range<f64>,
+        #[serde(default)]
+        smooth: bool,
+    },
+    ValueNoise {
+        #[serde(default)]
+        seed: Option<u64>,
+        freq: f64,
+    },
+    PerlinNoise {
+        #[serde(default)]
+        seed: Option<u64>,
+        freq: f64,
+    },
+}
+impl TextureDesc {
+    fn build(&self) -> SceneTexture {
+        match self {
+            TextureDesc::Solid(color) => SceneTexture::Solid(*color),
+            TextureDesc::Checkered { freq, odd, even } => {
+                SceneTexture::Checkered(Box::new(Checkered::new(*freq, odd.build(), even.build())))
+            }
+            TextureDesc::UvCheckered { freq, odd, even } => SceneTexture::UvCheckered(Box::new(
+                UvCheckered::new(*freq, odd.build(), even.build()),
+            )),
+            TextureDesc::Gradient {
+                from,
+                to,
+                axis,
+                range,
+                smooth,
+            } => {
+                let mut gradient = Gradient::new(*from, *to, *axis, range.clone());
+                gradient.smooth = *smooth;
+                SceneTexture::Gradient(gradient)
+            }
+            TextureDesc::ValueNoise { seed, freq } => {
+                SceneTexture::ValueNoise(Box::new(ValueNoise::new(*seed, *freq)))
+            }
+            TextureDesc::PerlinNoise { seed, freq } => {
+                SceneTexture::PerlinNoise(Box::new(PerlinNoise::new(*seed, *freq)))
+            }
+        }
+    }
+}
+
+/// The textures that can be created from a `TextureDesc`.
+#[derive(Debug)]
+enum SceneTexture {
+    Solid(Color),
+    Checkered(Box<Checkered<SceneTexture, SceneTexture>>),
+    UvCheckered(Box<UvCheckered<SceneTexture, SceneTexture>>),
+    Gradient(Gradient),
+    ValueNoise(Box<ValueNoise>),
+    PerlinNoise(Box<PerlinNoise>),
+}
+impl Texture for SceneTexture {
+    fn value(&self, coord: &TexCoord) -> Color {
+        use SceneTexture::*;
+        match self {
+            Solid(t) => t.value(coord),
+            Checkered(t) => t.value(coord),
+            UvCheckered(t) => t.value(coord),
+            Gradient(t) => t.value(coord),
+            ValueNoise(t) => t.value(coord),
+            PerlinNoise(t) => t.value(coord),
+        }
+    }
+}
+
+/// The materials that can be created from a `MaterialDesc`.
+#[derive(Debug)]
+enum SceneMaterial {
+    Lambertian(Lambertian<SceneTexture>),
+    Metal(Metal),
+    Dielectric(Dielectric<SceneTexture>),
+    DiffuseLight(DiffuseLight<SceneTexture>),
+    Isotropic(Isotropic<SceneTexture>),
+    OrenNayar(OrenNayar<SceneTexture>),
+    NormalDebug(NormalDebug),
+}
+impl SceneMaterial {
+    fn inner(&self) -> &dyn Material {
+        use SceneMaterial::*;
+        match self {
+            Lambertian(m) => m,
+            Metal(m) => m,
+            Dielectric(m) => m,
+            DiffuseLight(m) => m,
+            Isotropic(m) => m,
+            OrenNayar(m) => m,
+            NormalDebug(m) => m,
+        }
+    }
+}
+impl Material for SceneMaterial {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut CrateRng) -> Option<Scatter> {
+        self.inner().scatter(ray, hit, rng)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &Hit) -> Color {
+        self.inner().emitted(ray, hit)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        self.inner().scattering_pdf(ray, hit, scattered)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, dir: Vec3) -> Color {
+        self.inner().eval(ray, hit, dir)
+    }
+}
+
+#[cfg(test)]
+mod scene_file_test {
+    use super::*;
+    use crate::render::ray_color;
+    use rand::SeedableRng;
+
+    const SCENE: &str = r#"(
+        camera: (
+            origin: (13., 2., 3.),
+            look_at: (0., 0., 0.),
+            vfov_degrees: Some(20.),
+        ),
+        shapes: [
+            Sphere(
+                center: (0., -1000., 0.),
+                radius: 1000.,
+                material: Lambertian(albedo: Checkered(
+                    freq: 10.,
+                    odd: Solid((0.2, 0.3, 0.1)),
+                    even: Solid((0.9, 0.9, 0.9)),
+                )),
+            ),
+            Sphere(
+                center: (0., 1., 0.),
+                radius: 1.,
+                material: Dielectric(ref_index: 1.5),
+            ),
+        ],
+    )"#;
+
+    #[test]
+    fn parse_ron() {
+        let desc: SceneDesc = ron::de::from_str(SCENE).unwrap();
+        assert_eq!(desc.shapes.len(), 2);
+        assert!(matches!(desc.background, BackgroundDesc::Sky));
+        assert_eq!(desc.camera.origin, Vec3::new(13., 2., 3.));
+    }
+
+    #[test]
+    fn error_names_field() {
+        let bad = SCENE.replace("radius: 1.,", "radius: 1., radiu: 2.,");
+        let err = ron::de::from_str::<SceneDesc>(&bad).unwrap_err();
+        assert!(err.to_string().contains("radiu"), "{}", err);
+
+        let bad = SCENE.replace("radius: 1.,", "");
+        let err = ron::de::from_str::<SceneDesc>(&bad).unwrap_err();
+        assert!(err.to_string().contains("radius"), "{}", err);
+    }
+
+    #[test]
+    fn load_example() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/example.ron");
+        let (_, world, _, _) = SceneDesc::load(path).unwrap().create(16. / 9.).unwrap();
+        assert_eq!(world.len(), 4);
+    }
+
+    #[test]
+    fn lights_are_sampled() {
+        let light = "DiffuseLight(emit: Solid((1., 1., 1.)), intensity: 4.)";
+        let scene = SCENE.replace("Dielectric(ref_index: 1.5)", light);
+        let desc: SceneDesc = ron::de::from_str(&scene).unwrap();
+        let (_, world, lights, background) = desc.create(16. / 9.).unwrap();
+        assert_eq!((world.len(), lights.len()), (2, 1));
+
+        // Sampling the light lights the ground as much as only following the material does
+        let ray = Ray::from([2., 0.5, 0.], [0., -1., 0.], 0.);
+        let mean = |lights: &HitList| {
+            let mut rng = CrateRng::seed_from_u64(0);
+            let n = 20_000;
+            let sum: f64 = (0..n)
+                .map(|_| ray_color(&world, lights, &background, None, &ray, 10, &mut rng).r)
+                .sum();
+            sum / n as f64
+        };
+        let (sampled, unsampled) = (mean(&lights), mean(&HitList::new()));
+        assert!(sampled > 0., "{}", sampled);
+        assert!(
+            (sampled - unsampled).abs() < 0.05 * unsampled,
+            "{} {}",
+            sampled,
+            unsampled
+        );
+    }
+
+    #[test]
+    fn load_json() {
+        let desc: SceneDesc = ron::de::from_str(SCENE).unwrap();
+        let json = serde_json::to_string(&desc).unwrap();
+        // Unique to this test and process, so concurrent test runs don't share files
+        let name = format!("raytracing_{}_load_json.json", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, json).unwrap();
+        let desc = SceneDesc::load(&path).unwrap();
+        assert_eq!(desc.shapes.len(), 2);
+
+        fs::write(&path, "{\"camera\": {}}").unwrap();
+        let err = format!("{:?}", SceneDesc::load(&path).unwrap_err());
+        assert!(err.contains("origin"), "{}", err);
+    }
+}
@@ -1,11 +1,14 @@
 use std::f64::consts;
 use std::ops::Range;
+use std::path::Path;
 
 use anyhow::{anyhow, ensure, Context, Result};
+use image::RgbImage;
 use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
 use rayon::prelude::*;
 
-use crate::{config, Axis, Color, CrateRng, Ray, ResultExt, Vec3};
+use crate::{Axis, Color, CrateRng, Ray, ResultExt, Vec3};
 
 pub struct Screen {
     pub width: usize,
@@ -18,39 +21,101 @@ impl Screen {
         Self {
             width,
             height,
-            buffer: vec![Color::default(); width * height].into(),
+            buffer: vec![Color::new(0., 0., 0.); width * height].into(),
         }
     }
 
-    /// Encodes each Pixel into `0RGB` and applies gamma correction
-    pub fn encode(&self) -> Box<[u32]> {
+    /// Encodes each Pixel into `0RGB` and applies gamma correction with `gamma`
+    pub fn encode(&self, gamma: f64) -> Box<[u32]> {
         self.buffer
             .iter()
             .map(|p| {
-                // Check for invalid Colors, including NANs
-                let bounds = 0.0..=1.0;
-                if !bounds.contains(&p.r) || !bounds.contains(&p.g) || !bounds.contains(&p.b) {
-                    panic!("Invalid color: {:?}", p);
-                }
-
-                let (r, g, b) = (
-                    255.99 * p.r.sqrt(),
-                    255.99 * p.g.sqrt(),
-                    255.99 * p.b.sqrt(),
-                );
+                let [r, g, b] = p.to_rgb8(gamma);
                 let (r, g, b) = (r as u32, g as u32, b as u32);
                 (r << 16) | (g << 8) | b
             })
             .collect()
     }
 
-    pub fn rows_mut(&mut self) -> std::slice::ChunksExactMut<Color> {
+    /// Saves the screen as an image with gamma correction. The format is chosen from the
+    /// extension of `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, gamma: f64) -> Result<()> {
+        let path = path.as_ref();
+        let bytes = self
+            .buffer
+            .iter()
+            .flat_map(|p| p.to_rgb8(gamma).to_vec())
+            .collect();
+        let image = RgbImage::from_raw(self.width as u32, self.height as u32, bytes)
+            .expect("Screen buffer doesn't match its dimensions");
+        image
+            .save(path)
+            .with_context(|| format!("Couldn't save image to {:?}", path))
+    }
+
+    pub fn rows_mut(&mut self) -> std::slice::ChunksExactMut<'_, Color> {
         self.buffer.chunks_exact_mut(self.width)
     }
 
-    pub fn par_rows_mut(&mut self) -> rayon::slice::ChunksExactMut<Color> {
+    pub fn par_rows_mut(&mut self) -> rayon::slice::ChunksExactMut<'_, Color> {
         self.buffer.par_chunks_exact_mut(self.width)
     }
+
+    /// Split the screen into tiles of at most `TILE_SIZE` by `TILE_SIZE` pixels, in row-major
+    /// order.
+    pub fn tiles_mut(&mut self) -> Vec<Tile<'_>> {
+        let width = self.width;
+        let mut tiles = Vec::new();
+        for (band, pixels) in self.buffer.chunks_mut(width * TILE_SIZE).enumerate() {
+            let y = band * TILE_SIZE;
+            let height = pixels.len() / width;
+            // The tiles in this band start here
+            let first = tiles.len();
+            for x in (0..width).step_by(TILE_SIZE) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(width - x),
+                    height,
+                    rows: Vec::with_capacity(height),
+                });
+            }
+            for row in pixels.chunks_exact_mut(width) {
+                for (tile, chunk) in tiles[first..].iter_mut().zip(row.chunks_mut(TILE_SIZE)) {
+                    tile.rows.push(chunk);
+                }
+            }
+        }
+        tiles
+    }
+
+    pub fn par_tiles_mut(&mut self) -> rayon::vec::IntoIter<Tile<'_>> {
+        self.tiles_mut().into_par_iter()
+    }
+}
+
+pub const TILE_SIZE: usize = 32;
+
+/// A rectangular part of a `Screen`.
+pub struct Tile<'a> {
+    /// Column of the tile's top left pixel
+    pub x: usize,
+    /// Row of the tile's top left pixel
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    rows: Vec<&'a mut [Color]>,
+}
+impl<'a> Tile<'a> {
+    /// Iterate over the tile's pixels along with their `(x, y)` position on the screen.
+    pub fn into_pixels(self) -> impl Iterator<Item = (usize, usize, &'a mut Color)> {
+        let (x, y) = (self.x, self.y);
+        self.rows.into_iter().enumerate().flat_map(move |(j, row)| {
+            row.iter_mut()
+                .enumerate()
+                .map(move |(i, pix)| (x + i, y + j, pix))
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -62,8 +127,13 @@ pub struct Camera {
 
     /// Used for depth of field. Set to `0` to disable depth of field.
     pub lens_radius: f64,
+    /// Shape of the lens. Out of focus highlights take this shape.
+    pub aperture_shape: Aperture,
     /// Used for motion blur. Set to `None` to disable.
     pub shutter_time: Option<Uniform<f64>>,
+    /// Time of every ray when there's no `shutter_time`. `0` unless the camera is part of an
+    /// animation.
+    pub time: f64,
     /// Width part of the orthonormal basis.
     pub u: Vec3,
     /// Height part of the orthonormal basis.
@@ -80,33 +150,71 @@ impl Camera {
         let origin = if self.lens_radius == 0. {
             self.origin
         } else {
-            let rand_disk = self.lens_radius * Vec3::rand_unit_disk(rng);
+            let rand_disk = self.lens_radius * self.aperture_shape.sample(rng);
             let offset = rand_disk.x * self.u + rand_disk.y * self.v;
             self.origin + offset
         };
-        let time = self.shutter_time.map_or(0., |s| s.sample(rng));
+        let time = self.shutter_time.map_or(self.time, |s| s.sample(rng));
 
-        Ray::new(
-            origin,
-            self.lower_left + i * self.horiz + j * self.vert - origin,
-            time,
-        )
+        let dir = self.lower_left + i * self.horiz + j * self.vert - origin;
+        Ray {
+            primary: true,
+            ..Ray::new(origin, dir, time)
+        }
     }
 }
 
-#[derive(Debug)]
+/// The shape of a camera's lens.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon inscribed in the unit circle, with a vertex pointing up.
+    Polygon {
+        sides: u32,
+    },
+}
+impl Aperture {
+    /// Samples uniformly from the shape in the `x` and `y` dimensions. `z` is 0.
+    pub fn sample(&self, rng: &mut CrateRng) -> Vec3 {
+        match *self {
+            Aperture::Circle => Vec3::rand_unit_disk(rng),
+            Aperture::Polygon { sides } => {
+                // Pick one of the triangles between the center and each edge, then pick a
+                // point in that triangle.
+                let wedge = 2. * consts::PI / sides as f64;
+                let side = rng.gen_range(0, sides) as f64;
+                let corner = |i: f64| {
+                    let (sin, cos) = (consts::FRAC_PI_2 + i * wedge).sin_cos();
+                    Vec3::new(cos, sin, 0.)
+                };
+                let (a, b) = (corner(side), corner(side + 1.));
+
+                let (r1, r2): (f64, f64) = (rng.gen(), rng.gen());
+                let r1 = r1.sqrt();
+                r1 * (1. - r2) * a + r1 * r2 * b
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct CameraBuilder {
     origin: Option<Vec3>,
     look_at: Option<Vec3>,
     view_up: Vec3,
     vfov_degrees: f64,
-    aspect_ratio: f64,
+    aspect_ratio: Option<f64>,
     /// Used for depth of field. Set to `0` to disable depth of field.
     aperture: f64,
+    aperture_shape: Aperture,
     /// If None, defaults to magnitude of vector between `origin` and `look_at`.
     focus_dist: Option<f64>,
+    /// Point to focus on instead of `focus_dist`, resolved when the camera is built.
+    focus_point: Option<Vec3>,
     /// Used for motion blur. Set to `None` to disable.
     shutter_time: Option<Range<f64>>,
+    /// Distance from `look_at` when positioned with `orbit`.
+    orbit_radius: Option<f64>,
 }
 impl CameraBuilder {
     pub fn build(&self) -> Result<Camera> {
@@ -114,14 +222,15 @@ impl CameraBuilder {
 
         let origin = self.origin.unwrap();
         let look_at = self.look_at.unwrap();
+        let aspect_ratio = self.aspect_ratio.unwrap();
 
         let lens_radius = self.aperture / 2.;
-        let focus_dist = self.focus_dist.unwrap_or_else(|| (origin - look_at).norm());
+        let focus_dist = self.resolved_focus_dist(origin, look_at);
         let shutter_time = self.shutter_time.clone().map(Uniform::from);
 
         let theta = self.vfov_degrees.to_radians() / 2.;
         let half_height = focus_dist * theta.tan();
-        let half_width = self.aspect_ratio * half_height;
+        let half_width = aspect_ratio * half_height;
 
         // Project view_up onto the plane of the camera and form the orthonormal basis.
         let view_up = Vec3::checked_normalized(self.view_up).unwrap();
@@ -139,13 +248,37 @@ impl CameraBuilder {
             vert,
             lower_left,
             lens_radius,
+            aperture_shape: self.aperture_shape,
             shutter_time,
+            time: 0.,
             u,
             v,
             w,
         })
     }
 
+    /// Builds the camera at time `t` (from `0.0` to `1.0`) of a move from this builder's
+    /// origin and look_at to `end_origin` and `end_look_at`. `None` keeps that part fixed.
+    /// Without a shutter time, the camera's rays are also at time `t`, which animates textures
+    /// like `PerlinNoise4`.
+    pub fn build_between(
+        &self,
+        end_origin: Option<Vec3>,
+        end_look_at: Option<Vec3>,
+        t: f64,
+    ) -> Result<Camera> {
+        let mut builder = self.clone();
+        if let (Some(start), Some(end)) = (self.origin, end_origin) {
+            builder.origin(start.lerp(end, t));
+        }
+        if let (Some(start), Some(end)) = (self.look_at, end_look_at) {
+            builder.look_at(start.lerp(end, t));
+        }
+        let mut camera = builder.build()?;
+        camera.time = t;
+        Ok(camera)
+    }
+
     /// Deal with bad camera configurations.
     pub fn verify(&self) -> Result<()> {
         // Make sure that required parameters were set
@@ -155,6 +288,9 @@ impl CameraBuilder {
         let look_at = self
             .look_at
             .ok_or_else(|| anyhow!("Camera's look_at wasn't provided."))?;
+        let aspect_ratio = self
+            .aspect_ratio
+            .ok_or_else(|| anyhow!("Camera's aspect_ratio wasn't provided."))?;
 
         // Error if the view_up vector has length 0.
         let view_up = Vec3::checked_normalized(self.view_up)
@@ -178,13 +314,19 @@ impl CameraBuilder {
 
         // Aperture can be 0 to disable depth of field
         ensure!(self.aperture >= 0., "Camera's aperture is less than 0.");
+        if let Aperture::Polygon { sides } = self.aperture_shape {
+            ensure!(
+                sides >= 3,
+                "Camera's aperture polygon has less than 3 sides."
+            );
+        }
 
         ensure!(
             self.vfov_degrees > 0.,
             "Camera's fov is less than or equal to 0."
         );
         ensure!(
-            self.aspect_ratio > 0.,
+            aspect_ratio > 0.,
             "Camera's aspect ratio is less than or equal to 0."
         );
         if let Some(dist) = self.focus_dist {
@@ -193,13 +335,38 @@ impl CameraBuilder {
                 "Camera's focus distance is less than or equal to 0."
             );
         }
+        if let Some(point) = self.focus_point {
+            ensure!(
+                self.resolved_focus_dist(origin, look_at) > 0.,
+                "Camera's focus point isn't in front of the camera.\nPoint: {:?}",
+                point
+            );
+        }
+        if let Some(radius) = self.orbit_radius {
+            ensure!(
+                radius > 0.,
+                "Camera's orbit radius is less than or equal to 0."
+            );
+        }
 
         Ok(())
     }
 
+    /// The distance to the plane of focus. A focus point's distance is measured along the view
+    /// direction, so that an off-center point lies in the plane.
+    fn resolved_focus_dist(&self, origin: Vec3, look_at: Vec3) -> f64 {
+        let forward = look_at - origin;
+        match (self.focus_dist, self.focus_point) {
+            (Some(dist), _) => dist,
+            (None, Some(point)) => (point - origin).dot(forward) / forward.norm(),
+            (None, None) => forward.norm(),
+        }
+    }
+
     // ===== Builder Methods =====
     pub fn origin<T: Into<Vec3>>(&mut self, origin: T) -> &mut Self {
         self.origin = Some(origin.into());
+        self.orbit_radius = None;
         self
     }
     pub fn look_at<T: Into<Vec3>>(&mut self, look_at: T) -> &mut Self {
@@ -211,9 +378,13 @@ impl CameraBuilder {
         self
     }
     pub fn aspect_ratio(&mut self, aspect_ratio: f64) -> &mut Self {
-        self.aspect_ratio = aspect_ratio;
+        self.aspect_ratio = Some(aspect_ratio);
         self
     }
+    /// Set the aspect ratio to match an image of the given dimensions.
+    pub fn aspect_from(&mut self, width: usize, height: usize) -> &mut Self {
+        self.aspect_ratio(width as f64 / height as f64)
+    }
     pub fn view_up<T: Into<Vec3>>(&mut self, view_up: T) -> &mut Self {
         self.view_up = view_up.into();
         self
@@ -226,6 +397,7 @@ impl CameraBuilder {
     /// let c = Camera::builder()
     ///     .origin([0., 20., 0.])
     ///     .look_at([0., 10., 0.])
+    ///     .aspect_ratio(16. / 9.)
     ///     .view_up_degrees(15., Axis::Z)
     ///     .build()
     ///     .unwrap();
@@ -242,14 +414,76 @@ impl CameraBuilder {
         });
         self
     }
+    /// Place the camera `radius` away from `target` and look at it. `azimuth_deg` is measured
+    /// around the y axis from `+x` towards `+z`, and `elevation_deg` is the angle above the xz
+    /// plane.
+    /// # Example
+    /// ```
+    /// # use raytracing::{Camera, Vec3};
+    /// let c = Camera::builder()
+    ///     .orbit([0., 1., 0.], 10., 90., 0.)
+    ///     .aspect_ratio(16. / 9.)
+    ///     .build()
+    ///     .unwrap();
+    /// assert!((c.origin - Vec3::new(0., 1., 10.)).norm() < 1e-9);
+    /// ```
+    pub fn orbit<T: Into<Vec3>>(
+        &mut self,
+        target: T,
+        radius: f64,
+        azimuth_deg: f64,
+        elevation_deg: f64,
+    ) -> &mut Self {
+        let target = target.into();
+        let (sin_az, cos_az) = azimuth_deg.to_radians().sin_cos();
+        let (sin_el, cos_el) = elevation_deg.to_radians().sin_cos();
+        let offset = Vec3::new(cos_el * cos_az, sin_el, cos_el * sin_az);
+        self.origin(target + radius * offset).look_at(target);
+        self.orbit_radius = Some(radius);
+        self
+    }
+    /// The arguments to `orbit` that place the camera at its current `origin` looking at
+    /// `look_at`, i.e. the target, radius, azimuth and elevation. `None` if either isn't set.
+    pub fn orbit_params(&self) -> Option<(Vec3, f64, f64, f64)> {
+        let (origin, target) = (self.origin?, self.look_at?);
+        let offset = origin - target;
+        let radius = offset.norm();
+        let azimuth_deg = offset.z.atan2(offset.x).to_degrees();
+        let elevation_deg = (offset.y / radius).asin().to_degrees();
+        Some((target, radius, azimuth_deg, elevation_deg))
+    }
     /// Used for depth of field. Set to `None` to disable depth of field.
     pub fn aperture(&mut self, aperture: f64) -> &mut Self {
         self.aperture = aperture;
         self
     }
+    pub fn aperture_shape(&mut self, shape: Aperture) -> &mut Self {
+        self.aperture_shape = shape;
+        self
+    }
     /// If None, defaults to magnitude of vector between `origin` and `look_at`.
     pub fn focus_dist<T: Into<Option<f64>>>(&mut self, dist: T) -> &mut Self {
         self.focus_dist = dist.into();
+        self.focus_point = None;
+        self
+    }
+    /// Focus on `point`, wherever `origin` and `look_at` end up. Replaces `focus_dist`.
+    /// # Example
+    /// ```
+    /// # use raytracing::Camera;
+    /// let c = Camera::builder()
+    ///     .origin([0., 0., 10.])
+    ///     .look_at([0., 0., 0.])
+    ///     .focus_on([3., 1., 4.])
+    ///     .aspect_ratio(1.)
+    ///     .build()
+    ///     .unwrap();
+    /// // The plane of focus is 6 units in front of the camera
+    /// assert!((c.lower_left.z - 4.).abs() < 1e-9);
+    /// ```
+    pub fn focus_on<T: Into<Vec3>>(&mut self, point: T) -> &mut Self {
+        self.focus_point = Some(point.into());
+        self.focus_dist = None;
         self
     }
     /// Used for motion blur. Set to `None` to disable.
@@ -260,17 +494,142 @@ impl CameraBuilder {
 }
 impl Default for CameraBuilder {
     fn default() -> Self {
-        let width = config::GLOBAL().width.get() as f64;
-        let height = config::GLOBAL().height.get() as f64;
         Self {
             origin: None,
             look_at: None,
             view_up: Vec3::UNIT_Y,
             vfov_degrees: 60.,
-            aspect_ratio: width / height,
+            aspect_ratio: None,
             aperture: 0.,
+            aperture_shape: Aperture::Circle,
             focus_dist: None,
+            focus_point: None,
             shutter_time: None,
+            orbit_radius: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod screen_test {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_screen() {
+        let (width, height) = (TILE_SIZE * 2 + 5, TILE_SIZE + 1);
+        let mut screen = Screen::new(width, height);
+        let tiles = screen.tiles_mut();
+        assert_eq!(tiles.len(), 6);
+        for tile in tiles {
+            for (x, y, pix) in tile.into_pixels() {
+                // Every pixel should only be visited once
+                assert_eq!(pix.b, 0.);
+                *pix = Color::new(x as f64, y as f64, 1.);
+            }
+        }
+
+        for (i, pix) in screen.buffer.iter().enumerate() {
+            let (x, y) = ((i % width) as f64, (i / width) as f64);
+            assert_eq!((pix.r, pix.g, pix.b), (x, y, 1.));
+        }
+    }
+}
+
+#[cfg(test)]
+mod camera_test {
+    use super::*;
+
+    #[test]
+    fn aspect_ratio_required() {
+        let mut builder = Camera::builder();
+        builder.origin([0., 0., 1.]).look_at([0., 0., 0.]);
+        assert!(builder.build().is_err());
+        assert!(builder.aspect_from(200, 100).build().is_ok());
+    }
+
+    #[test]
+    fn aspect_from() {
+        let camera = Camera::builder()
+            .origin([0., 0., 1.])
+            .look_at([0., 0., 0.])
+            .aspect_from(200, 100)
+            .build()
+            .unwrap();
+        let ratio = camera.horiz.norm() / camera.vert.norm();
+        assert!((ratio - 2.).abs() < 1e-12, "{}", ratio);
+    }
+
+    #[test]
+    fn orbit() {
+        let target = Vec3::new(1., 2., 3.);
+        let mut builder = Camera::builder();
+        builder.aspect_ratio(1.).orbit(target, 5., 0., 90. - 1e-6);
+        let camera = builder.build().unwrap();
+        assert!(((camera.origin - target).norm() - 5.).abs() < 1e-9);
+        assert!(camera.origin.y - target.y > 5. - 1e-9);
+
+        builder.orbit(target, 2., 180., 0.);
+        let camera = builder.build().unwrap();
+        assert!((camera.origin - Vec3::new(-1., 2., 3.)).norm() < 1e-9);
+
+        assert!(builder.orbit(target, -1., 0., 0.).build().is_err());
+    }
+
+    #[test]
+    fn orbit_params() {
+        let mut builder = Camera::builder();
+        assert!(builder.orbit_params().is_none());
+        builder
+            .aspect_ratio(1.)
+            .origin([3., 4., -2.])
+            .look_at([1., 2., 3.]);
+        let (target, radius, azimuth, elevation) = builder.orbit_params().unwrap();
+        let camera = builder
+            .orbit(target, radius, azimuth, elevation)
+            .build()
+            .unwrap();
+        assert!((camera.origin - Vec3::new(3., 4., -2.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn focus_on() {
+        // Distance from the camera to the plane of focus, since the camera looks along -z
+        let focus = |builder: &CameraBuilder| {
+            let camera = builder.build().unwrap();
+            camera.origin.z - camera.lower_left.z
+        };
+        let mut builder = Camera::builder();
+        builder
+            .aspect_ratio(1.)
+            .origin([0., 0., 5.])
+            .look_at([0., 0., 0.])
+            .focus_on([1., 2., -1.]);
+        assert!((focus(&builder) - 6.).abs() < 1e-9);
+        // The point stays in focus when the camera moves
+        builder.origin([0., 0., 9.]);
+        assert!((focus(&builder) - 10.).abs() < 1e-9);
+        // and the last of focus_on and focus_dist wins
+        builder.focus_dist(3.);
+        assert!((focus(&builder) - 3.).abs() < 1e-9);
+
+        assert!(builder.focus_on([0., 0., 20.]).build().is_err());
+    }
+
+    #[test]
+    fn polygon_aperture() {
+        use rand::SeedableRng;
+        let sides = 6;
+        let aperture = Aperture::Polygon { sides };
+        let mut rng = CrateRng::seed_from_u64(0);
+        // The distance from the center to the middle of each edge
+        let apothem = (consts::PI / sides as f64).cos();
+        for _ in 0..10_000 {
+            let point = aperture.sample(&mut rng);
+            for side in 0..sides {
+                let angle = consts::FRAC_PI_2 + (side as f64 + 0.5) * 2. * consts::PI / 6.;
+                let (sin, cos) = angle.sin_cos();
+                assert!(point.x * cos + point.y * sin <= apothem + 1e-12);
+            }
         }
     }
 }
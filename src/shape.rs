@@ -1,8 +1,29 @@
 use std::f64::consts::PI;
 use std::fmt::Debug;
 use std::ops::Range;
+use std::sync::Arc;
 
-use crate::{Hit, Hittable, Material, Ray, Vec3, AABB};
+use rand::Rng;
+
+use crate::material::bump_normal;
+use crate::vec3::Onb;
+use crate::{Axis, CrateRng, Hit, HitList, Hittable, Material, Ray, TexCoord, Texture, Vec3, AABB};
+
+/// Range of hit times used when checking whether a sampled direction hits a `Hittable`.
+const SAMPLE_HIT_TIME: Range<f64> = 0.001..f64::INFINITY;
+
+/// Converts the probability density of uniformly sampling a point on a surface of `area` into a
+/// density with respect to the solid angle seen from the ray's origin.
+fn area_pdf(hit: Option<Hit>, dir: Vec3, area: f64) -> f64 {
+    match hit {
+        Some(hit) => {
+            let distance_squared = hit.time.powi(2) * dir.norm_squared();
+            let cos = hit.normal.dot(dir).abs() / dir.norm();
+            distance_squared / (cos * area)
+        }
+        None => 0.,
+    }
+}
 
 fn sphere_uv(point: Vec3, center: Vec3, radius: f64) -> (f64, f64) {
     let p: Vec3 = (point - center) / radius;
@@ -32,7 +53,7 @@ impl<T> Sphere<T> {
     }
 }
 impl<T: Material> Hittable for Sphere<T> {
-    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
         let oc = ray.origin - self.center;
         let a = ray.dir.norm_squared();
         let half_b = oc.dot(ray.dir);
@@ -68,6 +89,33 @@ impl<T: Material> Hittable for Sphere<T> {
         let rad = Vec3::from([self.radius; 3]);
         Some(AABB::new(self.center - rad, self.center + rad))
     }
+
+    /// Directions are sampled uniformly from the cone that the sphere subtends.
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        if self
+            .hit(&Ray::new(origin, dir, 0.), &SAMPLE_HIT_TIME)
+            .is_none()
+        {
+            return 0.;
+        }
+        let distance_squared = (self.center - origin).norm_squared();
+        if distance_squared <= self.radius.powi(2) {
+            // Inside the sphere every direction hits it.
+            return 1. / (4. * PI);
+        }
+        let cos_theta_max = (1. - self.radius.powi(2) / distance_squared).sqrt();
+        1. / (2. * PI * (1. - cos_theta_max))
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.norm_squared();
+        if distance_squared <= self.radius.powi(2) {
+            return Vec3::rand_unit_sphere(rng);
+        }
+        let cos_theta_max = (1. - self.radius.powi(2) / distance_squared).sqrt();
+        Onb::from_w(direction).local(Vec3::rand_cone_direction(rng, cos_theta_max))
+    }
 }
 
 /// Sphere whose center moves from `center_0` (at `time = 0.0`) to `center_1` (at `time = 1.0`).
@@ -98,7 +146,7 @@ impl<T> MovingSphere<T> {
     }
 }
 impl<T: Material> Hittable for MovingSphere<T> {
-    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
         let center = self.center(ray.time);
 
         let oc = ray.origin - center;
@@ -145,12 +193,594 @@ impl<T: Material> Hittable for MovingSphere<T> {
     }
 }
 
+/// Ellipsoid centered at `center` with semi-axes of length `radii` along `X`, `Y`, and `Z`.
+#[derive(Debug)]
+pub struct Ellipsoid<T> {
+    pub center: Vec3,
+    pub radii: Vec3,
+    pub material: T,
+}
+impl<T> Ellipsoid<T> {
+    pub fn new(center: Vec3, radii: Vec3, material: T) -> Self {
+        Self {
+            center,
+            radii,
+            material,
+        }
+    }
+
+    pub fn from(c: [f64; 3], radii: [f64; 3], material: T) -> Self {
+        Self::new(c.into(), radii.into(), material)
+    }
+}
+impl<T: Material> Hittable for Ellipsoid<T> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        // Intersect the unit sphere with the transformed ray. The transform is linear, so `t`
+        // is the same in both spaces.
+        let oc = (ray.origin - self.center) / self.radii;
+        let dir = ray.dir / self.radii;
+        let a = dir.norm_squared();
+        let half_b = oc.dot(dir);
+        let c = oc.norm_squared() - 1.;
+        let disciminant = half_b.powi(2) - a * c;
+
+        if disciminant >= 0. {
+            let root = disciminant.sqrt();
+            let hit = |t| {
+                let point = ray.at(t);
+                let unit_point = (point - self.center) / self.radii;
+                // Normals transform by the inverse transpose, i.e. divide by the radii again.
+                let outward_normal = Vec3::normalized(unit_point / self.radii);
+                let (u, v) = sphere_uv(unit_point, Vec3::ORIGIN, 1.);
+
+                let ret = Hit::ray(point, outward_normal, t, ray, &self.material, u, v);
+                Some(ret)
+            };
+
+            let t = (-half_b - root) / a;
+            if hit_time.contains(&t) {
+                return hit(t);
+            }
+
+            let t = (-half_b + root) / a;
+            if hit_time.contains(&t) {
+                return hit(t);
+            }
+        }
+
+        None
+    }
+
+    fn bounding_box(&self, _shutter_time: &Range<f64>) -> Option<AABB> {
+        Some(AABB::new(
+            self.center - self.radii,
+            self.center + self.radii,
+        ))
+    }
+}
+
+/// Triangle whose front face is the side from which its vertices appear counterclockwise.
+/// The `u` and `v` of a `Hit` are the barycentric weights of `vertices[1]` and `vertices[2]`.
+#[derive(Debug)]
+pub struct Triangle<T> {
+    pub vertices: [Vec3; 3],
+    pub material: T,
+    /// Ignore hits on the back face. Must be off for objects that are refracted through,
+    /// e.g. `Dielectric`, since rays exit through the back face.
+    pub cull_backfaces: bool,
+    /// Normals at the vertices, which are interpolated across the triangle for smooth shading.
+    /// `None` shades the triangle flat.
+    pub normals: Option<[Vec3; 3]>,
+}
+impl<T> Triangle<T> {
+    /// Used to reject rays that are parallel to the triangle.
+    const EPSILON: f64 = 1e-9;
+
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: T) -> Self {
+        Self {
+            vertices: [v0, v1, v2],
+            material,
+            cull_backfaces: false,
+            normals: None,
+        }
+    }
+
+    pub fn from(v0: [f64; 3], v1: [f64; 3], v2: [f64; 3], material: T) -> Self {
+        Self::new(v0.into(), v1.into(), v2.into(), material)
+    }
+
+    pub fn cull_backfaces(mut self, cull: bool) -> Self {
+        self.cull_backfaces = cull;
+        self
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+}
+impl<T: Material> Hittable for Triangle<T> {
+    /// Möller–Trumbore intersection
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+
+        let p = ray.dir.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < Self::EPSILON {
+            return None;
+        }
+        // `det` is `-ray.dir.dot(edge1.cross(edge2))`, so it's negative for back face hits.
+        if self.cull_backfaces && det < 0. {
+            return None;
+        }
+        let inv_det = 1. / det;
+
+        let s = ray.origin - v0;
+        let u = s.dot(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = ray.dir.dot(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        if !hit_time.contains(&t) {
+            return None;
+        }
+
+        let outward_normal = Vec3::normalized(edge1.cross(edge2));
+        let [n0, n1, n2] = match self.normals {
+            Some(normals) => normals,
+            None => {
+                return Some(Hit::ray(
+                    ray.at(t),
+                    outward_normal,
+                    t,
+                    ray,
+                    &self.material,
+                    u,
+                    v,
+                ))
+            }
+        };
+        // The side that was hit comes from the flat normal, since the interpolated normal can
+        // face the ray at grazing angles.
+        let front_face = det > 0.;
+        // Opposing vertex normals can cancel out, so fall back to the flat normal
+        let smooth =
+            Vec3::checked_normalized((1. - u - v) * n0 + u * n1 + v * n2).unwrap_or(outward_normal);
+        let normal = if front_face { smooth } else { -smooth };
+        Some(Hit::new(
+            ray.at(t),
+            normal,
+            t,
+            front_face,
+            &self.material,
+            u,
+            v,
+        ))
+    }
+
+    fn bounding_box(&self, _shutter_time: &Range<f64>) -> Option<AABB> {
+        // Pad the box so that axis-aligned triangles don't have a box of zero thickness.
+        let pad = Vec3::from([1e-4; 3]);
+        let [v0, v1, v2] = self.vertices;
+        let min = v0.min(v1).min(v2);
+        let max = v0.max(v1).max(v2);
+        Some(AABB::new(min - pad, max + pad))
+    }
+}
+
+/// Intersects a ray with the rectangle lying in the plane where `point[normal] = k`, bounded by
+/// `a_range` along `a` and `b_range` along `b`. The outward normal points along `+normal`.
+fn rect_hit<'a>(
+    ray: &Ray,
+    hit_time: &Range<f64>,
+    (a, a_range): (Axis, &Range<f64>),
+    (b, b_range): (Axis, &Range<f64>),
+    normal: Axis,
+    k: f64,
+    material: &'a dyn Material,
+) -> Option<Hit<'a>> {
+    let t = (k - ray.origin[normal]) / ray.dir[normal];
+    if !hit_time.contains(&t) {
+        return None;
+    }
+
+    let point = ray.at(t);
+    if !a_range.contains(&point[a]) || !b_range.contains(&point[b]) {
+        return None;
+    }
+
+    let u = (point[a] - a_range.start) / (a_range.end - a_range.start);
+    let v = (point[b] - b_range.start) / (b_range.end - b_range.start);
+    let mut outward_normal = Vec3::ORIGIN;
+    outward_normal[normal] = 1.;
+    Some(Hit::ray(point, outward_normal, t, ray, material, u, v))
+}
+
+/// Uniformly samples a point on a rectangle with the same layout as in `rect_hit`.
+fn rect_random_point(
+    (a, a_range): (Axis, &Range<f64>),
+    (b, b_range): (Axis, &Range<f64>),
+    normal: Axis,
+    k: f64,
+    rng: &mut CrateRng,
+) -> Vec3 {
+    let mut point = Vec3::ORIGIN;
+    point[a] = rng.gen_range(a_range.start, a_range.end);
+    point[b] = rng.gen_range(b_range.start, b_range.end);
+    point[normal] = k;
+    point
+}
+
+fn rect_area(a_range: &Range<f64>, b_range: &Range<f64>) -> f64 {
+    (a_range.end - a_range.start) * (b_range.end - b_range.start)
+}
+
+/// Bounding box of a rectangle, padded along the normal so that it isn't infinitely thin.
+fn rect_bounding_box(
+    (a, a_range): (Axis, &Range<f64>),
+    (b, b_range): (Axis, &Range<f64>),
+    normal: Axis,
+    k: f64,
+) -> AABB {
+    let (mut min, mut max) = (Vec3::ORIGIN, Vec3::ORIGIN);
+    min[a] = a_range.start;
+    max[a] = a_range.end;
+    min[b] = b_range.start;
+    max[b] = b_range.end;
+    min[normal] = k - 1e-4;
+    max[normal] = k + 1e-4;
+    AABB::new(min, max)
+}
+
+/// Rectangle parallel to the `XY` plane at `z = k`, facing `+Z`.
+#[derive(Debug)]
+pub struct XYRect<T> {
+    pub x: Range<f64>,
+    pub y: Range<f64>,
+    pub k: f64,
+    pub material: T,
+}
+impl<T> XYRect<T> {
+    pub fn new(x: Range<f64>, y: Range<f64>, k: f64, material: T) -> Self {
+        Self { x, y, k, material }
+    }
+}
+impl<T: Material> Hittable for XYRect<T> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let (a, b) = ((Axis::X, &self.x), (Axis::Y, &self.y));
+        rect_hit(ray, hit_time, a, b, Axis::Z, self.k, &self.material)
+    }
+
+    fn bounding_box(&self, _shutter_time: &Range<f64>) -> Option<AABB> {
+        let (a, b) = ((Axis::X, &self.x), (Axis::Y, &self.y));
+        Some(rect_bounding_box(a, b, Axis::Z, self.k))
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        let hit = self.hit(&Ray::new(origin, dir, 0.), &SAMPLE_HIT_TIME);
+        area_pdf(hit, dir, rect_area(&self.x, &self.y))
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        let (a, b) = ((Axis::X, &self.x), (Axis::Y, &self.y));
+        rect_random_point(a, b, Axis::Z, self.k, rng) - origin
+    }
+}
+
+/// Rectangle parallel to the `XZ` plane at `y = k`, facing `+Y`.
+#[derive(Debug)]
+pub struct XZRect<T> {
+    pub x: Range<f64>,
+    pub z: Range<f64>,
+    pub k: f64,
+    pub material: T,
+}
+impl<T> XZRect<T> {
+    pub fn new(x: Range<f64>, z: Range<f64>, k: f64, material: T) -> Self {
+        Self { x, z, k, material }
+    }
+}
+impl<T: Material> Hittable for XZRect<T> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let (a, b) = ((Axis::X, &self.x), (Axis::Z, &self.z));
+        rect_hit(ray, hit_time, a, b, Axis::Y, self.k, &self.material)
+    }
+
+    fn bounding_box(&self, _shutter_time: &Range<f64>) -> Option<AABB> {
+        let (a, b) = ((Axis::X, &self.x), (Axis::Z, &self.z));
+        Some(rect_bounding_box(a, b, Axis::Y, self.k))
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        let hit = self.hit(&Ray::new(origin, dir, 0.), &SAMPLE_HIT_TIME);
+        area_pdf(hit, dir, rect_area(&self.x, &self.z))
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        let (a, b) = ((Axis::X, &self.x), (Axis::Z, &self.z));
+        rect_random_point(a, b, Axis::Y, self.k, rng) - origin
+    }
+}
+
+/// Rectangle parallel to the `YZ` plane at `x = k`, facing `+X`.
+#[derive(Debug)]
+pub struct YZRect<T> {
+    pub y: Range<f64>,
+    pub z: Range<f64>,
+    pub k: f64,
+    pub material: T,
+}
+impl<T> YZRect<T> {
+    pub fn new(y: Range<f64>, z: Range<f64>, k: f64, material: T) -> Self {
+        Self { y, z, k, material }
+    }
+}
+impl<T: Material> Hittable for YZRect<T> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let (a, b) = ((Axis::Y, &self.y), (Axis::Z, &self.z));
+        rect_hit(ray, hit_time, a, b, Axis::X, self.k, &self.material)
+    }
+
+    fn bounding_box(&self, _shutter_time: &Range<f64>) -> Option<AABB> {
+        let (a, b) = ((Axis::Y, &self.y), (Axis::Z, &self.z));
+        Some(rect_bounding_box(a, b, Axis::X, self.k))
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        let hit = self.hit(&Ray::new(origin, dir, 0.), &SAMPLE_HIT_TIME);
+        area_pdf(hit, dir, rect_area(&self.y, &self.z))
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        let (a, b) = ((Axis::Y, &self.y), (Axis::Z, &self.z));
+        rect_random_point(a, b, Axis::X, self.k, rng) - origin
+    }
+}
+
+/// Axis-aligned box between the corners `min` and `max`, made of six rectangles sharing one
+/// material.
+#[derive(Debug)]
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+    sides: HitList,
+}
+impl Cuboid {
+    pub fn new<T: Material + Send + 'static>(min: Vec3, max: Vec3, material: T) -> Self {
+        let material = Arc::new(material);
+        let (x, y, z) = (min.x..max.x, min.y..max.y, min.z..max.z);
+        let mut sides = HitList::new();
+        sides.push(XYRect::new(x.clone(), y.clone(), min.z, material.clone()));
+        sides.push(XYRect::new(x.clone(), y.clone(), max.z, material.clone()));
+        sides.push(XZRect::new(x.clone(), z.clone(), min.y, material.clone()));
+        sides.push(XZRect::new(x, z.clone(), max.y, material.clone()));
+        sides.push(YZRect::new(y.clone(), z.clone(), min.x, material.clone()));
+        sides.push(YZRect::new(y, z, max.x, material));
+        Self { min, max, sides }
+    }
+    pub fn from<T: Material + Send + 'static>(min: [f64; 3], max: [f64; 3], material: T) -> Self {
+        Self::new(min.into(), max.into(), material)
+    }
+}
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let mut hit = self.sides.hit(ray, hit_time)?;
+        // Every rectangle faces the positive end of its axis, so the front face of the sides at
+        // `min` would point into the box. The normal already points against the ray, so the ray
+        // hit the outside if the normal points away from the center.
+        let center = 0.5 * (self.min + self.max);
+        hit.front_face = hit.normal.dot(hit.point - center) > 0.;
+        Some(hit)
+    }
+
+    fn bounding_box(&self, _shutter_time: &Range<f64>) -> Option<AABB> {
+        Some(AABB::new(self.min, self.max))
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.sides.pdf_value(origin, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.sides.random_dir(origin, rng)
+    }
+}
+
+/// Moves a `Hittable` by `offset`.
+#[derive(Debug)]
+pub struct Translate<H> {
+    pub inner: H,
+    pub offset: Vec3,
+}
+impl<H> Translate<H> {
+    pub fn new(inner: H, offset: Vec3) -> Self {
+        Self { inner, offset }
+    }
+}
+impl<H: Hittable> Hittable for Translate<H> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let moved = Ray {
+            origin: ray.origin - self.offset,
+            ..ray.clone()
+        };
+        let mut hit = self.inner.hit(&moved, hit_time)?;
+        hit.point += self.offset;
+        Some(hit)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        let bound = self.inner.bounding_box(shutter_time)?;
+        Some(AABB::new(bound.min + self.offset, bound.max + self.offset))
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.inner.pdf_value(origin - self.offset, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.inner.random_dir(origin - self.offset, rng)
+    }
+}
+
+/// Rotates a `Hittable` counterclockwise around the `Y` axis, when looking down from `+Y`.
+#[derive(Debug)]
+pub struct RotateY<H> {
+    pub inner: H,
+    sin: f64,
+    cos: f64,
+}
+impl<H> RotateY<H> {
+    pub fn new(inner: H, degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self { inner, sin, cos }
+    }
+
+    /// Rotates from the inner `Hittable`'s space to world space.
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos * v.x + self.sin * v.z,
+            v.y,
+            self.cos * v.z - self.sin * v.x,
+        )
+    }
+
+    /// Rotates from world space to the inner `Hittable`'s space.
+    fn unrotate(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos * v.x - self.sin * v.z,
+            v.y,
+            self.cos * v.z + self.sin * v.x,
+        )
+    }
+}
+impl<H: Hittable> Hittable for RotateY<H> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let rotated = Ray {
+            origin: self.unrotate(ray.origin),
+            dir: self.unrotate(ray.dir),
+            ..ray.clone()
+        };
+        let mut hit = self.inner.hit(&rotated, hit_time)?;
+        hit.point = self.rotate(hit.point);
+        hit.normal = self.rotate(hit.normal);
+        Some(hit)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        let bound = self.inner.bounding_box(shutter_time)?;
+        // Bound the rotated corners of the inner box. The rotation doesn't change `y`.
+        let mut min = Vec3::new(f64::INFINITY, bound.min.y, f64::INFINITY);
+        let mut max = Vec3::new(f64::NEG_INFINITY, bound.max.y, f64::NEG_INFINITY);
+        for &x in &[bound.min.x, bound.max.x] {
+            for &z in &[bound.min.z, bound.max.z] {
+                let corner = self.rotate(Vec3::new(x, bound.min.y, z));
+                min = min.min(corner);
+                max = max.max(corner);
+            }
+        }
+        Some(AABB::new(min, max))
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.inner
+            .pdf_value(self.unrotate(origin), self.unrotate(dir))
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.rotate(self.inner.random_dir(self.unrotate(origin), rng))
+    }
+}
+
+/// Replaces the material of everything in a `Hittable`, e.g. to render a whole scene with a
+/// debugging material.
+#[derive(Debug)]
+pub struct OverrideMaterial<H, M> {
+    pub inner: H,
+    pub material: M,
+}
+impl<H, M> OverrideMaterial<H, M> {
+    pub fn new(inner: H, material: M) -> Self {
+        Self { inner, material }
+    }
+}
+impl<H: Hittable, M: Material> Hittable for OverrideMaterial<H, M> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let hit = self.inner.hit(ray, hit_time)?;
+        Some(Hit {
+            material: &self.material,
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        self.inner.bounding_box(shutter_time)
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.inner.pdf_value(origin, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.inner.random_dir(origin, rng)
+    }
+}
+
+/// Wraps a `Hittable` and perturbs its normals using the slope of a height texture, making the
+/// surface look bumpy without changing its geometry.
+#[derive(Debug)]
+pub struct Bumpy<H, T> {
+    pub inner: H,
+    /// The luminance of the texture is used as the height.
+    pub height: T,
+    pub strength: f64,
+}
+impl<H, T> Bumpy<H, T> {
+    pub fn new(inner: H, height: T, strength: f64) -> Self {
+        Self {
+            inner,
+            height,
+            strength,
+        }
+    }
+}
+impl<H: Hittable, T: Texture> Hittable for Bumpy<H, T> {
+    fn hit(&self, ray: &Ray, hit_time: &Range<f64>) -> Option<Hit<'_>> {
+        let mut hit = self.inner.hit(ray, hit_time)?;
+        // The perturbed normal stays on the same side of the surface, so `front_face` remains
+        // correct.
+        let coord = TexCoord::from_hit(ray, &hit);
+        hit.normal = bump_normal(&self.height, self.strength, &coord);
+        Some(hit)
+    }
+
+    fn bounding_box(&self, shutter_time: &Range<f64>) -> Option<AABB> {
+        self.inner.bounding_box(shutter_time)
+    }
+
+    fn pdf_value(&self, origin: Vec3, dir: Vec3) -> f64 {
+        self.inner.pdf_value(origin, dir)
+    }
+
+    fn random_dir(&self, origin: Vec3, rng: &mut CrateRng) -> Vec3 {
+        self.inner.random_dir(origin, rng)
+    }
+}
+
 /// Dummy Hittable for use in BVH node
 #[derive(Debug)]
 pub struct Dummy {}
 impl Hittable for Dummy {
     /// Dummy will never return a hit.
-    fn hit(&self, _ray: &Ray, _hit_time: &Range<f64>) -> Option<Hit> {
+    fn hit(&self, _ray: &Ray, _hit_time: &Range<f64>) -> Option<Hit<'_>> {
         None
     }
 
@@ -159,3 +789,308 @@ impl Hittable for Dummy {
         unimplemented!("Hittable::bounding_box is not applicable for Dummy!")
     }
 }
+
+#[cfg(test)]
+mod shape_test {
+    use super::*;
+    use crate::material::{DbgBlack, Gradient, SharedMaterial};
+    use crate::{Color, HitList};
+    use rand::SeedableRng;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).norm() < EPSILON, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn bumpy_tilts_against_the_height_gradient() {
+        let floor = || XZRect::new(-5.0..5., -5.0..5., 0., DbgBlack {});
+        let ray = Ray::from([0.5, 1., 0.5], [0., -1., 0.], 0.);
+        let normal = |bumpy: &dyn Hittable| bumpy.hit(&ray, &(0.0..f64::INFINITY)).unwrap().normal;
+
+        // Rising by one unit per unit along x
+        let slope = Gradient::new([0., 0., 0.], [1., 1., 1.], Axis::X, 0.0..1.);
+        let bumpy = Bumpy::new(floor(), slope, 1.);
+        assert_close(normal(&bumpy), Vec3::normalized(Vec3::new(-1., 1., 0.)));
+
+        let flat = Bumpy::new(floor(), Color::new(0.5, 0.5, 0.5), 1.);
+        assert_close(normal(&flat), Vec3::UNIT_Y);
+    }
+
+    #[test]
+    fn hitlist_collects() {
+        let mut list: HitList = (0..3)
+            .map(|i| Box::new(Sphere::from([i as f64, 0., 0.], 0.5, DbgBlack {})) as _)
+            .collect();
+        assert_eq!(list.len(), 3);
+        list.extend(std::iter::once(Sphere::from([0., 5., 0.], 1., DbgBlack {})));
+        assert_eq!(list.iter().count(), 4);
+        assert!(!list.is_empty() && HitList::new().is_empty());
+
+        let ray = Ray::from([2., 0., -10.], [0., 0., 1.], 0.);
+        let hit = list.hit(&ray, &(0.0..f64::INFINITY)).unwrap();
+        assert_close(hit.point, Vec3::new(2., 0., -0.5));
+    }
+
+    #[test]
+    fn cuboid_faces_outwards() {
+        let cuboid = Cuboid::from([0., 0., 0.], [1., 2., 3.], DbgBlack {});
+        let rays = [
+            Ray::from([0.5, 1., -5.], [0., 0., 1.], 0.),
+            Ray::from([0.5, 1., 5.], [0., 0., -1.], 0.),
+            Ray::from([-5., 1., 1.], [1., 0., 0.], 0.),
+            Ray::from([0.5, -5., 1.], [0., 1., 0.], 0.),
+        ];
+        for ray in &rays {
+            let hit = cuboid.hit(ray, &(0.0..f64::INFINITY)).unwrap();
+            assert!(hit.front_face, "{:?}", ray.dir);
+        }
+        let inside = Ray::from([0.5, 1., 1.], [0., 0., -1.], 0.);
+        let hit = cuboid.hit(&inside, &(0.0..f64::INFINITY)).unwrap();
+        assert!(!hit.front_face);
+        assert_close(hit.point, Vec3::new(0.5, 1., 0.));
+    }
+
+    #[test]
+    fn rotate_and_translate() {
+        let cuboid = Cuboid::from([0., 0., 0.], [2., 1., 1.], DbgBlack {});
+        let moved = Translate::new(RotateY::new(cuboid, 90.), Vec3::new(10., 0., 0.));
+        // After rotating, the long side runs from z = 0 to z = -2.
+        let ray = Ray::from([10.5, 0.5, 5.], [0., 0., -1.], 0.);
+        let hit = moved.hit(&ray, &(0.0..f64::INFINITY)).unwrap();
+        assert_close(hit.point, Vec3::new(10.5, 0.5, 0.));
+        assert_close(hit.normal, Vec3::new(0., 0., 1.));
+        assert!(hit.front_face);
+        let ray = Ray::from([10.5, 0.5, -5.], [0., 0., 1.], 0.);
+        let hit = moved.hit(&ray, &(0.0..f64::INFINITY)).unwrap();
+        assert_close(hit.point, Vec3::new(10.5, 0.5, -2.));
+
+        let bound = moved.bounding_box(&(0.0..1.)).unwrap();
+        assert_close(bound.min, Vec3::new(10., 0., -2.));
+        assert_close(bound.max, Vec3::new(11., 1., 0.));
+    }
+
+    #[test]
+    fn slice_matches_hitlist() {
+        let shapes: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Sphere::from([0., 0., 0.], 1., DbgBlack {})),
+            Box::new(XYRect::new(-2.0..2., -2.0..2., -3., DbgBlack {})),
+        ];
+        let ray = Ray::from([0.5, 0.5, 10.], [0., 0., -1.], 0.);
+        let hit = shapes[..].hit(&ray, &(0.0..f64::INFINITY)).unwrap();
+        assert_close(hit.point, Vec3::new(0.5, 0.5, 0.5f64.sqrt()));
+        let hit = shapes[1..].hit(&ray, &(0.0..f64::INFINITY)).unwrap();
+        assert_close(hit.point, Vec3::new(0.5, 0.5, -3.));
+
+        let bound = shapes.bounding_box(&(0.0..1.)).unwrap();
+        let list: HitList = shapes.into_iter().collect();
+        let list_bound = list.bounding_box(&(0.0..1.)).unwrap();
+        assert_close(bound.min, list_bound.min);
+        assert_close(bound.max, list_bound.max);
+    }
+
+    #[test]
+    fn parallel_bvh_is_reproducible() {
+        // Long enough to be built in parallel
+        let spheres = || -> HitList {
+            let mut rng = CrateRng::seed_from_u64(0);
+            (0..5000)
+                .map(|_| {
+                    let center = Vec3::new(rng.gen(), rng.gen(), rng.gen());
+                    Box::new(Sphere::new(10. * center, 0.1, DbgBlack {})) as Box<dyn Hittable>
+                })
+                .collect()
+        };
+        let build = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut rng = CrateRng::seed_from_u64(1);
+            pool.install(|| format!("{:?}", spheres().into_bvh(&(0.0..1.), &mut rng)))
+        };
+        assert_eq!(build(1), build(4));
+    }
+
+    #[test]
+    fn aabb_queries() {
+        let bound = AABB::new(Vec3::new(-1., 0., 2.), Vec3::new(1., 3., 6.));
+        assert_eq!(bound.centroid(), Vec3::new(0., 1.5, 4.));
+        // 2 * (2 * 3 + 3 * 4 + 4 * 2)
+        assert_eq!(bound.surface_area(), 52.);
+        assert_eq!(bound.longest_axis(), Axis::Z);
+
+        let flat = AABB::new(Vec3::ORIGIN, Vec3::new(5., 5., 0.));
+        assert_eq!(flat.surface_area(), 50.);
+        assert_eq!(flat.longest_axis(), Axis::X);
+    }
+
+    #[test]
+    fn ellipsoid_matches_sphere() {
+        let sphere = Sphere::from([1., 2., 3.], 2., DbgBlack {});
+        let ellipsoid = Ellipsoid::from([1., 2., 3.], [2.; 3], DbgBlack {});
+        let rays = [
+            Ray::from([1., 2., -10.], [0., 0., 1.], 0.),
+            Ray::from([-5., 0., 0.], [1., 0.4, 0.5], 0.),
+            Ray::from([1., 2., 3.], [0.3, -1., 0.2], 0.),
+            Ray::from([10., 10., 10.], [-1., -1., -1.], 0.),
+        ];
+
+        for ray in &rays {
+            let s = sphere.hit(ray, &(0.001..f64::INFINITY)).unwrap();
+            let e = ellipsoid.hit(ray, &(0.001..f64::INFINITY)).unwrap();
+            assert!((s.time - e.time).abs() < EPSILON);
+            assert_close(s.point, e.point);
+            assert_close(s.normal, e.normal);
+            assert_eq!(s.front_face, e.front_face);
+            assert!((s.u - e.u).abs() < EPSILON && (s.v - e.v).abs() < EPSILON);
+        }
+
+        let miss = Ray::from([1., 5., -10.], [0., 0., 1.], 0.);
+        assert!(ellipsoid.hit(&miss, &(0.001..f64::INFINITY)).is_none());
+    }
+
+    #[test]
+    fn ellipsoid_uv_follows_unit_sphere() {
+        let radii = Vec3::new(3., 1., 2.);
+        let ellipsoid = Ellipsoid::new(Vec3::ORIGIN, radii, DbgBlack {});
+        let sphere = Sphere::from([0., 0., 0.], 1., DbgBlack {});
+        let range = 0.001..f64::INFINITY;
+
+        // Scaling a ray that hits the unit sphere gives a ray that hits the ellipsoid at the
+        // scaled point, which should have the same texture coordinates.
+        for (origin, dir) in &[
+            ([0., 0., 5.], [0., 0., -1.]),
+            ([-4., 1., 2.], [1., -0.2, -0.4]),
+            ([0.1, 5., 0.2], [0., -1., 0.]),
+        ] {
+            let ray = Ray::from(*origin, *dir, 0.);
+            let scaled = Ray::new(ray.origin * radii, ray.dir * radii, 0.);
+            let s = sphere.hit(&ray, &range).unwrap();
+            let e = ellipsoid.hit(&scaled, &range).unwrap();
+            assert_close(s.point * radii, e.point);
+            assert!((s.u - e.u).abs() < EPSILON && (s.v - e.v).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn ellipsoid_normal_is_perpendicular() {
+        let ellipsoid = Ellipsoid::from([0., 0., 0.], [3., 1., 1.], DbgBlack {});
+        let range = 0.001..f64::INFINITY;
+        let delta = 1e-5;
+
+        let origin = Vec3::new(-10., 4., 5.);
+        let target = Vec3::new(1.5, 0.4, 0.3);
+        let hit = |target: Vec3| ellipsoid.hit(&Ray::new(origin, target - origin, 0.), &range);
+
+        let center = hit(target).unwrap();
+        // Nearby hits on the surface form (approximate) tangent vectors at the hit point
+        for offset in &[Vec3::UNIT_X, Vec3::UNIT_Y, Vec3::UNIT_Z] {
+            let nearby = hit(target + delta * *offset).unwrap();
+            let tangent = Vec3::normalized(nearby.point - center.point);
+            assert!(tangent.dot(center.normal).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn triangle_backface_culling() {
+        let triangle = || Triangle::from([0., 0., 0.], [1., 0., 0.], [0., 1., 0.], DbgBlack {});
+        let range = 0.001..f64::INFINITY;
+        let front = Ray::from([0.2, 0.2, 1.], [0., 0., -1.], 0.);
+        let back = Ray::from([0.2, 0.2, -1.], [0., 0., 1.], 0.);
+
+        let culled = triangle().cull_backfaces(true);
+        assert!(culled.hit(&front, &range).unwrap().front_face);
+        assert!(culled.hit(&back, &range).is_none());
+
+        let unculled = triangle();
+        let hit = unculled.hit(&back, &range).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, -Vec3::UNIT_Z);
+    }
+
+    #[test]
+    fn shapes_share_materials() {
+        let shared: SharedMaterial = Arc::new(DbgBlack {});
+        let mut list = HitList::new();
+        for x in 0..3 {
+            list.push(Sphere::from([x as f64, 0., 0.], 0.4, shared.clone()));
+        }
+        assert_eq!(Arc::strong_count(&shared), 4);
+        let ray = Ray::from([1., 0., 5.], [0., 0., -1.], 0.);
+        let hit = list.hit(&ray, &(0.001..f64::INFINITY)).unwrap();
+        assert!((hit.point - Vec3::new(1., 0., 0.4)).length() < 1e-12);
+    }
+
+    #[test]
+    fn triangle_interpolates_normals() {
+        let tilted = Vec3::normalized(Vec3::new(1., 0., 1.));
+        let triangle = Triangle::from([0., 0., 0.], [1., 0., 0.], [0., 1., 0.], DbgBlack {})
+            .with_normals([Vec3::UNIT_Z, tilted, Vec3::UNIT_Z]);
+        let range = 0.001..f64::INFINITY;
+
+        // At a vertex, the normal is the vertex's
+        let at_v1 = Ray::from([1., 0., 1.], [0., 0., -1.], 0.);
+        assert_close(triangle.hit(&at_v1, &range).unwrap().normal, tilted);
+        // and in between it's a blend
+        let front = Ray::from([0.5, 0., 1.], [0., 0., -1.], 0.);
+        let hit = triangle.hit(&front, &range).unwrap();
+        assert!(hit.front_face);
+        assert_close(hit.normal, Vec3::normalized(Vec3::UNIT_Z + tilted));
+        // Back face hits flip it to face the ray
+        let back = Ray::from([0.5, 0., -1.], [0., 0., 1.], 0.);
+        let hit = triangle.hit(&back, &range).unwrap();
+        assert!(!hit.front_face);
+        assert_close(hit.normal, -Vec3::normalized(Vec3::UNIT_Z + tilted));
+
+        // Normals that cancel out fall back to the flat normal
+        let opposed = Triangle::from([0., 0., 0.], [1., 0., 0.], [0., 1., 0.], DbgBlack {})
+            .with_normals([Vec3::UNIT_X, -Vec3::UNIT_X, Vec3::UNIT_X]);
+        let hit = opposed.hit(&front, &range).unwrap();
+        assert_close(hit.normal, Vec3::UNIT_Z);
+    }
+
+    /// Monte Carlo estimate of the integral of `pdf_value` over the sphere of directions, using
+    /// uniformly sampled directions.
+    fn integrate_pdf(hittable: &dyn Hittable, origin: Vec3) -> f64 {
+        const SAMPLES: usize = 200_000;
+        let mut rng = CrateRng::seed_from_u64(9);
+        let sum: f64 = (0..SAMPLES)
+            .map(|_| hittable.pdf_value(origin, Vec3::rand_unit_sphere(&mut rng)))
+            .sum();
+        4. * PI * sum / SAMPLES as f64
+    }
+
+    /// Every sampled direction should hit the `Hittable` and have a positive density.
+    fn assert_samples_hit(hittable: &dyn Hittable, origin: Vec3) {
+        let mut rng = CrateRng::seed_from_u64(10);
+        for _ in 0..1000 {
+            let dir = hittable.random_dir(origin, &mut rng);
+            let ray = Ray::new(origin, dir, 0.);
+            assert!(hittable.hit(&ray, &SAMPLE_HIT_TIME).is_some());
+            assert!(hittable.pdf_value(origin, dir) > 0.);
+        }
+    }
+
+    #[test]
+    fn pdf_value_integrates_to_one() {
+        let sphere = Sphere::from([0., 0., -2.], 1., DbgBlack {});
+        let rect = XZRect::new(-1.0..1., -1.0..1., 1., DbgBlack {});
+        let mut list = HitList::new();
+        list.push(Sphere::from([0., 0., -2.], 1., DbgBlack {}));
+        list.push(XZRect::new(-1.0..1., -1.0..1., 1., DbgBlack {}));
+
+        let cases: [(&dyn Hittable, Vec3); 4] = [
+            (&sphere, Vec3::ORIGIN),
+            (&sphere, Vec3::new(0., 0.2, -2.)),
+            (&rect, Vec3::ORIGIN),
+            (&list, Vec3::ORIGIN),
+        ];
+        for (hittable, origin) in cases.iter() {
+            let integral = integrate_pdf(*hittable, *origin);
+            assert!((integral - 1.).abs() < 0.05, "{:?}: {}", hittable, integral);
+            assert_samples_hit(*hittable, *origin);
+        }
+    }
+}
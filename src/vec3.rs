@@ -7,6 +7,11 @@ use rand_distr::{Distribution, Standard, Uniform};
 use crate::CrateRng;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "[f64; 3]", into = "[f64; 3]")
+)]
 pub struct Vec3 {
     pub x: f64,
     pub y: f64,
@@ -34,7 +39,7 @@ impl Vec3 {
     pub fn normalized(v: Vec3) -> Self {
         let norm = v.norm();
         // TODO: Measure perf impact of assert! vs debug_assert!
-        debug_assert!(norm != 0., ERR_NORMED_0);
+        debug_assert!(norm != 0., "{}", ERR_NORMED_0);
         v / norm
     }
 
@@ -61,6 +66,36 @@ impl Vec3 {
         Self::new(ret[0], ret[1], 0.)
     }
 
+    /// Samples a unit vector from the hemisphere around `+z`, with a probability density of
+    /// `cos(theta) / pi` where `theta` is the angle from `+z`.
+    pub fn rand_cosine_direction(rng: &mut CrateRng) -> Self {
+        // Project a point on the unit disc up onto the hemisphere (Malley's method).
+        let disk = Self::rand_unit_disk(rng);
+        let z = (1. - disk.norm_squared()).max(0.).sqrt();
+        Self::new(disk.x, disk.y, z)
+    }
+
+    /// Samples a unit vector uniformly from the cone around `+z` whose angle from `+z` has a
+    /// cosine of at least `cos_theta_max`.
+    pub fn rand_cone_direction(rng: &mut CrateRng, cos_theta_max: f64) -> Self {
+        let z = 1. + rng.gen::<f64>() * (cos_theta_max - 1.);
+        let phi = 2. * std::f64::consts::PI * rng.gen::<f64>();
+        let r = (1. - z * z).max(0.).sqrt();
+        Self::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Returns true if every field is close to 0.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// assert!(Vec3::new(1e-9, -1e-10, 0.).near_zero());
+    /// assert!(!Vec3::new(1e-9, 1e-3, 0.).near_zero());
+    /// ```
+    pub fn near_zero(&self) -> bool {
+        const EPSILON: f64 = 1e-8;
+        self.x.abs() < EPSILON && self.y.abs() < EPSILON && self.z.abs() < EPSILON
+    }
+
     /// Applies an operation `f` to all fields of a `Vec3` and returns the result
     /// # Example
     /// ```
@@ -84,6 +119,140 @@ impl Vec3 {
         self.x.powi(2) + self.y.powi(2) + self.z.powi(2)
     }
 
+    /// Alias for `norm`
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let v = Vec3::new(2., 3., 6.);
+    /// assert_eq!(v.length(), 7.);
+    /// assert_eq!(v.length(), v.norm());
+    /// ```
+    pub fn length(&self) -> f64 {
+        self.norm()
+    }
+
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(1., 2., 3.);
+    /// let b = Vec3::new(4., 6., 3.);
+    /// assert_eq!(a.distance(b), 5.);
+    /// ```
+    pub fn distance(&self, other: Vec3) -> f64 {
+        (*self - other).norm()
+    }
+
+    /// Linearly interpolates between `self` (at `t = 0`) and `other` (at `t = 1`).
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(0., 2., 4.);
+    /// let b = Vec3::new(4., 4., 0.);
+    /// assert_eq!(a.lerp(b, 0.25), Vec3::new(1., 2.5, 3.));
+    /// ```
+    pub fn lerp(&self, other: Vec3, t: f64) -> Self {
+        (1. - t) * *self + t * other
+    }
+
+    /// Clamps each field of `self` to the range `[min, max]`.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(-1., 0.5, 3.);
+    /// assert_eq!(a.clamp(0., 1.), Vec3::new(0., 0.5, 1.));
+    /// ```
+    pub fn clamp(&self, min: f64, max: f64) -> Self {
+        self.map(|f| f.clamp(min, max))
+    }
+
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(-1., 2., -3.);
+    /// assert_eq!(a.abs(), Vec3::new(1., 2., 3.));
+    /// ```
+    pub fn abs(&self) -> Self {
+        self.map(f64::abs)
+    }
+
+    /// Returns the smaller of each pair of corresponding fields.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(1., 5., 3.);
+    /// let b = Vec3::new(4., 2., 3.);
+    /// assert_eq!(a.min(b), Vec3::new(1., 2., 3.));
+    /// ```
+    pub fn min(&self, other: Vec3) -> Self {
+        Self::new(
+            self.x.min(other.x),
+            self.y.min(other.y),
+            self.z.min(other.z),
+        )
+    }
+
+    /// Returns the larger of each pair of corresponding fields.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(1., 5., 3.);
+    /// let b = Vec3::new(4., 2., 3.);
+    /// assert_eq!(a.max(b), Vec3::new(4., 5., 3.));
+    /// ```
+    pub fn max(&self, other: Vec3) -> Self {
+        Self::new(
+            self.x.max(other.x),
+            self.y.max(other.y),
+            self.z.max(other.z),
+        )
+    }
+
+    pub fn min_component(&self) -> f64 {
+        self.min_component_axis().0
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.max_component_axis().0
+    }
+
+    /// Returns the smallest field and its `Axis`. Ties go to the earliest axis.
+    /// # Example
+    /// ```
+    /// # use raytracing::{Axis, Vec3};
+    /// let (min, axis) = Vec3::new(3., -2., 1.).min_component_axis();
+    /// assert_eq!(min, -2.);
+    /// assert_eq!(axis, Axis::Y);
+    /// ```
+    pub fn min_component_axis(&self) -> (f64, Axis) {
+        let mut ret = (self.x, Axis::X);
+        if self.y < ret.0 {
+            ret = (self.y, Axis::Y);
+        }
+        if self.z < ret.0 {
+            ret = (self.z, Axis::Z);
+        }
+        ret
+    }
+
+    /// Returns the largest field and its `Axis`. Ties go to the earliest axis.
+    /// # Example
+    /// ```
+    /// # use raytracing::{Axis, Vec3};
+    /// let (max, axis) = Vec3::new(3., -2., 4.).max_component_axis();
+    /// assert_eq!(max, 4.);
+    /// assert_eq!(axis, Axis::Z);
+    /// ```
+    pub fn max_component_axis(&self) -> (f64, Axis) {
+        let mut ret = (self.x, Axis::X);
+        if self.y > ret.0 {
+            ret = (self.y, Axis::Y);
+        }
+        if self.z > ret.0 {
+            ret = (self.z, Axis::Z);
+        }
+        ret
+    }
+
     /// # Example
     /// ```
     /// # use raytracing::vec3::Vec3;
@@ -110,15 +279,64 @@ impl Vec3 {
         }
     }
 
+    /// Rotates `self` counterclockwise by `radians` around `axis` (looking down at `axis`) using
+    /// Rodrigues' rotation formula. `axis` doesn't need to be normalized.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// use std::f64::consts::FRAC_PI_2;
+    /// let a = Vec3::UNIT_X.rotate_around(Vec3::new(0., 0., 2.), FRAC_PI_2);
+    /// assert!((a - Vec3::UNIT_Y).near_zero());
+    /// ```
+    pub fn rotate_around(&self, axis: Vec3, radians: f64) -> Self {
+        let k = Vec3::normalized(axis);
+        let (sin, cos) = radians.sin_cos();
+        cos * *self + sin * k.cross(*self) + (1. - cos) * k.dot(*self) * k
+    }
+
+    /// Reflects `self` about `normal`. Neither needs to be normalized. The result is a unit
+    /// vector.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// let a = Vec3::new(2., -2., 0.).reflect(Vec3::new(0., 3., 0.));
+    /// assert!((a - Vec3::normalized(Vec3::new(1., 1., 0.))).near_zero());
+    /// ```
     pub fn reflect(&self, normal: Vec3) -> Self {
-        let unit_dir = Vec3::normalized(*self);
-        unit_dir - 2. * unit_dir.dot(normal) * normal
+        Vec3::normalized(*self).reflect_unit(Vec3::normalized(normal))
     }
 
+    /// Like `reflect`, but assumes that `self` and `normal` are already unit vectors.
+    pub fn reflect_unit(&self, normal: Vec3) -> Self {
+        *self - 2. * self.dot(normal) * normal
+    }
+
+    /// Refracts `self` through a surface with the given `normal`, where `eta_i_over_eta_t` is
+    /// the ratio of the refractive index on the side of `self` to the one on the other side.
+    /// Neither vector needs to be normalized. The result is a unit vector.
+    ///
+    /// `normal` must face against `self`, and the caller must check for total internal reflection.
+    /// # Example
+    /// ```
+    /// # use raytracing::vec3::Vec3;
+    /// // Light entering glass at 60 degrees from the normal follows Snell's law
+    /// let theta_i = 60_f64.to_radians();
+    /// let dir = Vec3::new(theta_i.sin(), -theta_i.cos(), 0.);
+    /// let refracted = (3. * dir).refract(Vec3::new(0., 2., 0.), 1. / 1.5);
+    /// let sin_t = refracted.x / refracted.norm();
+    /// assert!((sin_t - theta_i.sin() / 1.5).abs() < 1e-12);
+    /// assert!((refracted.norm() - 1.).abs() < 1e-12);
+    /// ```
     pub fn refract(&self, normal: Vec3, eta_i_over_eta_t: f64) -> Self {
-        let cos_theta = (-*self).dot(normal);
+        Vec3::normalized(*self).refract_unit(Vec3::normalized(normal), eta_i_over_eta_t)
+    }
+
+    /// Like `refract`, but assumes that `self` and `normal` are already unit vectors.
+    pub fn refract_unit(&self, normal: Vec3, eta_i_over_eta_t: f64) -> Self {
+        let cos_theta = (-*self).dot(normal).min(1.);
         let refract_parallel = eta_i_over_eta_t * (*self + cos_theta * normal);
-        let refract_perp = -normal * (1. - refract_parallel.norm_squared()).sqrt();
+        // Rounding can push this just below 0 at grazing angles
+        let refract_perp = -normal * (1. - refract_parallel.norm_squared()).max(0.).sqrt();
         refract_parallel + refract_perp
     }
 
@@ -133,6 +351,19 @@ impl From<[f64; 3]> for Vec3 {
     }
 }
 
+/// Sets every field to the same value
+impl From<f64> for Vec3 {
+    fn from(f: f64) -> Self {
+        Self::new(f, f, f)
+    }
+}
+
+impl From<Vec3> for [f64; 3] {
+    fn from(v: Vec3) -> Self {
+        [v.x, v.y, v.z]
+    }
+}
+
 impl ops::Index<Axis> for Vec3 {
     type Output = f64;
 
@@ -216,6 +447,13 @@ impl ops::Mul for Vec3 {
         }
     }
 }
+impl ops::MulAssign for Vec3 {
+    fn mul_assign(&mut self, rhs: Self) {
+        self.x *= rhs.x;
+        self.y *= rhs.y;
+        self.z *= rhs.z;
+    }
+}
 
 impl ops::Mul<f64> for Vec3 {
     type Output = Self;
@@ -243,6 +481,26 @@ impl ops::MulAssign<f64> for Vec3 {
     }
 }
 
+/// Divide the corresponding fields
+impl ops::Div for Vec3 {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x / rhs.x,
+            y: self.y / rhs.y,
+            z: self.z / rhs.z,
+        }
+    }
+}
+impl ops::DivAssign for Vec3 {
+    fn div_assign(&mut self, rhs: Self) {
+        self.x /= rhs.x;
+        self.y /= rhs.y;
+        self.z /= rhs.z;
+    }
+}
+
 impl ops::Div<f64> for Vec3 {
     type Output = Self;
 
@@ -262,12 +520,56 @@ impl ops::DivAssign<f64> for Vec3 {
     }
 }
 
-#[derive(Copy, Clone)]
+/// An orthonormal basis.
+#[derive(Copy, Clone, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+impl Onb {
+    /// Builds a basis whose `w` axis points along `dir`.
+    pub fn from_w(dir: Vec3) -> Self {
+        let w = Vec3::normalized(dir);
+        // Any vector that isn't parallel to `w` works for the cross product.
+        let other = if w.x.abs() > 0.9 {
+            Vec3::UNIT_Y
+        } else {
+            Vec3::UNIT_X
+        };
+        let v = Vec3::normalized(w.cross(other));
+        let u = w.cross(v);
+        Self { u, v, w }
+    }
+
+    /// Converts `a` from coordinates in this basis to world coordinates.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Axis {
     X,
     Y,
     Z,
 }
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    /// Iterates over `X`, `Y`, and `Z` in order.
+    /// # Example
+    /// ```
+    /// # use raytracing::{Axis, Vec3};
+    /// let v = Vec3::new(1., 2., 3.);
+    /// let sum: f64 = Axis::all().map(|axis| v[axis]).sum();
+    /// assert_eq!(sum, 6.);
+    /// ```
+    pub fn all() -> impl Iterator<Item = Axis> {
+        Self::ALL.iter().copied()
+    }
+}
 impl Distribution<Axis> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Axis {
         let distr = Uniform::new(0u8, 3);